@@ -1,34 +1,37 @@
 use fefix::prelude::*;
-use fefix::session::FixConnectionBuilder;
-use fefix::tagvalue::Decoder;
-use slog::{debug, info, o, Logger};
+use fefix::session::{Endpoint, FixConnectionBuilder, FixInitiator};
+use slog::{debug, info, o, warn, Logger};
 use std::io;
-use std::net::{Ipv4Addr, SocketAddrV4};
 use std::ops::Range;
-use tokio::net::TcpSocket;
+use tokio::net::TcpStream;
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
 const PORT: u16 = 0xF13;
+const BACKUP_PORT: u16 = 0xF14;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let tcp_socket = TcpSocket::new_v4()?;
-    let socket_address = SocketAddrV4::new(Ipv4Addr::LOCALHOST, PORT);
-    let tcp_stream = tcp_socket.connect(socket_address.into()).await?;
-    tcp_stream.set_nodelay(true)?;
     let app = Application::new(logger());
     let mut builder = FixConnectionBuilder::default();
     builder.set_begin_string("FIX.4.2");
     builder.set_target_comp_id("TW");
     builder.set_sender_comp_id("INCA");
     let fix_dictionary = Dictionary::fix42();
-    let fix_decoder = Decoder::new(fix_dictionary);
-    let (reader, writer) = tokio::io::split(tcp_stream);
-    builder
-        .build()
-        .start(app, reader.compat(), writer.compat_write(), fix_decoder)
+    let mut initiator = FixInitiator::new(builder.build(), app, fix_dictionary);
+    initiator.add_endpoint(Endpoint::new("127.0.0.1", PORT));
+    initiator.add_endpoint(Endpoint::new("127.0.0.1", BACKUP_PORT));
+    let err = initiator
+        .run(|endpoint| {
+            let address = endpoint.to_string();
+            async move {
+                let tcp_stream = TcpStream::connect(address).await?;
+                tcp_stream.set_nodelay(true)?;
+                let (reader, writer) = tokio::io::split(tcp_stream);
+                Ok((reader.compat(), writer.compat_write()))
+            }
+        })
         .await;
-    Ok(())
+    Err(err)
 }
 
 #[derive(Clone)]
@@ -97,12 +100,21 @@ impl fefix::session::Backend for Application {
         Ok(())
     }
 
-    fn fetch_messages(&mut self) -> Result<&[&[u8]], Self::Error> {
-        Ok(&[b""])
-    }
-
-    fn pending_message(&mut self) -> Option<&[u8]> {
-        None
+    fn on_connection_attempt(
+        &mut self,
+        endpoint: &Endpoint,
+        result: Result<(), &io::Error>,
+    ) -> Result<(), Self::Error> {
+        match result {
+            Ok(()) => info!(self.logger, "Connected."; "endpoint" => endpoint.to_string()),
+            Err(err) => warn!(
+                self.logger,
+                "Connection failed.";
+                "endpoint" => endpoint.to_string(),
+                "error" => err.to_string(),
+            ),
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,98 @@
+use fefix::prelude::*;
+use fefix::session::{FixConnectionBuilder, FixServer, HeartbeatRule};
+use slog::{debug, info, o, Logger};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+const PORT: u16 = 0xF13;
+const CLIENTS: &[&str] = &["INCA", "INCB", "INCC"];
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let logger = logger();
+    let mut server = FixServer::new(Dictionary::fix42());
+    for client in CLIENTS {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_begin_string("FIX.4.2");
+        builder.set_sender_comp_id("TW");
+        builder.set_target_comp_id(*client);
+        builder.set_heartbeat_rule(HeartbeatRule::Range(
+            Duration::from_secs(5)..=Duration::from_secs(60),
+        ));
+        let app = Application::new(logger.new(o!("client" => *client)));
+        server.add_session(builder, app);
+    }
+    let server = Arc::new(server);
+    let socket_address = SocketAddrV4::new(Ipv4Addr::LOCALHOST, PORT);
+    let listener = TcpListener::bind(socket_address).await?;
+    loop {
+        let (tcp_stream, peer_address) = listener.accept().await?;
+        tcp_stream.set_nodelay(true)?;
+        info!(logger, "New connection."; "peer" => peer_address.to_string());
+        let server = server.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = tokio::io::split(tcp_stream);
+            server.serve(reader.compat(), writer.compat_write()).await;
+        });
+    }
+}
+
+#[derive(Clone)]
+struct Application {
+    logger: Logger,
+}
+
+impl Application {
+    fn new(logger: Logger) -> Self {
+        Self { logger }
+    }
+}
+
+impl fefix::session::Backend for Application {
+    type Error = ();
+
+    fn on_inbound_app_message(
+        &mut self,
+        message: fefix::tagvalue::Message<&[u8]>,
+    ) -> Result<(), Self::Error> {
+        self.on_inbound_message(message, true)
+    }
+
+    fn on_inbound_message(
+        &mut self,
+        message: fefix::tagvalue::Message<&[u8]>,
+        _is_app: bool,
+    ) -> Result<(), Self::Error> {
+        let message = std::str::from_utf8(message.as_bytes()).unwrap_or("(invalid UTF-8)");
+        debug!(self.logger, "Inbound FIX message."; "message" => message);
+        Ok(())
+    }
+
+    fn on_outbound_message(&mut self, message: &[u8]) -> Result<(), Self::Error> {
+        let message = std::str::from_utf8(message).unwrap_or("(invalid UTF-8)");
+        debug!(self.logger, "Outbound FIX message."; "message" => message);
+        Ok(())
+    }
+
+    fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+        info!(self.logger, "Logon successful.");
+        Ok(())
+    }
+
+    fn on_resend_request(&mut self, _range: Range<u64>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn logger() -> Logger {
+    use slog::Drain;
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    Logger::root(drain, o!())
+}
@@ -1511,7 +1511,15 @@ mod quickfix {
                         .attribute("name")
                         .ok_or(ParseDictionaryError::InvalidFormat)?
                         .to_string();
-                    import_component(&mut reader.builder, child, name)?;
+                    // Skip components which were imported already, because
+                    // other components referenced them.
+                    if reader
+                        .builder
+                        .symbol(KeyRef::ComponentByName(name.as_str()))
+                        .is_none()
+                    {
+                        import_component(&mut reader.builder, child, name)?;
+                    }
                 }
             }
             for child in reader.node_with_messages.children() {
@@ -1755,8 +1763,16 @@ mod quickfix {
                 LayoutItemKindData::Field { iid: *field_iid }
             }
             "component" => {
-                // Components may *not* be already present.
-                let component_iid = import_component(builder, node, name)?;
+                // Components may *not* be already present, in which case we
+                // must import their definition rather than the reference.
+                let component_iid = match builder.symbol(KeyRef::ComponentByName(name)) {
+                    Some(iid) => *iid,
+                    None => {
+                        let definition = component_definition(node, name)
+                            .ok_or(ParseDictionaryError::InvalidFormat)?;
+                        import_component(builder, definition, name)?
+                    }
+                };
                 LayoutItemKindData::Component { iid: component_iid }
             }
             "group" => {
@@ -1778,6 +1794,20 @@ mod quickfix {
         Ok(item)
     }
 
+    /// Finds the definition of the component named `name` within the
+    /// `<components>` section of the document of `node`.
+    fn component_definition<'a>(
+        node: roxmltree::Node<'a, 'a>,
+        name: &str,
+    ) -> Option<roxmltree::Node<'a, 'a>> {
+        node.document()
+            .root_element()
+            .children()
+            .find(|n| n.has_tag_name("components"))?
+            .children()
+            .find(|n| n.is_element() && n.attribute("name") == Some(name))
+    }
+
     fn import_category(
         builder: &mut DictionaryBuilder,
         node: roxmltree::Node,
@@ -1850,6 +1880,19 @@ mod test {
         }
     }
 
+    #[test]
+    fn components_referenced_by_messages_are_not_empty() {
+        let dict = Dictionary::fix44();
+        let order_qty_data = dict.component_by_name("OrderQtyData").unwrap();
+        assert!(order_qty_data
+            .items()
+            .any(|item| item.tag_text() == "OrderQty"));
+        let instrument = dict.component_by_name("Instrument").unwrap();
+        assert!(instrument
+            .items()
+            .any(|item| item.tag_text() == "SecAltIDGrp"));
+    }
+
     #[test]
     fn std_header_and_trailer_always_present() {
         for dict in Dictionary::all().iter() {
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use futures::channel::{mpsc, oneshot};
+use std::fmt;
+
+/// An administrative command for a running
+/// [`FixConnection`](super::FixConnection), usually issued via a
+/// [`SessionAdmin`]. See [`Input::Admin`](super::Input::Admin).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// Sets the expected `MsgSeqNum <34>` of the next inbound message.
+    SetNextInbound(u64),
+    /// Sets the `MsgSeqNum <34>` of the next outbound message.
+    SetNextOutbound(u64),
+    /// Sends a `ResendRequest <2>` for the messages from `begin_seq_no` to
+    /// `end_seq_no`, both included. An `end_seq_no` of 0 requests all
+    /// messages from `begin_seq_no` onwards.
+    ResendRequest {
+        /// `BeginSeqNo <7>`.
+        begin_seq_no: u64,
+        /// `EndSeqNo <16>`.
+        end_seq_no: u64,
+    },
+    /// Sends a `TestRequest <1>`, which the counterparty must answer with a
+    /// `Heartbeat <0>`.
+    TestRequest,
+    /// Sends a `Logout <5>` with the given `Text <58>`, then waits for the
+    /// counterparty's `Logout <5>` before disconnecting.
+    Logout(String),
+    /// Closes the transport right away, without any `Logout <5>`.
+    Disconnect,
+    /// Resets the message store, so that both seq. numbers start again from 1.
+    /// A logged-on session is logged out and disconnected first, and the
+    /// next `Logon <A>` of initiators carries `ResetSeqNumFlag <141>`.
+    Reset,
+}
+
+/// A snapshot of the state of a [`FixConnection`](super::FixConnection), as
+/// returned by [`SessionAdmin::state`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SessionState {
+    /// Whether the Logon handshake is complete and the transport is open.
+    pub is_logged_on: bool,
+    /// The expected `MsgSeqNum <34>` of the next inbound message.
+    pub next_inbound: u64,
+    /// The `MsgSeqNum <34>` of the next outbound message.
+    pub next_outbound: u64,
+    /// The time of the last outbound message. The next `Heartbeat <0>` is
+    /// due `HeartBtInt <108>` after it.
+    pub last_sent: DateTime<Utc>,
+    /// The time of the last inbound message, from which the counterparty's
+    /// heartbeat timeout is measured.
+    pub last_received: DateTime<Utc>,
+}
+
+/// A request to a running [`FixConnection`](super::FixConnection), together
+/// with the channel over which the outcome is sent back.
+#[derive(Debug)]
+pub(crate) enum AdminRequest {
+    State(oneshot::Sender<SessionState>),
+    Command(AdminCommand, AdminReply),
+}
+
+/// The channel over which the outcome of an [`AdminCommand`] is sent back.
+pub(crate) type AdminReply = oneshot::Sender<Result<(), AdminError>>;
+
+/// A cloneable handle to a [`FixConnection`](super::FixConnection) which
+/// allows operators to inspect and intervene on the session while it's
+/// running.
+///
+/// Requests are served by
+/// [`FixConnection::start`](super::FixConnection::start). Those issued while
+/// the connection isn't running wait for the next start.
+#[derive(Debug, Clone)]
+pub struct SessionAdmin {
+    sender: mpsc::UnboundedSender<AdminRequest>,
+}
+
+impl SessionAdmin {
+    pub(crate) fn new(sender: mpsc::UnboundedSender<AdminRequest>) -> Self {
+        Self { sender }
+    }
+
+    /// Returns the current [`SessionState`].
+    pub async fn state(&self) -> Result<SessionState, AdminError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.sender
+            .unbounded_send(AdminRequest::State(reply_sender))
+            .map_err(|_| AdminError::Closed)?;
+        reply_receiver.await.map_err(|_| AdminError::Closed)
+    }
+
+    /// Carries out `command`. Returns once it has been processed by the
+    /// session.
+    pub async fn execute(&self, command: AdminCommand) -> Result<(), AdminError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.sender
+            .unbounded_send(AdminRequest::Command(command, reply_sender))
+            .map_err(|_| AdminError::Closed)?;
+        reply_receiver.await.map_err(|_| AdminError::Closed)?
+    }
+
+    /// Sets the expected `MsgSeqNum <34>` of the next inbound message.
+    pub async fn set_next_inbound(&self, msg_seq_num: u64) -> Result<(), AdminError> {
+        self.execute(AdminCommand::SetNextInbound(msg_seq_num))
+            .await
+    }
+
+    /// Sets the `MsgSeqNum <34>` of the next outbound message.
+    pub async fn set_next_outbound(&self, msg_seq_num: u64) -> Result<(), AdminError> {
+        self.execute(AdminCommand::SetNextOutbound(msg_seq_num))
+            .await
+    }
+
+    /// Sends a `ResendRequest <2>`. See [`AdminCommand::ResendRequest`].
+    pub async fn resend_request(
+        &self,
+        begin_seq_no: u64,
+        end_seq_no: u64,
+    ) -> Result<(), AdminError> {
+        self.execute(AdminCommand::ResendRequest {
+            begin_seq_no,
+            end_seq_no,
+        })
+        .await
+    }
+
+    /// Sends a `TestRequest <1>`.
+    pub async fn test_request(&self) -> Result<(), AdminError> {
+        self.execute(AdminCommand::TestRequest).await
+    }
+
+    /// Sends a `Logout <5>` with the given `Text <58>`.
+    pub async fn logout<S>(&self, text: S) -> Result<(), AdminError>
+    where
+        S: Into<String>,
+    {
+        self.execute(AdminCommand::Logout(text.into())).await
+    }
+
+    /// Closes the transport right away.
+    pub async fn disconnect(&self) -> Result<(), AdminError> {
+        self.execute(AdminCommand::Disconnect).await
+    }
+
+    /// Resets the session. See [`AdminCommand::Reset`].
+    pub async fn reset(&self) -> Result<(), AdminError> {
+        self.execute(AdminCommand::Reset).await
+    }
+}
+
+/// The error returned by [`SessionAdmin`] requests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdminError {
+    /// The session is gone.
+    Closed,
+    /// The command requires the session to be logged on.
+    NotLoggedOn,
+    /// Seq. numbers must be strictly positive, and ranges can't be empty.
+    InvalidSeqNum,
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "The FIX session is closed"),
+            Self::NotLoggedOn => write!(f, "The FIX session is not logged on"),
+            Self::InvalidSeqNum => write!(f, "Invalid seq. number"),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_fail_once_session_is_dropped() {
+        let (sender, receiver) = mpsc::unbounded();
+        let admin = SessionAdmin::new(sender);
+        drop(receiver);
+        let result = futures::executor::block_on(admin.test_request());
+        assert_eq!(result, Err(AdminError::Closed));
+        let result = futures::executor::block_on(admin.state());
+        assert_eq!(result, Err(AdminError::Closed));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::definitions::{fix44, HardCodedFixFieldDefinition};
+use crate::dict::{FieldLocation, FixDatatype};
+use crate::tagvalue::{FieldAccess, Message};
+use std::net::SocketAddr;
+
+// FIX 5.0 SP1 field, which isn't part of `fix44`.
+
+pub(crate) const SESSION_STATUS: &HardCodedFixFieldDefinition = &HardCodedFixFieldDefinition {
+    name: "SessionStatus",
+    tag: 1409,
+    is_group_leader: false,
+    data_type: FixDatatype::Int,
+    location: FieldLocation::Body,
+};
+
+/// The values of `SessionStatus <1409>`, by which acceptors report the
+/// outcome of authentication in their `Logon <A>` or `Logout <5>`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SessionStatus {
+    /// The session is active.
+    SessionActive,
+    /// The password was changed as requested via `NewPassword <925>`.
+    SessionPasswordChanged,
+    /// The password is about to expire.
+    SessionPasswordDueToExpire,
+    /// The `NewPassword <925>` doesn't comply with the password policy.
+    NewSessionPasswordDoesNotComplyWithPolicy,
+    /// The session has been logged out.
+    SessionLogoutComplete,
+    /// Invalid `Username <553>` or `Password <554>`.
+    InvalidUsernameOrPassword,
+    /// The account is locked.
+    AccountLocked,
+    /// Logons aren't allowed at this time.
+    LogonsAreNotAllowedAtThisTime,
+    /// The password has expired.
+    PasswordExpired,
+}
+
+impl SessionStatus {
+    /// Returns the value of `SessionStatus <1409>` for `self`.
+    pub fn value(&self) -> u32 {
+        match self {
+            Self::SessionActive => 0,
+            Self::SessionPasswordChanged => 1,
+            Self::SessionPasswordDueToExpire => 2,
+            Self::NewSessionPasswordDoesNotComplyWithPolicy => 3,
+            Self::SessionLogoutComplete => 4,
+            Self::InvalidUsernameOrPassword => 5,
+            Self::AccountLocked => 6,
+            Self::LogonsAreNotAllowedAtThisTime => 7,
+            Self::PasswordExpired => 8,
+        }
+    }
+}
+
+/// The counterparty's `Logon <A>`, as submitted to
+/// [`Backend::authenticate`](super::Backend::authenticate).
+#[derive(Debug, Copy, Clone)]
+pub struct LogonRequest<'a> {
+    message: Message<'a, &'a [u8]>,
+    peer_addr: Option<SocketAddr>,
+}
+
+impl<'a> LogonRequest<'a> {
+    pub(crate) fn new(message: Message<'a, &'a [u8]>, peer_addr: Option<SocketAddr>) -> Self {
+        Self { message, peer_addr }
+    }
+
+    /// Returns the whole `Logon <A>` message.
+    pub fn message(&self) -> Message<'a, &'a [u8]> {
+        self.message
+    }
+
+    /// Returns `Username <553>`, if any.
+    pub fn username(&self) -> Option<&str> {
+        self.message.fv(fix44::USERNAME).ok()
+    }
+
+    /// Returns `Password <554>`, if any.
+    pub fn password(&self) -> Option<&str> {
+        self.message.fv(fix44::PASSWORD).ok()
+    }
+
+    /// Returns `NewPassword <925>`, if any, i.e. the password that the
+    /// counterparty wants to use from now on.
+    pub fn new_password(&self) -> Option<&str> {
+        self.message.fv(fix44::NEW_PASSWORD).ok()
+    }
+
+    /// Returns `RawData <96>`, if any.
+    pub fn raw_data(&self) -> Option<&[u8]> {
+        self.message.fv_raw(fix44::RAW_DATA)
+    }
+
+    /// Returns the network address of the counterparty, if known. See
+    /// [`FixConnection::set_peer_addr`](super::FixConnection::set_peer_addr).
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+}
+
+/// The outcome of [`Backend::authenticate`](super::Backend::authenticate).
+///
+/// Accepted Logons are answered with a `Logon <A>`, refused ones with a
+/// `Logout <5>` carrying the given `Text <58>`, after which the transport is
+/// closed. Both can carry a [`SessionStatus`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authentication {
+    accepted: bool,
+    text: Option<String>,
+    session_status: Option<SessionStatus>,
+}
+
+impl Authentication {
+    /// Accepts the `Logon <A>`.
+    pub fn accept() -> Self {
+        Self {
+            accepted: true,
+            text: None,
+            session_status: None,
+        }
+    }
+
+    /// Refuses the `Logon <A>` with a `Logout <5>` carrying `text`.
+    pub fn reject<S>(text: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            accepted: false,
+            text: Some(text.into()),
+            session_status: None,
+        }
+    }
+
+    /// Sets the `SessionStatus <1409>` of the response, e.g.
+    /// [`SessionStatus::SessionPasswordChanged`] once a `NewPassword <925>`
+    /// is accepted.
+    pub fn set_session_status(&mut self, session_status: SessionStatus) {
+        self.session_status = Some(session_status);
+    }
+
+    /// Returns `true` if the `Logon <A>` is accepted.
+    pub fn is_accepted(&self) -> bool {
+        self.accepted
+    }
+
+    /// Returns the `Text <58>` of the `Logout <5>`, if any.
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    /// Returns the `SessionStatus <1409>` of the response, if any.
+    pub fn session_status(&self) -> Option<SessionStatus> {
+        self.session_status
+    }
+}
@@ -1 +1,31 @@
+//! Ready-made [`Backend`] implementations.
 
+use super::Backend;
+use crate::tagvalue::Message;
+use std::convert::Infallible;
+use std::ops::Range;
+
+/// A [`Backend`] that accepts every message and ignores all session events.
+/// It's useful for tests and tools that only care about the session layer.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Noop;
+
+impl Backend for Noop {
+    type Error = Infallible;
+
+    fn on_inbound_app_message(&mut self, _message: Message<&[u8]>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_outbound_message(&mut self, _message: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_resend_request(&mut self, _range: Range<u64>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use crate::definitions::fix44::BusinessRejectReason;
+
+/// The contents of a `BusinessMessageReject <j>`, by which the application
+/// refuses an inbound message that it can't process.
+///
+/// See [`Backend::business_reject`](super::Backend::business_reject).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusinessReject {
+    reason: BusinessRejectReason,
+    ref_id: Option<String>,
+    text: Option<String>,
+}
+
+impl BusinessReject {
+    /// Creates a new [`BusinessReject`] with the given `BusinessRejectReason
+    /// <380>`.
+    pub fn new(reason: BusinessRejectReason) -> Self {
+        Self {
+            reason,
+            ref_id: None,
+            text: None,
+        }
+    }
+
+    /// Sets `BusinessRejectRefID <379>`, i.e. the business-level ID of the
+    /// rejected message (e.g. its `ClOrdID <11>`).
+    pub fn set_ref_id<S>(&mut self, ref_id: S)
+    where
+        S: Into<String>,
+    {
+        self.ref_id = Some(ref_id.into());
+    }
+
+    /// Sets `Text <58>`.
+    pub fn set_text<S>(&mut self, text: S)
+    where
+        S: Into<String>,
+    {
+        self.text = Some(text.into());
+    }
+
+    /// Returns the `BusinessRejectReason <380>` of `self`.
+    pub fn reason(&self) -> BusinessRejectReason {
+        self.reason
+    }
+
+    /// Returns the `BusinessRejectRefID <379>` of `self`, if any.
+    pub fn ref_id(&self) -> Option<&str> {
+        self.ref_id.as_deref()
+    }
+
+    /// Returns the `Text <58>` of `self`, if any.
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+}
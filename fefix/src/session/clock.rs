@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures_timer::Delay;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// A source of the current UTC time.
+///
+/// FIX connections never read the system time directly, so that
+/// time-sensitive behavior (e.g. `SendingTime <52>` validation and heartbeat
+/// timers) can be tested deterministically.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current UTC time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns a [`Future`] which completes once [`Clock::now`] reaches
+    /// `deadline`. The default implementation simply sleeps for the
+    /// remaining time.
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        let duration = (deadline - self.now()).to_std().unwrap_or_default();
+        Box::pin(Delay::new(duration))
+    }
+}
+
+/// A [`Clock`] which simply reads the system time.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+impl<C> Clock for Arc<C>
+where
+    C: Clock + ?Sized,
+{
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        (**self).sleep_until(deadline)
+    }
+}
+
+/// A [`Clock`] which only moves forward when told to, for tests and
+/// simulations.
+///
+/// All clones share the same time, so one clone can be handed over to a
+/// [`FixConnectionBuilder`](super::FixConnectionBuilder) while another one
+/// advances the time. Sleeping futures complete as soon as the time reaches
+/// their deadline.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+#[derive(Debug)]
+struct MockClockState {
+    now: DateTime<Utc>,
+    sleepers: Vec<Waker>,
+}
+
+impl MockClock {
+    /// Creates a new [`MockClock`] which starts at `now`.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockClockState {
+                now,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the time forward by `duration`.
+    pub fn advance(&self, duration: chrono::Duration) {
+        let now = self.now() + duration;
+        self.set(now);
+    }
+
+    /// Sets the current time to `now`.
+    pub fn set(&self, now: DateTime<Utc>) {
+        let sleepers = {
+            let mut state = self.state.lock().unwrap();
+            state.now = now;
+            std::mem::take(&mut state.sleepers)
+        };
+        // Sleepers check their own deadline once woken up.
+        for sleeper in sleepers {
+            sleeper.wake();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+        Box::pin(MockSleep {
+            state: self.state.clone(),
+            deadline,
+        })
+    }
+}
+
+struct MockSleep {
+    state: Arc<Mutex<MockClockState>>,
+    deadline: DateTime<Utc>,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= self.deadline {
+            Poll::Ready(())
+        } else {
+            state.sleepers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn mock_clock_is_shared_by_clones() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let clone = clock.clone();
+        clock.advance(chrono::Duration::seconds(30));
+        assert_eq!(clone.now(), start + chrono::Duration::seconds(30));
+    }
+
+    #[test]
+    fn mock_sleep_completes_once_deadline_is_reached() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let mut sleep = clock.sleep_until(start + chrono::Duration::seconds(10));
+        assert_eq!((&mut sleep).now_or_never(), None);
+        clock.advance(chrono::Duration::seconds(9));
+        assert_eq!((&mut sleep).now_or_never(), None);
+        clock.advance(chrono::Duration::seconds(1));
+        assert_eq!(sleep.now_or_never(), Some(()));
+    }
+}
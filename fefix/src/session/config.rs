@@ -17,6 +17,25 @@ pub trait Configure: Clone + Default {
     fn max_allowed_latency(&self) -> Duration {
         Duration::from_secs(3)
     }
+
+    /// Asks initiators to reset seq. numbers on connect, by sending
+    /// `ResetSeqNumFlag <141>` in their `Logon <A>`. `false` by default.
+    fn reset_seq_num_on_logon(&self) -> bool {
+        false
+    }
+
+    /// Asks the FIX connector to send `NextExpectedMsgSeqNum <789>` in its
+    /// `Logon <A>` messages. `false` by default.
+    fn enable_next_expected_msg_seq_num(&self) -> bool {
+        false
+    }
+
+    /// Asks the FIX connector to validate inbound messages against the
+    /// dictionary of the decoder, and to refuse invalid ones with a
+    /// `Reject <3>`. `false` by default.
+    fn validate_messages(&self) -> bool {
+        false
+    }
 }
 
 /// The canonical implementor of [`Configure`]. Every setting can be changed.
@@ -24,6 +43,9 @@ pub trait Configure: Clone + Default {
 pub struct Config {
     verify_test_indicator: bool,
     max_allowed_latency: Duration,
+    reset_seq_num_on_logon: bool,
+    enable_next_expected_msg_seq_num: bool,
+    validate_messages: bool,
 }
 
 impl Config {
@@ -31,18 +53,80 @@ impl Config {
     pub fn set_verify_test_indicator(&mut self, verify: bool) {
         self.verify_test_indicator = verify;
     }
+
+    /// Changes the value of [`Configure::max_allowed_latency`].
+    pub fn set_max_allowed_latency(&mut self, max_allowed_latency: Duration) {
+        self.max_allowed_latency = max_allowed_latency;
+    }
+
+    /// Changes the value of [`Configure::reset_seq_num_on_logon`].
+    pub fn set_reset_seq_num_on_logon(&mut self, reset: bool) {
+        self.reset_seq_num_on_logon = reset;
+    }
+
+    /// Changes the value of [`Configure::enable_next_expected_msg_seq_num`].
+    pub fn set_enable_next_expected_msg_seq_num(&mut self, enable: bool) {
+        self.enable_next_expected_msg_seq_num = enable;
+    }
+
+    /// Changes the value of [`Configure::validate_messages`].
+    pub fn set_validate_messages(&mut self, validate: bool) {
+        self.validate_messages = validate;
+    }
 }
 
-impl Configure for Config {}
+impl Configure for Config {
+    fn verify_test_indicator(&self) -> bool {
+        self.verify_test_indicator
+    }
+
+    fn max_allowed_latency(&self) -> Duration {
+        self.max_allowed_latency
+    }
+
+    fn reset_seq_num_on_logon(&self) -> bool {
+        self.reset_seq_num_on_logon
+    }
+
+    fn enable_next_expected_msg_seq_num(&self) -> bool {
+        self.enable_next_expected_msg_seq_num
+    }
+
+    fn validate_messages(&self) -> bool {
+        self.validate_messages
+    }
+}
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             verify_test_indicator: true,
             max_allowed_latency: Duration::from_secs(3),
+            reset_seq_num_on_logon: false,
+            enable_next_expected_msg_seq_num: false,
+            validate_messages: false,
         }
     }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_settings_are_applied() {
+        let mut config = Config::default();
+        assert!(config.verify_test_indicator());
+        assert_eq!(config.max_allowed_latency(), Duration::from_secs(3));
+        config.set_verify_test_indicator(false);
+        config.set_max_allowed_latency(Duration::from_millis(500));
+        config.set_reset_seq_num_on_logon(true);
+        config.set_enable_next_expected_msg_seq_num(true);
+        config.set_validate_messages(true);
+        assert!(!config.verify_test_indicator());
+        assert_eq!(config.max_allowed_latency(), Duration::from_millis(500));
+        assert!(config.reset_seq_num_on_logon());
+        assert!(config.enable_next_expected_msg_seq_num());
+        assert!(config.validate_messages());
+    }
+}
@@ -1,15 +1,32 @@
-use super::{errs, Backend, LlEvent, LlEventLoop};
+use super::admin::{AdminReply, AdminRequest};
+use super::auth::SESSION_STATUS;
+use super::fixt::{ApplVersions, DEFAULT_APPL_VER_ID};
+use super::log::{inbound_event, outbound_event};
+use super::sender::{OutboundRequest, Reply};
+use super::validator::{Validator, Violation};
+use super::{
+    errs, AdminCommand, AdminError, ApplVerId, Authentication, Backend, BusinessReject, Clock,
+    Config, Configure, Direction, HeartbeatRule, LogonRequest, MemoryStore, MessageStore,
+    MetricsRegistry, Mirror, OutboundMessage, Schedule, SendError, SessionAdmin, SessionId,
+    SessionLog, SessionMetrics, SessionSender, SessionState, SessionStatus, SystemClock, Throttle,
+};
 use crate::definitions::fix44;
 use crate::dict::IsFieldDefinition;
+use crate::fix_values::Timestamp;
 use crate::session::{Environment, SeqNumbers};
 use crate::tagvalue::FieldAccess;
 use crate::tagvalue::Message;
 use crate::tagvalue::{Decoder, DecoderBuffered, Encoder, EncoderHandle};
-use crate::Buffer;
-use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::{Buffer, FixValue};
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::future::{self, Fuse};
+use futures::{select, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt};
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::marker::Unpin;
-use std::pin::Pin;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 use uuid::Uuid;
 
@@ -39,6 +56,17 @@ impl Iterator for MsgSeqNumCounter {
     }
 }
 
+/// The part played by a [`FixConnection`] during the Logon handshake.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Sends the first `Logon <A>` message and waits for the counterparty's
+    /// reply.
+    Initiator,
+    /// Waits for the counterparty's `Logon <A>` message, validates it and
+    /// replies with either a `Logon <A>` or a `Logout <5>`.
+    Acceptor,
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(test, derive(enum_as_inner::EnumAsInner))]
 pub enum Response<'a> {
@@ -50,14 +78,87 @@ pub enum Response<'a> {
     Inbound(Message<'a, &'a [u8]>),
     Outbound(Message<'a, &'a [u8]>),
     OutboundBytes(&'a [u8]),
+    /// Several outbound messages, to be sent in order.
+    OutboundMessages(Vec<Vec<u8>>),
+    /// Outbound messages replayed in response to a `ResendRequest <2>`.
     Resend {
-        range: (),
+        messages: Vec<Vec<u8>>,
     },
     /// The FIX session processor should log each encountered garbled message to
     /// assist in problem detection and diagnosis.
     LogGarbled,
 }
 
+/// An input to the sans-I/O core of a [`FixConnection`]. See
+/// [`FixConnection::handle`].
+#[derive(Debug)]
+pub enum Input<'a> {
+    /// Bytes read from the transport. They don't need to be aligned to
+    /// message boundaries: incomplete messages are buffered until the rest
+    /// arrives.
+    Bytes(&'a [u8]),
+    /// An inbound message which was read and decoded by the caller.
+    Message(Message<'a, &'a [u8]>),
+    /// The timer armed by the last [`Action::ArmTimer`] has expired.
+    Tick,
+    /// An application message to send, identified by the caller's `id`.
+    /// Messages sent before the Logon handshake is complete are queued until
+    /// then, and all messages are subject to the [`Throttle`] of the
+    /// connection. Each of them eventually results in either
+    /// [`Action::Sent`] or [`Action::Throttled`] with the same `id`.
+    Send {
+        /// Identifies the message in the resulting [`Action`].
+        id: u64,
+        /// The message itself.
+        message: OutboundMessage,
+    },
+    /// An administrative command, e.g. from a [`SessionAdmin`]. It always
+    /// results in exactly one [`Action::Admin`], ahead of any other
+    /// resulting action.
+    Admin(AdminCommand),
+    /// The transport has been closed.
+    Disconnect,
+}
+
+/// Something that the caller of [`FixConnection::handle`] must do on behalf
+/// of the FIX session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Write these bytes to the transport.
+    Write(Vec<u8>),
+    /// Feed [`Input::Tick`] to the [`FixConnection`] at the given time. This
+    /// replaces any previously armed timer.
+    ArmTimer(DateTime<Utc>),
+    /// Close the transport. No further input will be processed until
+    /// [`FixConnection::connect`] is called again.
+    Disconnect,
+    /// The message of [`Input::Send`] with this `id` has been written by the
+    /// last [`Action::Write`].
+    Sent {
+        /// The `id` of the [`Input::Send`].
+        id: u64,
+        /// The `MsgSeqNum <34>` assigned to the message.
+        msg_seq_num: u64,
+    },
+    /// The message of [`Input::Send`] with this `id` has been refused by the
+    /// [`Throttle`].
+    Throttled {
+        /// The `id` of the [`Input::Send`].
+        id: u64,
+    },
+    /// The outcome of the last [`Input::Admin`].
+    Admin(Result<(), AdminError>),
+}
+
+/// The state of the current transport.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    Disconnected,
+    /// Waiting for the counterparty's `Logon <A>`.
+    LoggingOn,
+    Active,
+}
+
 //#[derive(Debug)]
 //pub struct Responses<'a> {
 //    connection: &'a mut FixConnection,
@@ -76,11 +177,20 @@ pub struct FixConnectionBuilder {
     begin_string: String,
     environment: Environment,
     heartbeat: Duration,
+    heartbeat_rule: HeartbeatRule,
+    role: Role,
+    schedule: Option<Schedule>,
     seq_numbers: SeqNumbers,
-    msg_seq_num_inbound: MsgSeqNumCounter,
-    msg_seq_num_outbound: MsgSeqNumCounter,
     sender_comp_id: String,
     target_comp_id: String,
+    message_store: Option<Arc<Mutex<dyn MessageStore + Send>>>,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    appl_versions: ApplVersions,
+    metrics: Option<MetricsRegistry>,
+    log: Option<Arc<Mutex<dyn SessionLog>>>,
+    mirror: Option<Mirror>,
+    throttle: Throttle,
 }
 
 impl FixConnectionBuilder {
@@ -119,7 +229,137 @@ impl FixConnectionBuilder {
         self.target_comp_id = target_comp_id.into();
     }
 
+    /// Sets `HeartBtInt <108>`, which initiators send in their `Logon <A>`.
+    /// Acceptors adopt the counterparty's value instead. 30 seconds by
+    /// default.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Sets whether the connection initiates the Logon handshake or waits for
+    /// the counterparty to do so. Defaults to [`Role::Initiator`].
+    pub fn set_role(&mut self, role: Role) {
+        self.role = role;
+    }
+
+    /// Sets the [`HeartbeatRule`] that the counterparty's `HeartBtInt <108>`
+    /// must satisfy. It only applies to [`Role::Acceptor`] connections, and
+    /// it defaults to [`HeartbeatRule::Any`].
+    pub fn set_heartbeat_rule(&mut self, rule: HeartbeatRule) {
+        self.heartbeat_rule = rule;
+    }
+
+    /// Restricts the connection to the time window of `schedule`.
+    /// [`FixInitiator`](super::FixInitiator)s wait for the session to start
+    /// before connecting, acceptors refuse Logons outside of it, and both log
+    /// out when the session ends. The
+    /// message store is reset at the start of each new session.
+    ///
+    /// Connections are always active by default.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = Some(schedule);
+    }
+
+    /// Sets the session-level [`Config`].
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Sets the [`Clock`] which [`FixConnection::start`] reads the current
+    /// time from, e.g. to stamp and validate `SendingTime <52>` and to run
+    /// heartbeat timers. A [`SystemClock`] is used by default.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Sets the [`MessageStore`] used to persist outbound messages and seq.
+    /// numbers. Seq. numbers found in `store` take precedence over those
+    /// provided via [`FixConnectionBuilder::set_seq_numbers`].
+    ///
+    /// A [`MemoryStore`] is used by default. Clones of `self` share the same
+    /// store. Schedule resets compare the creation time of `store` against
+    /// the [`Clock`] of the connection, so custom clocks should be shared with
+    /// the store, e.g. via [`MemoryStore::with_clock`].
+    pub fn set_message_store<S>(&mut self, store: S)
+    where
+        S: MessageStore + Send + 'static,
+    {
+        self.message_store = Some(Arc::new(Mutex::new(store)));
+    }
+
+    /// Sets `DefaultApplVerID <1137>`, which is sent at Logon over FIXT.1.1
+    /// sessions. It's also the application version of inbound messages
+    /// without `ApplVerID <1128>`, unless the counterparty's `Logon <A>`
+    /// specifies otherwise.
+    pub fn set_default_appl_ver_id(&mut self, appl_ver_id: ApplVerId) {
+        self.appl_versions.set_default(appl_ver_id);
+    }
+
+    /// Decodes inbound application messages of version `appl_ver_id` with
+    /// `dictionary`, e.g. [`Dictionary::fix50sp2`](crate::Dictionary) for
+    /// [`ApplVerId::Fix50Sp2`]. This is meant for FIXT.1.1 sessions, whose
+    /// session-level messages are still decoded with the `Decoder` provided
+    /// to [`FixConnection::start`] (i.e. a FIXT.1.1 one).
+    ///
+    /// Once at least one application dictionary is configured, messages of
+    /// any other version are refused with a `Reject <3>`.
+    pub fn add_appl_dictionary(&mut self, appl_ver_id: ApplVerId, dictionary: crate::Dictionary) {
+        self.appl_versions.add(appl_ver_id, dictionary);
+    }
+
+    /// Registers the [`SessionMetrics`] of the connection with `registry`,
+    /// so that they're rendered by [`MetricsRegistry::render`]. Metrics are
+    /// always recorded, and available via [`FixConnection::metrics`].
+    pub fn set_metrics_registry(&mut self, registry: MetricsRegistry) {
+        self.metrics = Some(registry);
+    }
+
+    /// Sets the [`SessionLog`] which records all inbound and outbound
+    /// messages, together with session events. Nothing is logged by default.
+    /// Clones of `self` share the same log.
+    pub fn set_log<L>(&mut self, log: L)
+    where
+        L: SessionLog + 'static,
+    {
+        self.log = Some(Arc::new(Mutex::new(log)));
+    }
+
+    /// Sets the [`Mirror`] which copies all inbound and outbound messages,
+    /// exactly as they're passed to the [`SessionLog`] and timestamped by
+    /// the [`Clock`] of the connection. Nothing is mirrored by default.
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Sets the [`Throttle`] of outbound application messages. Messages are
+    /// never throttled by default.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
     pub fn build(self) -> FixConnection {
+        let (outbound_sender, outbound_receiver) = mpsc::unbounded();
+        let (admin_sender, admin_receiver) = mpsc::unbounded();
+        let session_id = SessionId {
+            begin_string: self.begin_string.clone(),
+            sender_comp_id: self.sender_comp_id.clone(),
+            target_comp_id: self.target_comp_id.clone(),
+        };
+        let metrics = self
+            .metrics
+            .map(|registry| registry.session(&session_id))
+            .unwrap_or_default();
+        let now = self.clock.now();
+        let seq_numbers = self.seq_numbers;
+        let clock = &self.clock;
+        let store = self.message_store.unwrap_or_else(|| {
+            let store = MemoryStore::with_clock(seq_numbers, clock.clone());
+            Arc::new(Mutex::new(store))
+        });
+        let seq_numbers = store.lock().unwrap().seq_numbers();
         FixConnection {
             uuid: Uuid::new_v4(),
             buffer: vec![],
@@ -127,11 +367,46 @@ impl FixConnectionBuilder {
             environment: self.environment,
             encoder: Encoder::default(),
             heartbeat: self.heartbeat,
-            seq_numbers: self.seq_numbers,
-            msg_seq_num_inbound: self.msg_seq_num_inbound,
-            msg_seq_num_outbound: self.msg_seq_num_outbound,
+            heartbeat_rule: self.heartbeat_rule,
+            role: self.role,
+            schedule: self.schedule.clone(),
+            msg_seq_num_inbound: MsgSeqNumCounter(seq_numbers.next_inbound() - 1),
+            msg_seq_num_outbound: MsgSeqNumCounter(seq_numbers.next_outbound() - 1),
             sender_comp_id: self.sender_comp_id,
             target_comp_id: self.target_comp_id,
+            phase: Phase::Disconnected,
+            established: false,
+            now,
+            last_sent: now,
+            last_received: now,
+            session_end: None,
+            decoder: None,
+            inbound_buffer: Vec::new(),
+            inbound_queue: BTreeMap::new(),
+            resend_request_end: None,
+            store,
+            store_decoder: None,
+            queue_decoder: None,
+            pending_test_req_id: None,
+            test_request_sent: false,
+            logout_sent: false,
+            logout_received: false,
+            outbound_sender,
+            outbound_receiver,
+            outbound_queue: Vec::new(),
+            next_request_id: 0,
+            admin_sender,
+            admin_receiver,
+            reset_on_logon: false,
+            validator: None,
+            appl_versions: self.appl_versions,
+            config: self.config,
+            clock: self.clock,
+            metrics,
+            log: self.log,
+            mirror: self.mirror,
+            throttle: self.throttle,
+            peer_addr: None,
         }
     }
 }
@@ -139,14 +414,23 @@ impl FixConnectionBuilder {
 impl Default for FixConnectionBuilder {
     fn default() -> Self {
         Self {
-            msg_seq_num_inbound: MsgSeqNumCounter::START,
-            msg_seq_num_outbound: MsgSeqNumCounter::START,
-            begin_string: "FIX-4.4".to_string(),
+            begin_string: "FIX.4.4".to_string(),
             environment: Environment::Testing,
             heartbeat: Duration::from_secs(30),
+            heartbeat_rule: HeartbeatRule::Any,
+            role: Role::Initiator,
+            schedule: None,
             seq_numbers: SeqNumbers::default(),
             sender_comp_id: "ABC".to_string(),
             target_comp_id: "XYZ".to_string(),
+            message_store: None,
+            config: Config::default(),
+            clock: Arc::new(SystemClock),
+            appl_versions: ApplVersions::default(),
+            metrics: None,
+            log: None,
+            mirror: None,
+            throttle: Throttle::default(),
         }
     }
 }
@@ -160,126 +444,749 @@ pub struct FixConnection {
     encoder: Encoder,
     buffer: Vec<u8>,
     heartbeat: Duration,
-    seq_numbers: SeqNumbers,
+    heartbeat_rule: HeartbeatRule,
+    role: Role,
+    schedule: Option<Schedule>,
     msg_seq_num_inbound: MsgSeqNumCounter,
     msg_seq_num_outbound: MsgSeqNumCounter,
     sender_comp_id: String,
     target_comp_id: String,
+    phase: Phase,
+    // Whether the Logon handshake over the current transport was successful.
+    established: bool,
+    // The time of the last input.
+    now: DateTime<Utc>,
+    last_sent: DateTime<Utc>,
+    last_received: DateTime<Utc>,
+    session_end: Option<DateTime<Utc>>,
+    // Decodes inbound messages, which are buffered until complete. It becomes
+    // available once the connection starts.
+    decoder: Option<Decoder>,
+    inbound_buffer: Vec<u8>,
+    // Out-of-order inbound messages, indexed by `MsgSeqNum <34>`, waiting for
+    // the gap before them to be filled.
+    inbound_queue: BTreeMap<u64, Vec<u8>>,
+    // The last seq. number requested by the pending `ResendRequest <2>`, if
+    // any.
+    resend_request_end: Option<u64>,
+    store: Arc<Mutex<dyn MessageStore + Send>>,
+    // Decodes stored outbound messages before replaying them. It becomes
+    // available once the connection starts.
+    store_decoder: Option<Decoder>,
+    // Decodes queued inbound messages, once their turn comes.
+    queue_decoder: Option<Decoder>,
+    // The `TestReqID <112>` of the last `TestRequest <1>` which is still
+    // waiting for a `Heartbeat <0>`, if any.
+    pending_test_req_id: Option<String>,
+    // Whether a `TestRequest <1>` was sent since the last inbound message.
+    test_request_sent: bool,
+    logout_sent: bool,
+    logout_received: bool,
+    // Application messages sent via `SessionSender`s.
+    outbound_sender: mpsc::UnboundedSender<OutboundRequest>,
+    outbound_receiver: mpsc::UnboundedReceiver<OutboundRequest>,
+    // Application messages waiting for the Logon handshake to complete,
+    // together with their `Input::Send` ids.
+    outbound_queue: Vec<(u64, OutboundMessage)>,
+    // The id of the next `Input::Send` issued by `start`. It's never reset,
+    // so that messages queued by a previous run can't be mistaken for new
+    // ones.
+    next_request_id: u64,
+    // Requests from `SessionAdmin`s.
+    admin_sender: mpsc::UnboundedSender<AdminRequest>,
+    admin_receiver: mpsc::UnboundedReceiver<AdminRequest>,
+    // Whether the next `Logon <A>` must reset seq. numbers, after an
+    // `AdminCommand::Reset`.
+    reset_on_logon: bool,
+    // Only available once the connection starts, if enabled.
+    validator: Option<Validator>,
+    appl_versions: ApplVersions,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    metrics: SessionMetrics,
+    log: Option<Arc<Mutex<dyn SessionLog>>>,
+    mirror: Option<Mirror>,
+    throttle: Throttle,
+    // The counterparty's address over the current transport, if known.
+    peer_addr: Option<SocketAddr>,
 }
 
 #[allow(dead_code)]
 impl FixConnection {
+    /// Runs the FIX session over `input` and `output` until the transport is
+    /// closed or the session is terminated. Returns `true` if the Logon
+    /// handshake was successful.
+    ///
+    /// This is a thin asynchronous driver around [`FixConnection::connect`]
+    /// and [`FixConnection::handle`]: it feeds them inbound bytes, timer
+    /// expirations and messages sent via [`SessionSender`]s, timestamped by
+    /// the [`Clock`] of `self`, and then carries out the resulting
+    /// [`Action`]s.
+    ///
+    /// Seq. numbers are preserved, so the same [`FixConnection`] can be
+    /// started again over a new transport. Initiators log on right away, even
+    /// outside of their [`Schedule`]: it's up to the caller (e.g.
+    /// [`FixInitiator`](super::FixInitiator)) to connect in time.
     pub async fn start<B, I, O>(
         &mut self,
         mut app: B,
         mut input: I,
         mut output: O,
         decoder: Decoder,
-    ) where
+    ) -> bool
+    where
         B: Backend,
         I: AsyncRead + Unpin,
         O: AsyncWrite + Unpin,
     {
-        let mut decoder = decoder.buffered();
-        self.establish_connection(&mut app, &mut input, &mut output, &mut decoder)
-            .await;
-        self.event_loop(app, input, output, decoder).await;
+        let mut actions = self.connect(&mut app, decoder, self.clock.now());
+        let mut timer = Fuse::terminated();
+        let mut buffer = vec![0; 4096];
+        // `SessionSender`s only get their `MsgSeqNum <34>` back once the
+        // message has been written, which might take a while if it's
+        // throttled.
+        let mut replies: HashMap<u64, Reply> = HashMap::new();
+        let mut admin_replies: VecDeque<AdminReply> = VecDeque::new();
+        loop {
+            for action in actions {
+                match action {
+                    Action::Write(bytes) => {
+                        if output.write_all(&bytes).await.is_err() {
+                            self.handle(&mut app, Input::Disconnect, self.clock.now());
+                            return self.established;
+                        }
+                    }
+                    Action::ArmTimer(deadline) => timer = self.clock.sleep_until(deadline).fuse(),
+                    Action::Disconnect => return self.established,
+                    Action::Sent { id, msg_seq_num } => {
+                        if let Some(reply) = replies.remove(&id) {
+                            reply.send(Ok(msg_seq_num)).ok();
+                        }
+                    }
+                    Action::Throttled { id } => {
+                        if let Some(reply) = replies.remove(&id) {
+                            reply.send(Err(SendError::Throttled)).ok();
+                        }
+                    }
+                    Action::Admin(outcome) => {
+                        if let Some(reply) = admin_replies.pop_front() {
+                            reply.send(outcome).ok();
+                        }
+                    }
+                }
+            }
+            actions = select! {
+                len = input.read(&mut buffer).fuse() => {
+                    let now = self.clock.now();
+                    match len {
+                        Ok(len) if len > 0 => {
+                            self.handle(&mut app, Input::Bytes(&buffer[..len]), now)
+                        }
+                        _ => {
+                            self.handle(&mut app, Input::Disconnect, now);
+                            return self.established;
+                        }
+                    }
+                },
+                () = timer => self.handle(&mut app, Input::Tick, self.clock.now()),
+                request = if self.phase == Phase::Active {
+                    self.outbound_receiver.next().left_future()
+                } else {
+                    future::pending().right_future()
+                } => {
+                    match request {
+                        Some((message, reply)) => {
+                            let id = self.next_request_id;
+                            self.next_request_id += 1;
+                            replies.insert(id, reply);
+                            self.handle(&mut app, Input::Send { id, message }, self.clock.now())
+                        }
+                        None => vec![],
+                    }
+                },
+                request = self.admin_receiver.next() => {
+                    match request {
+                        Some(AdminRequest::State(reply)) => {
+                            reply.send(self.state()).ok();
+                            vec![]
+                        }
+                        Some(AdminRequest::Command(command, reply)) => {
+                            admin_replies.push_back(reply);
+                            self.handle(&mut app, Input::Admin(command), self.clock.now())
+                        }
+                        None => vec![],
+                    }
+                },
+            };
+        }
+    }
+
+    /// Starts the FIX session over a new transport, whose inbound messages
+    /// are decoded by `decoder`. Initiators send their `Logon <A>` right
+    /// away, while acceptors wait for the counterparty's.
+    ///
+    /// Together with [`FixConnection::handle`], this is the sans-I/O core of
+    /// [`FixConnection`]: it never touches the transport nor reads the system
+    /// time, so it can be embedded in any event loop and tested
+    /// deterministically. [`Backend`] callbacks are invoked synchronously,
+    /// while the returned [`Action`]s are up to the caller.
+    pub fn connect<B>(&mut self, app: &mut B, decoder: Decoder, now: DateTime<Utc>) -> Vec<Action>
+    where
+        B: Backend,
+    {
+        self.now = now;
+        // Gap recovery and Logout can't span across transports.
+        self.inbound_queue.clear();
+        self.inbound_buffer.clear();
+        self.resend_request_end = None;
+        self.pending_test_req_id = None;
+        self.test_request_sent = false;
+        self.logout_sent = false;
+        self.logout_received = false;
+        self.established = false;
+        self.store_decoder = Some(Decoder::with_config(
+            decoder.dictionary().clone(),
+            *decoder.config(),
+        ));
+        self.queue_decoder = Some(Decoder::with_config(
+            decoder.dictionary().clone(),
+            *decoder.config(),
+        ));
+        self.validator = if self.config.validate_messages() {
+            Some(Validator::new(decoder.dictionary().clone()))
+        } else {
+            None
+        };
+        self.appl_versions
+            .set_validation(if self.config.validate_messages() {
+                Some(decoder.dictionary())
+            } else {
+                None
+            });
+        self.decoder = Some(decoder);
+        self.reset_if_new_session();
+        self.phase = Phase::LoggingOn;
+        if self.throttle.queue_depth() > 0 {
+            self.throttle.clear();
+            app.on_throttle_queue_depth(0).ok();
+        }
+        let reset_on_logon = std::mem::take(&mut self.reset_on_logon);
+        let mut actions = vec![];
+        if self.role == Role::Initiator {
+            let reset_seq_num = self.config.reset_seq_num_on_logon() || reset_on_logon;
+            if reset_seq_num {
+                self.reset_seq_numbers();
+            }
+            let next_expected = self.msg_seq_num_inbound.expected();
+            let logon = self.build_logon(app, reset_seq_num, next_expected, None);
+            push_response(Response::OutboundBytes(logon), app, &mut actions);
+        }
+        self.finish(actions)
+    }
+
+    /// Processes `input`, which took place at time `now`, and returns the
+    /// resulting [`Action`]s in order. [`FixConnection::connect`] must be
+    /// called first for each new transport.
+    pub fn handle<B>(&mut self, app: &mut B, input: Input, now: DateTime<Utc>) -> Vec<Action>
+    where
+        B: Backend,
+    {
+        self.now = now;
+        let mut actions = vec![];
+        match input {
+            Input::Bytes(bytes) => {
+                self.inbound_buffer.extend_from_slice(bytes);
+                self.on_inbound_bytes(app, &mut actions);
+            }
+            Input::Message(msg) => self.on_message(msg, app, &mut actions),
+            Input::Tick => self.on_tick(app, &mut actions),
+            Input::Send { id, message } => {
+                if self.phase == Phase::Active {
+                    self.on_send(id, message, app, &mut actions);
+                } else {
+                    self.outbound_queue.push((id, message));
+                }
+            }
+            Input::Admin(command) => self.on_admin(command, app, &mut actions),
+            Input::Disconnect => {
+                if self.phase != Phase::Disconnected {
+                    self.log_event("Disconnected by the transport");
+                }
+                self.phase = Phase::Disconnected;
+            }
+        }
+        self.finish(actions)
+    }
+
+    /// Decodes and processes all complete messages in the inbound buffer.
+    /// Garbled messages (e.g. with a wrong `CheckSum <10>`) are ignored, as
+    /// required by specs. §4.7, but data which can't even be split into
+    /// messages terminates the transport.
+    fn on_inbound_bytes<B>(&mut self, app: &mut B, actions: &mut Vec<Action>)
+    where
+        B: Backend,
+    {
+        let mut decoder = match self.decoder.take() {
+            Some(decoder) => decoder,
+            None => return,
+        };
+        let separator = crate::tagvalue::Configure::separator(decoder.config());
+        while self.phase != Phase::Disconnected {
+            let len = match frame_len(&self.inbound_buffer[..], separator) {
+                Ok(Some(len)) => len,
+                Ok(None) => break,
+                Err(()) => {
+                    self.disconnect(actions);
+                    break;
+                }
+            };
+            let frame: Vec<u8> = self.inbound_buffer.drain(..len).collect();
+            match decoder.decode(frame.as_slice()) {
+                Ok(msg) => self.on_message(msg, app, actions),
+                Err(_) => {
+                    let text = format!(
+                        "Garbled message ignored: {}",
+                        String::from_utf8_lossy(&frame)
+                    );
+                    self.log_event(&text);
+                }
+            }
+        }
+        self.decoder = Some(decoder);
+    }
+
+    fn on_message<B>(&mut self, msg: Message<&[u8]>, app: &mut B, actions: &mut Vec<Action>)
+    where
+        B: Backend,
+    {
+        if self.phase != Phase::Disconnected {
+            self.metrics.on_inbound(&msg, self.now);
+            if let Some(log) = self.log.as_ref() {
+                let mut log = log.lock().unwrap();
+                log.on_incoming(self.now, msg.as_bytes()).ok();
+            }
+            if let Some(mirror) = self.mirror.as_mut() {
+                mirror.mirror_at(Direction::Inbound, msg.as_bytes(), self.now);
+            }
+            if let Some(event) = inbound_event(&msg) {
+                self.log_event(&event);
+            }
+        }
+        match self.phase {
+            Phase::Disconnected => {}
+            Phase::LoggingOn => self.on_logon_received(msg, app, actions),
+            Phase::Active => {
+                let response = self.on_inbound_message(msg, app);
+                push_response(response, app, actions);
+                // Any inbound message is proof that the counterparty is
+                // alive.
+                self.last_received = self.now;
+                self.test_request_sent = false;
+                // The last message might have filled a gap, which allows us
+                // to process queued messages in order.
+                if let Some(mut decoder) = self.queue_decoder.take() {
+                    while let Some(queued) = self.pop_queued_message() {
+                        if let Ok(msg) = decoder.decode(queued.as_slice()) {
+                            let response = self.on_inbound_message(msg, app);
+                            push_response(response, app, actions);
+                        }
+                    }
+                    self.queue_decoder = Some(decoder);
+                }
+                let response = self.resume_gap_recovery();
+                push_response(response, app, actions);
+                if self.is_logged_out() {
+                    self.disconnect(actions);
+                }
+            }
+        }
     }
 
-    async fn establish_connection<A, I, O>(
+    /// Completes the Logon handshake according to the [`Role`] of `self`,
+    /// once the counterparty's first message is received. The transport is
+    /// closed if the handshake fails.
+    fn on_logon_received<B>(
         &mut self,
-        app: &mut A,
-        mut input: &mut I,
-        output: &mut O,
-        decoder: &mut DecoderBuffered,
+        logon: Message<&[u8]>,
+        app: &mut B,
+        actions: &mut Vec<Action>,
     ) where
-        A: Backend,
-        I: AsyncRead + Unpin,
-        O: AsyncWrite + Unpin,
+        B: Backend,
     {
-        let logon = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"A");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::SENDING_TIME, chrono::Utc::now());
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
-            msg.set(fix44::ENCRYPT_METHOD, fix44::EncryptMethod::None);
-            msg.set(fix44::HEART_BT_INT, self.heartbeat.as_secs());
-            msg.wrap()
+        // The first message must be a `Logon <A>`, otherwise we disconnect
+        // without sending a `Logout <5>`. See specs. §4.3.
+        if logon.fv::<&[u8], _>(fix44::MSG_TYPE) != Ok(b"A") {
+            return self.disconnect(actions);
+        }
+        app.on_inbound_message(logon, false).ok();
+        let validation = self.validate_logon(&logon);
+        let validation = validation.and_then(|heartbeat| {
+            self.appl_versions.on_logon(&logon)?;
+            Ok(heartbeat)
+        });
+        let heartbeat = match validation {
+            Ok(heartbeat) => heartbeat,
+            Err(text) => {
+                let logout = self.make_logout(text);
+                push_response(logout, app, actions);
+                return self.disconnect(actions);
+            }
         };
-        output.write(logon).await.unwrap();
-        app.on_outbound_message(logon).ok();
-        let logon;
-        loop {
-            let mut input = Pin::new(&mut input);
-            let buffer = decoder.supply_buffer();
-            input.read_exact(buffer).await.unwrap();
-            if let Ok(Some(())) = decoder.state() {
-                logon = decoder.message();
-                break;
+        let mut session_status = None;
+        if self.role == Role::Acceptor {
+            let authentication = app.authenticate(&LogonRequest::new(logon, self.peer_addr));
+            session_status = authentication.session_status();
+            if !authentication.is_accepted() {
+                let logout = self.make_authentication_logout(&authentication);
+                push_response(logout, app, actions);
+                return self.disconnect(actions);
+            }
+        }
+        let reset_seq_num = logon.fv::<bool, _>(fix44::RESET_SEQ_NUM_FLAG) == Ok(true);
+        let replayed = match self.role {
+            Role::Initiator => {
+                if reset_seq_num {
+                    self.msg_seq_num_inbound = MsgSeqNumCounter::START;
+                    self.persist_seq_numbers();
+                }
+                // Our own `Logon <A>` is the last message we sent.
+                let last_sent = self.msg_seq_num_outbound.0;
+                self.on_next_expected_msg_seq_num(&logon, last_sent)
+            }
+            Role::Acceptor => {
+                self.heartbeat = heartbeat;
+                if reset_seq_num {
+                    self.reset_seq_numbers();
+                }
+                let last_sent = self.msg_seq_num_outbound.0;
+                let replayed = self.on_next_expected_msg_seq_num(&logon, last_sent);
+                if replayed.is_ok() {
+                    let expected = self.msg_seq_num_inbound.expected();
+                    let next_expected = match logon.fv::<u64, _>(fix44::MSG_SEQ_NUM) {
+                        Ok(msg_seq_num) if msg_seq_num == expected => expected + 1,
+                        _ => expected,
+                    };
+                    let logon = self.build_logon(app, reset_seq_num, next_expected, session_status);
+                    push_response(Response::OutboundBytes(logon), app, actions);
+                }
+                replayed
+            }
+        };
+        match replayed {
+            Ok(messages) => push_response(Response::Resend { messages }, app, actions),
+            Err(text) => {
+                let logout = self.make_logout(text);
+                push_response(logout, app, actions);
+                return self.disconnect(actions);
+            }
+        }
+        match self.on_logon_seq_num(logon) {
+            Ok(response) => push_response(response, app, actions),
+            Err(logout) => {
+                push_response(logout, app, actions);
+                return self.disconnect(actions);
             }
         }
-        self.on_logon(logon);
-        app.on_inbound_message(logon, true).ok();
-        decoder.clear();
-        self.msg_seq_num_inbound.next();
+        let now = self.now;
+        self.phase = Phase::Active;
+        self.established = true;
+        self.last_received = now;
+        self.session_end = self
+            .schedule
+            .as_ref()
+            .and_then(|schedule| schedule.time_until_end(now))
+            .and_then(|duration| add_duration(now, duration));
+        self.log_event("Logon contact established");
         app.on_successful_handshake().ok();
+        for (id, message) in std::mem::take(&mut self.outbound_queue) {
+            self.on_send(id, message, app, actions);
+        }
     }
 
-    async fn event_loop<A, I, O>(
+    /// Sends `message` as soon as the [`Throttle`] allows it, unless it's
+    /// refused.
+    fn on_send<B>(
         &mut self,
-        mut app: A,
-        input: I,
-        mut output: O,
-        decoder: DecoderBuffered,
+        id: u64,
+        message: OutboundMessage,
+        app: &mut B,
+        actions: &mut Vec<Action>,
     ) where
-        A: Backend,
-        I: AsyncRead + Unpin,
-        O: AsyncWrite + Unpin,
+        B: Backend,
     {
-        let event_loop = &mut LlEventLoop::new(decoder, input, self.heartbeat);
-        loop {
-            let event = event_loop.next().await;
-            match event {
-                LlEvent::Message { msg } => {
-                    let response = self.on_inbound_message(msg, &mut app);
-                    match response {
-                        Response::OutboundBytes(bytes) => {
-                            output.write_all(bytes).await.unwrap();
-                            app.on_outbound_message(bytes).ok();
-                        }
-                        Response::ResetHeartbeat => {
-                            event_loop.ping_heartbeat();
-                        }
-                        _ => {}
-                    }
+        let depth = self.throttle.queue_depth();
+        if self.throttle.push(id, message, self.now) {
+            self.release_throttled(app, actions);
+        } else {
+            actions.push(Action::Throttled { id });
+        }
+        if self.throttle.queue_depth() != depth {
+            app.on_throttle_queue_depth(self.throttle.queue_depth())
+                .ok();
+        }
+    }
+
+    /// Sends all queued application messages which the [`Throttle`] allows.
+    fn release_throttled<B>(&mut self, app: &mut B, actions: &mut Vec<Action>)
+    where
+        B: Backend,
+    {
+        while let Some((id, message)) = self.throttle.pop(self.now) {
+            let (msg_seq_num, bytes) = self.on_outbound_app_message(&message);
+            push_response(Response::OutboundBytes(bytes), app, actions);
+            actions.push(Action::Sent { id, msg_seq_num });
+        }
+    }
+
+    /// Carries out an [`AdminCommand`], whose outcome comes first among the
+    /// resulting actions.
+    fn on_admin<B>(&mut self, command: AdminCommand, app: &mut B, actions: &mut Vec<Action>)
+    where
+        B: Backend,
+    {
+        let is_logged_on = self.phase == Phase::Active;
+        let outcome = match &command {
+            AdminCommand::SetNextInbound(0) | AdminCommand::SetNextOutbound(0) => {
+                Err(AdminError::InvalidSeqNum)
+            }
+            AdminCommand::ResendRequest {
+                begin_seq_no,
+                end_seq_no,
+            } if *begin_seq_no == 0 || (*end_seq_no != 0 && end_seq_no < begin_seq_no) => {
+                Err(AdminError::InvalidSeqNum)
+            }
+            AdminCommand::ResendRequest { .. }
+            | AdminCommand::TestRequest
+            | AdminCommand::Logout(_)
+                if !is_logged_on =>
+            {
+                Err(AdminError::NotLoggedOn)
+            }
+            _ => Ok(()),
+        };
+        actions.push(Action::Admin(outcome));
+        if outcome.is_err() {
+            return;
+        }
+        match command {
+            AdminCommand::SetNextInbound(msg_seq_num) => {
+                self.log_event(&format!("Next inbound MsgSeqNum set to {}", msg_seq_num));
+                self.msg_seq_num_inbound = MsgSeqNumCounter(msg_seq_num - 1);
+                self.persist_seq_numbers();
+            }
+            AdminCommand::SetNextOutbound(msg_seq_num) => {
+                self.log_event(&format!("Next outbound MsgSeqNum set to {}", msg_seq_num));
+                self.msg_seq_num_outbound = MsgSeqNumCounter(msg_seq_num - 1);
+                self.persist_seq_numbers();
+            }
+            AdminCommand::ResendRequest {
+                begin_seq_no,
+                end_seq_no,
+            } => {
+                let resend_request = self.make_resend_request(begin_seq_no, end_seq_no);
+                push_response(resend_request, app, actions);
+            }
+            AdminCommand::TestRequest => {
+                let test_request = self.on_test_request_is_due();
+                push_response(Response::OutboundBytes(test_request), app, actions);
+            }
+            AdminCommand::Logout(text) => {
+                let logout = self.make_logout(text);
+                push_response(logout, app, actions);
+            }
+            AdminCommand::Disconnect => {
+                if self.phase != Phase::Disconnected {
+                    self.disconnect(actions);
+                }
+            }
+            AdminCommand::Reset => {
+                if is_logged_on {
+                    let logout = self.make_logout(errs::session_reset());
+                    push_response(logout, app, actions);
+                    self.disconnect(actions);
+                }
+                self.log_event("Session reset");
+                self.reset_seq_numbers();
+                self.reset_on_logon = true;
+            }
+        }
+    }
+
+    /// Sends `Heartbeat <0>` and `TestRequest <1>` messages when they're due,
+    /// and terminates the session once the [`Schedule`] is over or the
+    /// counterparty is unresponsive. See specs. §4.7.
+    fn on_tick<B>(&mut self, app: &mut B, actions: &mut Vec<Action>)
+    where
+        B: Backend,
+    {
+        if self.phase != Phase::Active {
+            return;
+        }
+        let now = self.now;
+        let is_due = |deadline: Option<DateTime<Utc>>| matches!(deadline, Some(d) if d <= now);
+        if is_due(self.session_end) {
+            let logout = self.make_logout(errs::session_end());
+            push_response(logout, app, actions);
+            return self.disconnect(actions);
+        }
+        let depth = self.throttle.queue_depth();
+        self.release_throttled(app, actions);
+        if self.throttle.queue_depth() != depth {
+            app.on_throttle_queue_depth(self.throttle.queue_depth())
+                .ok();
+        }
+        // A `HeartBtInt <108>` of zero disables heartbeats altogether.
+        if self.heartbeat == Duration::from_secs(0) {
+            return;
+        }
+        if is_due(logout_deadline(self.last_received, self.heartbeat)) {
+            // The counterparty is unresponsive, so there's no point in
+            // waiting for its `Logout <5>`.
+            let logout = self.make_logout(errs::heartbeat_timeout());
+            push_response(logout, app, actions);
+            self.metrics.on_test_request_timeout();
+            self.log_event("Timed out waiting for heartbeat");
+            app.on_test_request_timeout().ok();
+            return self.disconnect(actions);
+        }
+        let test_request_deadline = test_request_deadline(self.last_received, self.heartbeat);
+        if !self.test_request_sent && is_due(test_request_deadline) {
+            self.test_request_sent = true;
+            self.metrics.on_heartbeat_timeout();
+            self.log_event("Heartbeat timeout, sending TestRequest");
+            let test_request = self.on_test_request_is_due();
+            push_response(Response::OutboundBytes(test_request), app, actions);
+        } else if is_due(add_duration(self.last_sent, self.heartbeat)) {
+            let heartbeat = self.on_heartbeat_is_due();
+            push_response(Response::OutboundBytes(heartbeat), app, actions);
+        }
+    }
+
+    /// Returns the next time at which [`Input::Tick`] is due, if any.
+    fn next_tick(&self) -> Option<DateTime<Utc>> {
+        if self.phase != Phase::Active {
+            return None;
+        }
+        let mut deadlines = vec![self.session_end, self.throttle.next_release(self.now)];
+        if self.heartbeat > Duration::from_secs(0) {
+            deadlines.push(add_duration(self.last_sent, self.heartbeat));
+            deadlines.push(if self.test_request_sent {
+                logout_deadline(self.last_received, self.heartbeat)
+            } else {
+                test_request_deadline(self.last_received, self.heartbeat)
+            });
+        }
+        deadlines.into_iter().flatten().min()
+    }
+
+    /// Keeps track of outbound traffic and arms the timer for the next
+    /// [`Input::Tick`].
+    fn finish(&mut self, mut actions: Vec<Action>) -> Vec<Action> {
+        for action in actions.iter() {
+            if let Action::Write(bytes) = action {
+                if is_admin_msg_type(raw_msg_type(bytes)) {
+                    self.throttle.on_admin_message(self.now);
                 }
-                LlEvent::IoError { err: _err } => {
-                    return;
+                self.metrics.on_outbound(bytes);
+                if let Some(log) = self.log.as_ref() {
+                    let mut log = log.lock().unwrap();
+                    log.on_outgoing(self.now, bytes).ok();
+                    if let Some(event) = outbound_event(bytes) {
+                        log.on_event(self.now, &event).ok();
+                    }
                 }
-                LlEvent::Heartbeat => {
-                    let heartbeat = self.on_heartbeat_is_due();
-                    output.write_all(heartbeat).await.unwrap();
-                    app.on_outbound_message(heartbeat).ok();
+                if let Some(mirror) = self.mirror.as_mut() {
+                    mirror.mirror_at(Direction::Outbound, bytes, self.now);
                 }
-                LlEvent::Logout => {}
-                LlEvent::TestRequest => {}
+                self.last_sent = self.now;
             }
         }
+        self.metrics.set_seq_numbers(
+            self.msg_seq_num_inbound.expected(),
+            self.msg_seq_num_outbound.0 + 1,
+        );
+        if let Some(deadline) = self.next_tick() {
+            actions.push(Action::ArmTimer(deadline));
+        }
+        actions
+    }
+
+    fn disconnect(&mut self, actions: &mut Vec<Action>) {
+        self.log_event("Disconnecting");
+        self.phase = Phase::Disconnected;
+        actions.push(Action::Disconnect);
+    }
+
+    fn log_event(&mut self, text: &str) {
+        if let Some(log) = self.log.as_ref() {
+            let mut log = log.lock().unwrap();
+            log.on_event(self.now, text).ok();
+        }
+    }
+
+    /// Returns a new [`SessionSender`] for sending application messages over
+    /// `self`.
+    pub fn sender(&self) -> SessionSender {
+        SessionSender::new(self.outbound_sender.clone())
+    }
+
+    /// Returns the [`SessionId`] of `self`, from the point of view of the local
+    /// party.
+    pub fn session_id(&self) -> SessionId {
+        SessionId {
+            begin_string: self.begin_string.clone(),
+            sender_comp_id: self.sender_comp_id.clone(),
+            target_comp_id: self.target_comp_id.clone(),
+        }
+    }
+
+    /// Sets the network address of the counterparty over the next transport,
+    /// which is handed over to [`Backend::authenticate`]. It should be set
+    /// before [`FixConnection::start`] or [`FixConnection::connect`].
+    pub fn set_peer_addr(&mut self, peer_addr: Option<SocketAddr>) {
+        self.peer_addr = peer_addr;
+    }
+
+    /// Returns a new [`SessionAdmin`] for inspecting and controlling `self`
+    /// while [`FixConnection::start`] is running.
+    pub fn admin(&self) -> SessionAdmin {
+        SessionAdmin::new(self.admin_sender.clone())
+    }
+
+    /// Returns a snapshot of the current [`SessionState`] of `self`.
+    pub fn state(&self) -> SessionState {
+        SessionState {
+            is_logged_on: self.phase == Phase::Active,
+            next_inbound: self.msg_seq_num_inbound.expected(),
+            next_outbound: self.msg_seq_num_outbound.expected(),
+            last_sent: self.last_sent,
+            last_received: self.last_received,
+        }
+    }
+
+    /// Returns the number of application messages held back by the
+    /// [`Throttle`].
+    pub fn throttle_queue_depth(&self) -> usize {
+        self.throttle.queue_depth()
+    }
+
+    /// Returns the [`Clock`] of `self`.
+    pub(crate) fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Returns the [`Schedule`] of `self`, if any.
+    pub(crate) fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
     }
 
-    fn seq_numbers(&self) -> SeqNumbers {
-        self.seq_numbers
+    fn store(&self) -> MutexGuard<'_, dyn MessageStore + Send + 'static> {
+        self.store.lock().unwrap()
     }
 
-    fn seq_numbers_mut(&mut self) -> &mut SeqNumbers {
-        &mut self.seq_numbers
+    /// Returns the [`SessionMetrics`] of `self`.
+    pub fn metrics(&self) -> &SessionMetrics {
+        &self.metrics
     }
 
     fn environment(&self) -> Environment {
@@ -309,7 +1216,7 @@ impl FixConnection {
         let env = self.environment();
         // Check `TestMessageIndicator <464>`.
         if let Ok(indicator) = msg.fv::<bool, _>(fix44::TEST_MESSAGE_INDICATOR) {
-            if !env.allows_testing() && indicator {
+            if self.config.verify_test_indicator() && !env.allows_testing() && indicator {
                 return self.on_wrong_environment(msg);
             }
         }
@@ -317,12 +1224,16 @@ impl FixConnection {
         // Compare seq. numbers.
         let msg_seq_num_cmp =
             msg_seq_num.map(|seqnum| seqnum.cmp(&self.msg_seq_num_inbound.expected()));
-        // Increment immediately.
-        self.msg_seq_num_inbound.next();
         // Compare the incoming seq. number to the one we expected and act
         // accordingly.
         match msg_seq_num_cmp {
-            Ok(Ordering::Equal) => {}
+            // `SequenceReset-Reset <4>` ignores `MsgSeqNum <34>`. See specs.
+            // §4.8.
+            Ok(_) if is_sequence_reset_reset(&msg) => {}
+            Ok(Ordering::Equal) => {
+                self.msg_seq_num_inbound.next();
+                self.persist_seq_numbers();
+            }
             Ok(Ordering::Less) => {
                 return self.on_low_seqnum(msg);
             }
@@ -335,10 +1246,39 @@ impl FixConnection {
                 return self.on_missing_seqnum(msg);
             }
         };
+        if msg.fv_raw(fix44::SENDING_TIME).is_none() {
+            let tag = fix44::SENDING_TIME.tag().get().into();
+            let violation = Violation {
+                reason: fix44::SessionRejectReason::RequiredTagMissing as u32,
+                ref_tag: Some(tag),
+                text: errs::missing_field(fix44::SENDING_TIME.name(), tag),
+            };
+            return self.make_reject_for_violation(msg, violation);
+        }
         if !self.sending_time_is_ok(&msg) {
             return self.make_reject_for_inaccurate_sending_time(msg);
         }
+        if is_missing_orig_sending_time(&msg) {
+            let tag = fix44::ORIG_SENDING_TIME.tag().get().into();
+            let violation = Violation {
+                reason: fix44::SessionRejectReason::RequiredTagMissing as u32,
+                ref_tag: Some(tag),
+                text: errs::missing_field(fix44::ORIG_SENDING_TIME.name(), tag),
+            };
+            return self.make_reject_for_violation(msg, violation);
+        }
+        if !self.orig_sending_time_is_ok(&msg) {
+            return self.make_reject_for_inaccurate_orig_sending_time(msg);
+        }
         let msg_type = msg.fv::<&[u8], _>(fix44::MSG_TYPE).unwrap();
+        // Application messages of FIXT.1.1 sessions are validated against
+        // their own dictionary instead.
+        let is_versioned = self.appl_versions.is_enabled() && !is_admin_msg_type(msg_type);
+        if let Some(validator) = self.validator.as_mut().filter(|_| !is_versioned) {
+            if let Err(violation) = validator.validate(&msg) {
+                return self.make_reject_for_violation(msg, violation);
+            }
+        }
         match msg_type {
             b"A" => {
                 self.on_logon(msg);
@@ -347,17 +1287,20 @@ impl FixConnection {
             }
             b"1" => {
                 app.on_inbound_message(msg, false).ok();
-                let msg = self.on_test_request(msg);
-                return Response::OutboundBytes(msg);
+                let heartbeat = self.on_test_request(msg);
+                return Response::OutboundBytes(heartbeat);
             }
             b"2" => {
                 app.on_inbound_message(msg, false).ok();
-                self.on_resend_request(&msg, app);
-                return Response::None;
+                return self.on_resend_request(&msg, app);
+            }
+            b"4" => {
+                app.on_inbound_message(msg, false).ok();
+                return self.on_sequence_reset(msg);
             }
             b"5" => {
                 app.on_inbound_message(msg, false).ok();
-                return Response::OutboundBytes(self.on_logout(&msg));
+                return self.on_logout(&msg);
             }
             b"0" => {
                 self.on_heartbeat(msg);
@@ -365,46 +1308,305 @@ impl FixConnection {
                 return Response::ResetHeartbeat;
             }
             _ => {
-                app.on_inbound_app_message(msg).ok();
+                if !app.supports_msg_type(msg_type) {
+                    let mut reject =
+                        BusinessReject::new(fix44::BusinessRejectReason::UnsupportedMessageType);
+                    reject.set_text(errs::unsupported_msg_type());
+                    return self.make_business_reject(msg, reject);
+                }
+                let outcome = if is_versioned {
+                    self.appl_versions
+                        .decode(&msg)
+                        .and_then(|(msg, validator)| {
+                            if let Some(validator) = validator {
+                                validator.validate(&msg)?;
+                            }
+                            Ok(deliver(app, msg))
+                        })
+                } else {
+                    Ok(deliver(app, msg))
+                };
+                match outcome {
+                    Ok(None) => {}
+                    Ok(Some(reject)) => return self.make_business_reject(msg, reject),
+                    Err(violation) => return self.make_reject_for_violation(msg, violation),
+                }
                 return self.on_application_message(msg);
             }
         }
     }
 
-    fn on_resend_request<B>(&self, msg: &Message<&[u8]>, app: &mut B)
+    /// Replays all stored outbound messages requested by the counterparty.
+    /// Admin messages and messages that are missing from the store are
+    /// replaced by `SequenceReset-GapFill <4>` messages. See specs. §4.8.
+    fn on_resend_request<B>(&mut self, msg: &Message<&[u8]>, app: &mut B) -> Response
     where
         B: Backend,
     {
-        let begin_seq_num = msg.fv(fix44::BEGIN_SEQ_NO).unwrap();
-        let end_seq_num = msg.fv(fix44::END_SEQ_NO).unwrap();
-        app.on_resend_request(begin_seq_num..end_seq_num).ok();
+        let begin_seq_no = msg.fv::<u64, _>(fix44::BEGIN_SEQ_NO).unwrap_or(1);
+        let end_seq_no = msg.fv::<u64, _>(fix44::END_SEQ_NO).unwrap_or(0);
+        let last_sent = self.msg_seq_num_outbound.0;
+        // `EndSeqNo <16>` is 0 when all messages are requested.
+        let end_seq_no = if end_seq_no == 0 || end_seq_no > last_sent {
+            last_sent
+        } else {
+            end_seq_no
+        };
+        app.on_resend_request(begin_seq_no..end_seq_no + 1).ok();
+        if begin_seq_no == 0 || begin_seq_no > end_seq_no {
+            return Response::None;
+        }
+        Response::Resend {
+            messages: self.replay(begin_seq_no, end_seq_no),
+        }
     }
 
-    fn on_logout(&mut self, _msg: &Message<&[u8]>) -> &[u8] {
-        let fix_message = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"5");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
-            msg.set(fix44::TEXT, "Logout");
-            msg.wrap()
-        };
+    /// Returns the stored outbound messages from `begin_seq_no` to
+    /// `end_seq_no` (both included), ready to be sent again. Gaps are filled
+    /// with `SequenceReset-GapFill <4>` messages.
+    fn replay(&mut self, begin_seq_no: u64, end_seq_no: u64) -> Vec<Vec<u8>> {
+        let stored = self
+            .store()
+            .get(begin_seq_no..end_seq_no + 1)
+            .unwrap_or_default();
+        let now = self.now;
+        let mut messages = vec![];
+        let mut gap_fill_start = None;
+        let mut next_seq_num = begin_seq_no;
+        for (msg_seq_num, original) in stored {
+            if msg_seq_num > next_seq_num && gap_fill_start.is_none() {
+                gap_fill_start = Some(next_seq_num);
+            }
+            next_seq_num = msg_seq_num + 1;
+            let replayed = match self.store_decoder.as_mut() {
+                Some(decoder) => match decoder.decode(original.as_slice()) {
+                    Ok(original) => {
+                        let msg_type = original.fv_raw(fix44::MSG_TYPE).unwrap_or(b"");
+                        if is_admin_msg_type(msg_type) {
+                            None
+                        } else {
+                            let begin_string = self.begin_string.as_bytes();
+                            let mut msg = self.encoder.start_message(
+                                begin_string,
+                                &mut self.buffer,
+                                msg_type,
+                            );
+                            msg.set(fix44::POSS_DUP_FLAG, true);
+                            msg.set(fix44::SENDING_TIME, now);
+                            if let Some(orig_sending_time) = original.fv_raw(fix44::SENDING_TIME) {
+                                msg.set(fix44::ORIG_SENDING_TIME, orig_sending_time);
+                            }
+                            for (tag, value) in original.fields() {
+                                match tag.get() {
+                                    8 | 9 | 10 | 35 | 43 | 52 | 122 => {}
+                                    _ => msg.set_any(tag, value),
+                                }
+                            }
+                            Some(msg.wrap().to_vec())
+                        }
+                    }
+                    Err(_) => None,
+                },
+                None => None,
+            };
+            match replayed {
+                Some(replayed) => {
+                    if let Some(start) = gap_fill_start.take() {
+                        messages.push(self.make_gap_fill(start, msg_seq_num));
+                    }
+                    messages.push(replayed);
+                }
+                None => {
+                    gap_fill_start = gap_fill_start.or(Some(msg_seq_num));
+                }
+            }
+        }
+        if next_seq_num <= end_seq_no && gap_fill_start.is_none() {
+            gap_fill_start = Some(next_seq_num);
+        }
+        if let Some(start) = gap_fill_start {
+            messages.push(self.make_gap_fill(start, end_seq_no + 1));
+        }
+        messages
+    }
+
+    fn make_gap_fill(&mut self, msg_seq_num: u64, new_seq_no: u64) -> Vec<u8> {
+        let begin_string = self.begin_string.as_bytes();
+        let mut msg = self
+            .encoder
+            .start_message(begin_string, &mut self.buffer, b"4");
+        Self::add_comp_id(&mut msg, &self.sender_comp_id, &self.target_comp_id);
+        // Gap fills take the place of the messages they replace, so they
+        // don't consume a new seq. number and aren't stored.
+        msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+        msg.set(fix44::POSS_DUP_FLAG, true);
+        msg.set(fix44::SENDING_TIME, self.now);
+        msg.set(fix44::GAP_FILL_FLAG, true);
+        msg.set(fix44::NEW_SEQ_NO, new_seq_no);
+        msg.wrap().to_vec()
+    }
+
+    /// Builds a `Logon <A>`. `next_expected` is only used if
+    /// [`Configure::enable_next_expected_msg_seq_num`] is set.
+    fn build_logon<A>(
+        &mut self,
+        app: &mut A,
+        reset_seq_num: bool,
+        next_expected: u64,
+        session_status: Option<SessionStatus>,
+    ) -> &[u8]
+    where
+        A: Backend,
+    {
+        let heartbeat = self.heartbeat.as_secs();
+        let default_appl_ver_id = self.appl_versions.default_appl_ver_id();
+        let next_expected = if self.config.enable_next_expected_msg_seq_num() {
+            Some(next_expected)
+        } else {
+            None
+        };
+        self.build_message(b"A", |msg| {
+            msg.set(fix44::ENCRYPT_METHOD, fix44::EncryptMethod::None);
+            msg.set(fix44::HEART_BT_INT, heartbeat);
+            if reset_seq_num {
+                msg.set(fix44::RESET_SEQ_NUM_FLAG, true);
+            }
+            if let Some(next_expected) = next_expected {
+                msg.set(fix44::NEXT_EXPECTED_MSG_SEQ_NUM, next_expected);
+            }
+            if let Some(appl_ver_id) = default_appl_ver_id {
+                msg.set(DEFAULT_APPL_VER_ID, appl_ver_id.value());
+            }
+            if let Some(session_status) = session_status {
+                msg.set(SESSION_STATUS, session_status.value());
+            }
+            app.on_outbound_logon(msg).ok();
+        })
+    }
+
+    /// Encodes a new outbound message of type `msg_type`, complete with
+    /// standard header, and persists it to the message store. `body` is
+    /// responsible for adding all remaining fields.
+    fn build_message<F>(&mut self, msg_type: &[u8], body: F) -> &[u8]
+    where
+        F: FnOnce(&mut EncoderHandle<Vec<u8>>),
+    {
+        let msg_seq_num = self.msg_seq_num_outbound.next();
+        let seq_numbers = self.current_seq_numbers();
+        let now = self.now;
+        if msg_type == b"5" {
+            self.logout_sent = true;
+        }
+        let fix_message = {
+            let begin_string = self.begin_string.as_bytes();
+            let mut msg = self
+                .encoder
+                .start_message(begin_string, &mut self.buffer, msg_type);
+            Self::add_comp_id(&mut msg, &self.sender_comp_id, &self.target_comp_id);
+            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+            msg.set(fix44::SENDING_TIME, now);
+            body(&mut msg);
+            msg.wrap()
+        };
+        // There's not much we can do about storage failures: the message
+        // would simply be gap-filled upon resend.
+        let mut store = self.store.lock().unwrap();
+        store.set(msg_seq_num, fix_message).ok();
+        store.set_seq_numbers(seq_numbers).ok();
         fix_message
     }
 
+    /// Resets the message store and seq. numbers if the store belongs to a
+    /// previous session of the [`Schedule`].
+    fn reset_if_new_session(&mut self) {
+        let schedule = match &self.schedule {
+            Some(schedule) => schedule,
+            None => return,
+        };
+        if !schedule.is_same_session(self.store().creation_time(), self.now) {
+            self.reset_seq_numbers();
+        }
+    }
+
+    /// Resets the message store, so that both seq. numbers start again from 1.
+    fn reset_seq_numbers(&mut self) {
+        self.store().reset().ok();
+        self.msg_seq_num_inbound = MsgSeqNumCounter::START;
+        self.msg_seq_num_outbound = MsgSeqNumCounter::START;
+    }
+
+    fn current_seq_numbers(&self) -> SeqNumbers {
+        SeqNumbers {
+            next_inbound: self.msg_seq_num_inbound.expected(),
+            next_outbound: self.msg_seq_num_outbound.expected(),
+        }
+    }
+
+    fn persist_seq_numbers(&mut self) {
+        let seq_numbers = self.current_seq_numbers();
+        self.store().set_seq_numbers(seq_numbers).ok();
+    }
+
+    /// Replies to the counterparty's `Logout <5>`, unless it's itself a reply
+    /// to ours. Either way, the session is over. See specs. §4.6.
+    fn on_logout(&mut self, _msg: &Message<&[u8]>) -> Response {
+        self.logout_received = true;
+        if self.logout_sent {
+            Response::TerminateTransport
+        } else {
+            Response::OutboundBytes(self.build_message(b"5", |msg| {
+                msg.set(fix44::TEXT, "Logout");
+            }))
+        }
+    }
+
+    /// Returns `true` once `Logout <5>` messages have been exchanged in both
+    /// directions.
+    fn is_logged_out(&self) -> bool {
+        self.logout_sent && self.logout_received
+    }
+
+    /// Checks that `SendingTime <52>` is within
+    /// [`Configure::max_allowed_latency`] from the current time, in either
+    /// direction. See specs. §4.5.1.
     fn sending_time_is_ok(&self, msg: &Message<&[u8]>) -> bool {
-        let sending_time = msg.fv::<&str, _>(fix44::SENDING_TIME);
-        if let Ok(_sending_time) = sending_time {
-            // TODO
-            true
+        let sending_time = msg
+            .fv::<Timestamp, _>(fix44::SENDING_TIME)
+            .ok()
+            .and_then(|timestamp| timestamp.to_chrono_utc());
+        let sending_time = match sending_time {
+            Some(sending_time) => sending_time,
+            None => return false,
+        };
+        let now = self.now;
+        let latency = if now > sending_time {
+            now - sending_time
         } else {
-            false
+            sending_time - now
+        };
+        match latency.to_std() {
+            Ok(latency) => latency <= self.config.max_allowed_latency(),
+            Err(_) => false,
+        }
+    }
+
+    /// Checks that `OrigSendingTime <122>`, if present on a possible
+    /// duplicate, doesn't come after `SendingTime <52>`. See specs. §4.5.2.
+    fn orig_sending_time_is_ok(&self, msg: &Message<&[u8]>) -> bool {
+        if msg.fv::<bool, _>(fix44::POSS_DUP_FLAG) != Ok(true) {
+            return true;
+        }
+        let timestamp = |field| {
+            msg.fv::<Timestamp, _>(field)
+                .ok()
+                .and_then(|timestamp| timestamp.to_chrono_utc())
+        };
+        match (
+            timestamp(fix44::ORIG_SENDING_TIME),
+            timestamp(fix44::SENDING_TIME),
+        ) {
+            (Some(orig_sending_time), Some(sending_time)) => orig_sending_time <= sending_time,
+            _ => true,
         }
     }
 
@@ -428,45 +1630,43 @@ impl FixConnection {
     //
     //    #[must_use]
     pub fn on_heartbeat_is_due(&mut self) -> &[u8] {
-        let fix_message = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"0");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
-            msg.set(fix44::SENDING_TIME, chrono::Utc::now());
-            msg.wrap()
-        };
-        fix_message
+        self.build_message(b"0", |_msg| {})
     }
 
-    pub fn on_heartbeat(&mut self, _msg: Message<&[u8]>) {
-        // TODO: verify stuff.
+    /// Stamps the standard header onto `message`, then persists it. Returns the
+    /// assigned `MsgSeqNum <34>` and the encoded message.
+    fn on_outbound_app_message(&mut self, message: &OutboundMessage) -> (u64, &[u8]) {
+        let msg_seq_num = self.msg_seq_num_outbound.expected();
+        let bytes = self.build_message(message.msg_type(), |msg| msg.raw(message.body()));
+        (msg_seq_num, bytes)
     }
 
+    /// Builds a `TestRequest <1>` with a new, unique `TestReqID <112>`, to
+    /// check whether the silent counterparty is still alive.
+    pub fn on_test_request_is_due(&mut self) -> &[u8] {
+        let test_req_id = Uuid::new_v4().to_string();
+        self.pending_test_req_id = Some(test_req_id.clone());
+        self.build_message(b"1", |msg| {
+            msg.set(fix44::TEST_REQ_ID, test_req_id.as_str());
+        })
+    }
+
+    pub fn on_heartbeat(&mut self, msg: Message<&[u8]>) {
+        let test_req_id = msg.fv::<&str, _>(fix44::TEST_REQ_ID).ok();
+        if test_req_id.is_some() && test_req_id == self.pending_test_req_id.as_deref() {
+            self.pending_test_req_id = None;
+        }
+    }
+
+    /// Replies to a `TestRequest <1>` with a `Heartbeat <0>` carrying the same
+    /// `TestReqID <112>`. See specs. §4.7.3.
     fn on_test_request(&mut self, msg: Message<&[u8]>) -> &[u8] {
-        let test_req_id = msg.fv::<&[u8], _>(fix44::TEST_REQ_ID).unwrap();
-        let fix_message = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"1");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
-            msg.set(fix44::SENDING_TIME, chrono::Utc::now());
-            msg.set(fix44::TEST_REQ_ID, test_req_id);
-            msg.wrap()
-        };
-        fix_message
+        let test_req_id = msg.fv::<&[u8], _>(fix44::TEST_REQ_ID).ok();
+        self.build_message(b"0", |msg| {
+            if let Some(test_req_id) = test_req_id {
+                msg.set(fix44::TEST_REQ_ID, test_req_id);
+            }
+        })
     }
 
     fn on_wrong_environment(&mut self, _message: Message<&[u8]>) -> Response {
@@ -500,29 +1700,25 @@ impl FixConnection {
         ))
     }
 
-    fn on_low_seqnum(&mut self, _message: Message<&[u8]>) -> Response {
+    fn on_low_seqnum(&mut self, message: Message<&[u8]>) -> Response {
+        // Messages resent by the counterparty which we've already processed
+        // must be ignored. See specs. §4.8.
+        if let Ok(true) = message.fv::<bool, _>(fix44::POSS_DUP_FLAG) {
+            return Response::None;
+        }
         self.make_logout(errs::msg_seq_num(self.msg_seq_num_inbound.0 + 1))
     }
 
     fn on_reject(
         &mut self,
-        _ref_seq_num: u64,
+        ref_seq_num: u64,
         ref_tag: Option<u32>,
         ref_msg_type: Option<&[u8]>,
         reason: u32,
         err_text: String,
-    ) -> Response {
-        let fix_message = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"3");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+    ) -> &[u8] {
+        self.build_message(b"3", |msg| {
+            msg.set(fix44::REF_SEQ_NUM, ref_seq_num);
             if let Some(ref_tag) = ref_tag {
                 msg.set(fix44::REF_TAG_ID, ref_tag);
             }
@@ -531,58 +1727,309 @@ impl FixConnection {
             }
             msg.set(fix44::SESSION_REJECT_REASON, reason);
             msg.set(fix44::TEXT, err_text.as_str());
-            msg.wrap()
-        };
-        Response::OutboundBytes(fix_message)
+        })
     }
 
+    /// Rejects a message with an inaccurate `SendingTime <52>` and then logs
+    /// out, as required by specs. §4.5.1.
     fn make_reject_for_inaccurate_sending_time(&mut self, offender: Message<&[u8]>) -> Response {
         let ref_seq_num = offender.fv(fix44::MSG_SEQ_NUM).unwrap();
         let ref_msg_type = offender.fv::<&str, _>(fix44::MSG_TYPE).unwrap();
-        self.on_reject(
+        let reject = self
+            .on_reject(
+                ref_seq_num,
+                Some(fix44::SENDING_TIME.tag().get().into()),
+                Some(ref_msg_type.as_bytes()),
+                fix44::SessionRejectReason::SendingtimeAccuracyProblem as u32,
+                errs::sending_time_accuracy(),
+            )
+            .to_vec();
+        let logout = self
+            .build_message(b"5", |msg| {
+                msg.set(fix44::TEXT, errs::sending_time_accuracy().as_str());
+            })
+            .to_vec();
+        Response::OutboundMessages(vec![reject, logout])
+    }
+
+    fn make_reject_for_inaccurate_orig_sending_time(
+        &mut self,
+        offender: Message<&[u8]>,
+    ) -> Response {
+        let ref_seq_num = offender.fv(fix44::MSG_SEQ_NUM).unwrap();
+        let ref_msg_type = offender.fv::<&str, _>(fix44::MSG_TYPE).unwrap();
+        let reject = self.on_reject(
             ref_seq_num,
-            Some(fix44::SENDING_TIME.tag().get().into()),
+            Some(fix44::ORIG_SENDING_TIME.tag().get().into()),
             Some(ref_msg_type.as_bytes()),
             fix44::SessionRejectReason::SendingtimeAccuracyProblem as u32,
-            "Bad SendingTime".to_string(),
-        )
+            errs::orig_sending_time(),
+        );
+        Response::OutboundBytes(reject)
+    }
+
+    /// Rejects a message which doesn't comply with the dictionary. See specs.
+    /// §4.10.
+    fn make_reject_for_violation(
+        &mut self,
+        offender: Message<&[u8]>,
+        violation: Violation,
+    ) -> Response {
+        let ref_seq_num = offender.fv(fix44::MSG_SEQ_NUM).unwrap();
+        let ref_msg_type = offender.fv::<&[u8], _>(fix44::MSG_TYPE).ok();
+        let reject = self.on_reject(
+            ref_seq_num,
+            violation.ref_tag,
+            ref_msg_type,
+            violation.reason,
+            violation.text,
+        );
+        Response::OutboundBytes(reject)
+    }
+
+    /// Refuses an application message with a `BusinessMessageReject <j>`. See
+    /// specs. §4.11.
+    fn make_business_reject(
+        &mut self,
+        offender: Message<&[u8]>,
+        reject: BusinessReject,
+    ) -> Response {
+        let ref_seq_num = offender.fv::<u64, _>(fix44::MSG_SEQ_NUM).unwrap();
+        let ref_msg_type = offender.fv::<&[u8], _>(fix44::MSG_TYPE).unwrap();
+        let fix_message = self.build_message(b"j", |msg| {
+            msg.set(fix44::REF_SEQ_NUM, ref_seq_num);
+            msg.set(fix44::REF_MSG_TYPE, ref_msg_type);
+            if let Some(ref_id) = reject.ref_id() {
+                msg.set(fix44::BUSINESS_REJECT_REF_ID, ref_id);
+            }
+            msg.set(fix44::BUSINESS_REJECT_REASON, reject.reason());
+            if let Some(text) = reject.text() {
+                msg.set(fix44::TEXT, text);
+            }
+        });
+        Response::OutboundBytes(fix_message)
     }
 
     fn make_logout(&mut self, text: String) -> Response {
-        let fix_message = {
-            let begin_string = self.begin_string.as_bytes();
-            let sender_comp_id = self.sender_comp_id.as_str();
-            let target_comp_id = self.target_comp_id.as_str();
-            let msg_seq_num = self.msg_seq_num_outbound.next();
-            let mut msg = self
-                .encoder
-                .start_message(begin_string, &mut self.buffer, b"5");
-            msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
-            msg.set(fix44::TARGET_COMP_ID, target_comp_id);
-            msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+        let fix_message = self.build_message(b"5", |msg| {
             msg.set(fix44::TEXT, text.as_str());
-            msg.wrap()
-        };
+        });
+        Response::OutboundBytes(fix_message)
+    }
+
+    /// Builds the `Logout <5>` which refuses a `Logon <A>` that failed
+    /// authentication.
+    fn make_authentication_logout(&mut self, authentication: &Authentication) -> Response {
+        let fix_message = self.build_message(b"5", |msg| {
+            if let Some(text) = authentication.text() {
+                msg.set(fix44::TEXT, text);
+            }
+            if let Some(session_status) = authentication.session_status() {
+                msg.set(SESSION_STATUS, session_status.value());
+            }
+        });
         Response::OutboundBytes(fix_message)
     }
 
     fn make_resend_request(&mut self, start: u64, end: u64) -> Response {
-        let begin_string = self.begin_string.as_bytes();
-        let mut msg = self
-            .encoder
-            .start_message(begin_string, &mut self.buffer, b"2");
-        //Self::add_comp_id(msg);
-        //self.add_sending_time(msg);
-        //self.add_seqnum(msg);
-        msg.set(fix44::BEGIN_SEQ_NO, start);
-        msg.set(fix44::END_SEQ_NO, end);
-        Response::OutboundBytes(msg.wrap())
+        let fix_message = self.build_message(b"2", |msg| {
+            msg.set(fix44::BEGIN_SEQ_NO, start);
+            msg.set(fix44::END_SEQ_NO, end);
+        });
+        Response::OutboundBytes(fix_message)
     }
 
     fn on_high_seqnum(&mut self, msg: Message<&[u8]>) -> Response {
         let msg_seq_num = msg.fv(fix44::MSG_SEQ_NUM).unwrap();
-        self.make_resend_request(self.seq_numbers().next_inbound(), msg_seq_num);
-        todo!()
+        // We can't process this message until the gap is filled, so we
+        // hold on to it. Refer to specs. §4.8.
+        self.inbound_queue
+            .insert(msg_seq_num, msg.as_bytes().to_vec());
+        if self.resend_request_end.is_some() {
+            // Recovery is already underway. If it doesn't cover this gap,
+            // `resume_gap_recovery` will take care of it later.
+            return Response::None;
+        }
+        let start = self.msg_seq_num_inbound.expected();
+        self.resend_request_end = Some(msg_seq_num - 1);
+        self.make_resend_request(start, msg_seq_num - 1)
+    }
+
+    /// Removes and returns the queued message which comes next in the inbound
+    /// sequence, if any. Queued messages made obsolete by a
+    /// `SequenceReset-GapFill <4>` are discarded.
+    fn pop_queued_message(&mut self) -> Option<Vec<u8>> {
+        let expected = self.msg_seq_num_inbound.expected();
+        while let Some(msg_seq_num) = self.inbound_queue.keys().next().copied() {
+            if msg_seq_num >= expected {
+                break;
+            }
+            self.inbound_queue.remove(&msg_seq_num);
+        }
+        self.inbound_queue.remove(&expected)
+    }
+
+    /// Terminates gap recovery once all requested messages have been received.
+    /// If queued messages are still preceded by a gap, a new
+    /// `ResendRequest <2>` is issued.
+    fn resume_gap_recovery(&mut self) -> Response {
+        let expected = self.msg_seq_num_inbound.expected();
+        match self.resend_request_end {
+            Some(end) if end >= expected => return Response::None,
+            _ => self.resend_request_end = None,
+        }
+        let first_queued = self.inbound_queue.keys().next().copied();
+        match first_queued {
+            Some(msg_seq_num) if msg_seq_num > expected => {
+                self.resend_request_end = Some(msg_seq_num - 1);
+                self.make_resend_request(expected, msg_seq_num - 1)
+            }
+            _ => Response::None,
+        }
+    }
+
+    /// Moves the inbound seq. number forward to `NewSeqNo <36>`, in both
+    /// modes of `SequenceReset <4>`. Attempts to decrease it are refused with
+    /// a `Reject <3>`. See specs. §4.8.
+    fn on_sequence_reset(&mut self, msg: Message<&[u8]>) -> Response {
+        let new_seq_no = match msg.fv::<u64, _>(fix44::NEW_SEQ_NO) {
+            Ok(new_seq_no) => new_seq_no,
+            Err(_) => {
+                let tag = fix44::NEW_SEQ_NO.tag().get().into();
+                let violation = if msg.fv_raw(fix44::NEW_SEQ_NO).is_none() {
+                    Violation {
+                        reason: fix44::SessionRejectReason::RequiredTagMissing as u32,
+                        ref_tag: Some(tag),
+                        text: errs::missing_field(fix44::NEW_SEQ_NO.name(), tag),
+                    }
+                } else {
+                    Violation {
+                        reason: fix44::SessionRejectReason::IncorrectDataFormatForValue as u32,
+                        ref_tag: Some(tag),
+                        text: errs::incorrect_data_format(fix44::NEW_SEQ_NO.name(), tag),
+                    }
+                };
+                return self.make_reject_for_violation(msg, violation);
+            }
+        };
+        // With `GapFillFlag <123>`, the seq. number of `msg` itself has
+        // been accounted for already, so `NewSeqNo <36>` must be greater.
+        let expected = self.msg_seq_num_inbound.expected();
+        match new_seq_no.cmp(&expected) {
+            Ordering::Less => {
+                let tag = fix44::NEW_SEQ_NO.tag().get().into();
+                let violation = Violation {
+                    reason: fix44::SessionRejectReason::ValueIsIncorrect as u32,
+                    ref_tag: Some(tag),
+                    text: errs::new_seq_no_too_low(new_seq_no, expected),
+                };
+                self.make_reject_for_violation(msg, violation)
+            }
+            Ordering::Equal => {
+                if is_sequence_reset_reset(&msg) {
+                    self.log_event(&errs::new_seq_no_unchanged(new_seq_no));
+                }
+                Response::None
+            }
+            Ordering::Greater => {
+                // All messages up to `NewSeqNo <36>` (excluded) are skipped.
+                self.msg_seq_num_inbound = MsgSeqNumCounter(new_seq_no - 1);
+                self.persist_seq_numbers();
+                Response::None
+            }
+        }
+    }
+
+    /// Validates the counterparty's `Logon <A>` and returns the heartbeat
+    /// interval to use. Acceptors check `HeartBtInt <108>` against their
+    /// [`HeartbeatRule`], while initiators expect it to be unchanged. On
+    /// failure, the text of the `Logout <5>` to send is returned instead.
+    fn validate_logon(&self, logon: &Message<&[u8]>) -> Result<Duration, String> {
+        if let Some(schedule) = &self.schedule {
+            if !schedule.is_active(self.now) {
+                return Err(errs::outside_schedule());
+            }
+        }
+        // The counterparty's `SenderCompID <49>` is our `TargetCompID <56>`,
+        // and vice versa.
+        if logon.fv::<&str, _>(fix44::SENDER_COMP_ID) != Ok(self.target_comp_id()) {
+            return Err(errs::comp_id(
+                fix44::SENDER_COMP_ID.name(),
+                fix44::SENDER_COMP_ID.tag().get().into(),
+            ));
+        }
+        if logon.fv::<&str, _>(fix44::TARGET_COMP_ID) != Ok(self.sender_comp_id()) {
+            return Err(errs::comp_id(
+                fix44::TARGET_COMP_ID.name(),
+                fix44::TARGET_COMP_ID.tag().get().into(),
+            ));
+        }
+        let heartbeat = match logon.fv::<u64, _>(fix44::HEART_BT_INT) {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                return Err(errs::missing_field(
+                    fix44::HEART_BT_INT.name(),
+                    fix44::HEART_BT_INT.tag().get().into(),
+                ))
+            }
+        };
+        match self.role {
+            Role::Acceptor => self.heartbeat_rule.validate(&heartbeat)?,
+            Role::Initiator => {
+                if heartbeat != self.heartbeat {
+                    return Err(errs::heartbeat_exact(self.heartbeat.as_secs()));
+                }
+            }
+        }
+        Ok(heartbeat)
+    }
+
+    /// Honours `NextExpectedMsgSeqNum <789>` of the counterparty's
+    /// `Logon <A>`, if any, by replaying all messages after `last_sent` that
+    /// it hasn't received. A value beyond our last sent message is an error.
+    fn on_next_expected_msg_seq_num(
+        &mut self,
+        logon: &Message<&[u8]>,
+        last_sent: u64,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        let next_expected = match logon.fv::<u64, _>(fix44::NEXT_EXPECTED_MSG_SEQ_NUM) {
+            Ok(next_expected) => next_expected,
+            Err(_) => return Ok(vec![]),
+        };
+        if next_expected > last_sent + 1 {
+            Err(errs::inbound_seqnum())
+        } else if next_expected == 0 || next_expected == last_sent + 1 {
+            Ok(vec![])
+        } else {
+            Ok(self.replay(next_expected, last_sent))
+        }
+    }
+
+    /// Checks the `MsgSeqNum <34>` of the counterparty's `Logon <A>`. A seq.
+    /// number that is too low terminates the session with a `Logout <5>`,
+    /// which is returned as an error. A seq. number that is too high is
+    /// accepted, but triggers gap recovery. See specs. §4.8.
+    fn on_logon_seq_num<'a>(
+        &'a mut self,
+        logon: Message<&[u8]>,
+    ) -> Result<Response<'a>, Response<'a>> {
+        let msg_seq_num = match logon.fv::<u64, _>(fix44::MSG_SEQ_NUM) {
+            Ok(msg_seq_num) => msg_seq_num,
+            Err(_) => return Err(self.on_missing_seqnum(logon)),
+        };
+        match msg_seq_num.cmp(&self.msg_seq_num_inbound.expected()) {
+            Ordering::Equal => {
+                self.msg_seq_num_inbound.next();
+                self.persist_seq_numbers();
+                Ok(Response::None)
+            }
+            Ordering::Less => {
+                Err(self.make_logout(errs::msg_seq_num(self.msg_seq_num_inbound.expected())))
+            }
+            // The `Logon <A>` itself is queued and processed again (as a
+            // no-op) once the gap is filled.
+            Ordering::Greater => Ok(self.on_high_seqnum(logon)),
+        }
     }
 
     fn on_logon(&mut self, _logon: Message<&[u8]>) {
@@ -600,6 +2047,144 @@ impl FixConnection {
     }
 }
 
+/// Hands an application message over to `app`. Returns the
+/// [`BusinessReject`] to send back, if any.
+fn deliver<B>(app: &mut B, msg: Message<&[u8]>) -> Option<BusinessReject>
+where
+    B: Backend,
+{
+    match app.on_inbound_app_message(msg) {
+        Ok(()) => None,
+        Err(err) => app.business_reject(&err),
+    }
+}
+
+fn is_admin_msg_type(msg_type: &[u8]) -> bool {
+    matches!(msg_type, b"0" | b"1" | b"2" | b"3" | b"4" | b"5" | b"A")
+}
+
+/// Returns `true` if `msg` is a resent message (i.e. with `PossDupFlag <43>`)
+/// without `OrigSendingTime <122>`. Like QuickFIX, we tolerate this for
+/// `SequenceReset <4>`. See specs. §4.5.1.
+fn is_missing_orig_sending_time(msg: &Message<&[u8]>) -> bool {
+    msg.fv::<bool, _>(fix44::POSS_DUP_FLAG) == Ok(true)
+        && msg.fv_raw(fix44::ORIG_SENDING_TIME).is_none()
+        && msg.fv_raw(fix44::MSG_TYPE) != Some(&b"4"[..])
+}
+
+/// Returns `true` if `msg` is a `SequenceReset <4>` in Reset mode, i.e.
+/// without `GapFillFlag <123>`.
+fn is_sequence_reset_reset(msg: &Message<&[u8]>) -> bool {
+    msg.fv_raw(fix44::MSG_TYPE) == Some(&b"4"[..])
+        && !msg.fv::<bool, _>(fix44::GAP_FILL_FLAG).unwrap_or(false)
+}
+
+/// Returns the `MsgType <35>` of an encoded message, or an empty slice.
+fn raw_msg_type(message: &[u8]) -> &[u8] {
+    message
+        .split(|byte| *byte == 0x1)
+        .find_map(|field| field.strip_prefix(b"35="))
+        .unwrap_or(b"")
+}
+
+/// Reads the next message from `input`. Returns `None` if the transport is
+/// closed or it carries garbled data.
+pub(crate) async fn read_message<'a, I>(
+    input: &mut I,
+    decoder: &'a mut DecoderBuffered,
+) -> Option<Message<'a, &'a [u8]>>
+where
+    I: AsyncRead + Unpin,
+{
+    loop {
+        let buffer = decoder.supply_buffer();
+        input.read_exact(buffer).await.ok()?;
+        match decoder.state() {
+            Ok(Some(())) => return Some(decoder.message()),
+            Ok(None) => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Turns `response` into [`Action::Write`]s, notifying `app` of each
+/// outbound message.
+fn push_response<A>(response: Response, app: &mut A, actions: &mut Vec<Action>)
+where
+    A: Backend,
+{
+    match response {
+        Response::OutboundBytes(bytes) => {
+            app.on_outbound_message(bytes).ok();
+            actions.push(Action::Write(bytes.to_vec()));
+        }
+        Response::OutboundMessages(messages) | Response::Resend { messages } => {
+            for message in messages {
+                app.on_outbound_message(&message).ok();
+                actions.push(Action::Write(message));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the length of the message at the start of `bytes`, or `None` if
+/// more bytes are needed to tell. Fails if `bytes` doesn't start with
+/// `BeginString <8>` and `BodyLength <9>`.
+fn frame_len(bytes: &[u8], separator: u8) -> Result<Option<usize>, ()> {
+    // Both fields are short, so garbled data is detected early on.
+    const MAX_PREFIX_LEN: usize = 64;
+    let n = bytes.len().min(2);
+    if bytes[..n] != b"8="[..n] {
+        return Err(());
+    }
+    let prefix = &bytes[..bytes.len().min(MAX_PREFIX_LEN)];
+    let mut separators = prefix
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == separator)
+        .map(|(i, _)| i);
+    let (begin_string_end, body_length_end) = match (separators.next(), separators.next()) {
+        (Some(i), Some(j)) => (i, j),
+        _ if prefix.len() < MAX_PREFIX_LEN => return Ok(None),
+        _ => return Err(()),
+    };
+    if !bytes[begin_string_end + 1..].starts_with(b"9=") {
+        return Err(());
+    }
+    let body_length =
+        u64::deserialize(&bytes[begin_string_end + 3..body_length_end]).map_err(|_| ())? as usize;
+    // The body is followed by `CheckSum <10>`, i.e. "10=NNN" plus separator.
+    let len = body_length_end + 1 + body_length + 7;
+    Ok(if bytes.len() >= len { Some(len) } else { None })
+}
+
+/// Returns `time + duration`, or `None` on overflow.
+pub(crate) fn add_duration(time: DateTime<Utc>, duration: Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| time.checked_add_signed(duration))
+}
+
+/// Returns when a counterparty last heard from at `last_received` is due a
+/// `TestRequest <1>`, i.e. after 1.2 times `HeartBtInt <108>` of silence, so
+/// that a reasonable transmission delay is tolerated whatever the interval.
+pub(crate) fn test_request_deadline(
+    last_received: DateTime<Utc>,
+    heartbeat: Duration,
+) -> Option<DateTime<Utc>> {
+    add_duration(last_received, heartbeat * 6 / 5)
+}
+
+/// Returns when a counterparty last heard from at `last_received` is given
+/// up on, i.e. one more `HeartBtInt <108>` after its `TestRequest <1>`.
+pub(crate) fn logout_deadline(
+    last_received: DateTime<Utc>,
+    heartbeat: Duration,
+) -> Option<DateTime<Utc>> {
+    add_duration(last_received, heartbeat * 6 / 5 + heartbeat)
+}
+
 //fn add_time_to_msg(mut msg: EncoderHandle) {
 //    // https://www.onixs.biz/fix-dictionary/4.4/index.html#UTCTimestamp.
 //    let time = chrono::Utc::now();
@@ -607,68 +2192,1536 @@ impl FixConnection {
 //    msg.set(fix44::SENDING_TIME, timestamp.to_string().as_str());
 //}
 
-//#[cfg(test)]
-//mod test {
-//    use super::*;
-//    use std::time::Duration;
-//
-//    fn conn() -> FixConnection {
-//        let builder = FixConnectionBuilder {
-//            environment: Environment::ProductionDisallowTest,
-//            heartbeat: Duration::from_secs(30),
-//            seq_numbers: SeqNumbers::default(),
-//            sender_comp_id: "SENDER".to_string(),
-//            target_comp_id: "TARGET".to_string(),
-//        };
-//        builder.build()
-//    }
-//
-//    #[test]
-//    fn on_heartbeat_is_due() {
-//        let conn = &mut conn();
-//        let responses = &mut conn.on_heartbeat_is_due();
-//        let next = responses.next().unwrap();
-//        let msg = next.as_outbound().unwrap();
-//        assert_eq!(msg.field_str(tags::MSG_TYPE), Some("0"));
-//        assert_eq!(msg.field_str(tags::SENDER_COMP_ID), Some("SENDER"));
-//        assert_eq!(msg.field_str(tags::TARGET_COMP_ID), Some("TARGET"));
-//        assert_eq!(msg.field_bool(tags::POSS_DUP_FLAG), None);
-//        assert_eq!(msg.field_i64(tags::TEST_REQ_ID), None);
-//        assert!(responses.next().is_none());
-//    }
-//
-//    #[test]
-//    fn terminate_transport_when_error() {
-//        let conn = &mut conn();
-//        let responses = &mut conn.on_transport_error();
-//        let next = responses.next().unwrap();
-//        assert!(next.as_terminate_transport().is_some());
-//    }
-//
-//    #[test]
-//    fn inaccurate_sending_time() {
-//        let conn = &mut conn();
-//        let mut msg = FixMessage::new();
-//        msg.add_str(tags::MSG_TYPE, "BE");
-//        msg.add_str(tags::SENDER_COMP_ID, "SENDER");
-//        msg.add_str(tags::TARGET_COMP_ID, "TARGET");
-//        msg.add_i64(tags::MSG_SEQ_NUM, 1);
-//        msg.add_str(
-//            tags::USER_REQUEST_ID,
-//            "47b6f4a6-993d-4430-b68f-d9b680a1a772",
-//        );
-//        msg.add_i64(tags::USER_REQUEST_TYPE, 1);
-//        msg.add_str(tags::USERNAME, "john-doe");
-//        let mut responses = conn.on_inbound_message(msg);
-//        let next = responses.next().unwrap();
-//        let msg = next.as_outbound().unwrap();
-//        assert_eq!(msg.field_str(tags::MSG_TYPE), Some("3"));
-//        assert_eq!(msg.field_str(tags::SENDER_COMP_ID), Some("SENDER"));
-//        assert_eq!(msg.field_str(tags::TARGET_COMP_ID), Some("TARGET"));
-//        assert_eq!(msg.field_bool(tags::POSS_DUP_FLAG), None);
-//        assert_eq!(msg.field_i64(tags::TEST_REQ_ID), None);
-//        assert_eq!(msg.field_i64(tags::SESSION_REJECT_REASON), Some(10));
-//        assert_eq!(msg.field_i64(tags::REF_SEQ_NUM), Some(10));
-//        assert!(responses.next().is_none());
-//    }
-//}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::HardCodedFixFieldDefinition;
+    use crate::session::{FileStore, MockClock, RateLimit};
+    use crate::Dictionary;
+    use chrono::TimeZone;
+    use std::ops::Range;
+
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        app_msg_seq_nums: Vec<u64>,
+        username: Option<&'static str>,
+        peer_addr: Option<SocketAddr>,
+    }
+
+    impl Backend for Recorder {
+        type Error = BusinessReject;
+
+        /// Messages with `ClOrdID <11>` "unknown" are rejected.
+        fn on_inbound_app_message(&mut self, message: Message<&[u8]>) -> Result<(), Self::Error> {
+            let msg_seq_num = message.fv(fix44::MSG_SEQ_NUM).unwrap();
+            self.app_msg_seq_nums.push(msg_seq_num);
+            if message.fv::<&str, _>(fix44::CL_ORD_ID) == Ok("unknown") {
+                let mut reject = BusinessReject::new(fix44::BusinessRejectReason::UnkownId);
+                reject.set_ref_id("unknown");
+                return Err(reject);
+            }
+            Ok(())
+        }
+
+        fn supports_msg_type(&self, msg_type: &[u8]) -> bool {
+            msg_type != b"F"
+        }
+
+        fn business_reject(&self, error: &Self::Error) -> Option<BusinessReject> {
+            Some(error.clone())
+        }
+
+        fn on_outbound_message(&mut self, _message: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_resend_request(&mut self, _range: Range<u64>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_outbound_logon(
+            &mut self,
+            logon: &mut EncoderHandle<Vec<u8>>,
+        ) -> Result<(), Self::Error> {
+            if let Some(username) = self.username {
+                logon.set(fix44::USERNAME, username);
+            }
+            Ok(())
+        }
+
+        /// Logons with a `Password <554>` other than "secret" are refused.
+        fn authenticate(&mut self, logon: &LogonRequest) -> Authentication {
+            self.peer_addr = logon.peer_addr();
+            match (logon.password(), logon.new_password()) {
+                (Some(password), _) if password != "secret" => {
+                    let mut authentication = Authentication::reject("Invalid password");
+                    authentication.set_session_status(SessionStatus::InvalidUsernameOrPassword);
+                    authentication
+                }
+                (Some(_), Some(_)) => {
+                    let mut authentication = Authentication::accept();
+                    authentication.set_session_status(SessionStatus::SessionPasswordChanged);
+                    authentication
+                }
+                _ => Authentication::accept(),
+            }
+        }
+    }
+
+    fn conn() -> FixConnection {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.build()
+    }
+
+    fn decoder() -> Decoder {
+        Decoder::new(Dictionary::fix44())
+    }
+
+    fn counterparty_msg(
+        msg_type: &[u8],
+        msg_seq_num: u64,
+        fields: &[(&HardCodedFixFieldDefinition, &str)],
+    ) -> Vec<u8> {
+        let mut encoder = Encoder::<crate::tagvalue::Config>::default();
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(b"FIX.4.4", &mut buffer, msg_type);
+        msg.set(fix44::SENDER_COMP_ID, "TARGET");
+        msg.set(fix44::TARGET_COMP_ID, "SENDER");
+        msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+        msg.set(fix44::SENDING_TIME, chrono::Utc::now());
+        for (field, value) in fields {
+            msg.set(*field, *value);
+        }
+        msg.wrap().to_vec()
+    }
+
+    fn collect_outbound(response: Response, outbound: &mut Vec<Vec<u8>>) {
+        match response {
+            Response::OutboundBytes(bytes) => outbound.push(bytes.to_vec()),
+            Response::OutboundMessages(messages) | Response::Resend { messages } => {
+                outbound.extend(messages)
+            }
+            _ => {}
+        }
+    }
+
+    /// Feeds `bytes` to `conn` just like the event loop does, i.e. releasing
+    /// queued messages afterwards. Returns all outbound messages.
+    fn feed(conn: &mut FixConnection, backend: &mut Recorder, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut outbound = vec![];
+        let mut decoder = decoder();
+        let msg = decoder.decode(bytes).unwrap();
+        collect_outbound(conn.on_inbound_message(msg, backend), &mut outbound);
+        while let Some(queued) = conn.pop_queued_message() {
+            let msg = decoder.decode(queued.as_slice()).unwrap();
+            collect_outbound(conn.on_inbound_message(msg, backend), &mut outbound);
+        }
+        collect_outbound(conn.resume_gap_recovery(), &mut outbound);
+        outbound
+    }
+
+    #[test]
+    fn on_heartbeat_is_due() {
+        let conn = &mut conn();
+        let bytes = conn.on_heartbeat_is_due().to_vec();
+        let mut decoder = decoder();
+        let msg = decoder.decode(bytes.as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("0"));
+        assert_eq!(msg.fv::<&str, _>(fix44::SENDER_COMP_ID), Ok("SENDER"));
+        assert_eq!(msg.fv::<&str, _>(fix44::TARGET_COMP_ID), Ok("TARGET"));
+        assert!(msg.fv_raw(fix44::POSS_DUP_FLAG).is_none());
+        assert!(msg.fv_raw(fix44::TEST_REQ_ID).is_none());
+    }
+
+    #[test]
+    fn high_seqnum_triggers_resend_request() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 4, &[]));
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("2"));
+        assert_eq!(msg.fv::<u64, _>(fix44::BEGIN_SEQ_NO), Ok(1));
+        assert_eq!(msg.fv::<u64, _>(fix44::END_SEQ_NO), Ok(3));
+        assert!(backend.app_msg_seq_nums.is_empty());
+        // No new `ResendRequest <2>` while recovery is in progress.
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 5, &[]));
+        assert!(outbound.is_empty());
+    }
+
+    #[test]
+    fn queued_messages_are_released_in_order_after_resend() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 3, &[]));
+        feed(conn, backend, &counterparty_msg(b"D", 4, &[]));
+        let poss_dup = &[
+            (fix44::POSS_DUP_FLAG, "Y"),
+            (fix44::ORIG_SENDING_TIME, "20210101-00:00:00.000"),
+        ];
+        feed(conn, backend, &counterparty_msg(b"D", 1, poss_dup));
+        assert_eq!(backend.app_msg_seq_nums, vec![1]);
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 2, poss_dup));
+        assert!(outbound.is_empty());
+        assert_eq!(backend.app_msg_seq_nums, vec![1, 2, 3, 4]);
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 5);
+    }
+
+    #[test]
+    fn gap_fill_skips_missing_messages() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 4, &[]));
+        let gap_fill = &[
+            (fix44::POSS_DUP_FLAG, "Y"),
+            (fix44::GAP_FILL_FLAG, "Y"),
+            (fix44::NEW_SEQ_NO, "4"),
+        ];
+        feed(conn, backend, &counterparty_msg(b"4", 1, gap_fill));
+        assert_eq!(backend.app_msg_seq_nums, vec![4]);
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 5);
+    }
+
+    #[test]
+    fn sequence_reset_ignores_msg_seq_num_and_skips_queued_messages() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 3, &[]));
+        let reset = &[(fix44::NEW_SEQ_NO, "10")];
+        let outbound = feed(conn, backend, &counterparty_msg(b"4", 42, reset));
+        assert!(outbound.is_empty());
+        assert!(backend.app_msg_seq_nums.is_empty());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 10);
+        assert!(conn.inbound_queue.is_empty());
+        assert_eq!(conn.resend_request_end, None);
+    }
+
+    #[test]
+    fn sequence_reset_cannot_decrease_inbound_seq_num() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        feed(conn, backend, &counterparty_msg(b"D", 2, &[]));
+        let reset = &[(fix44::POSS_DUP_FLAG, "Y"), (fix44::NEW_SEQ_NO, "2")];
+        let outbound = feed(conn, backend, &counterparty_msg(b"4", 1, reset));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 3);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(msg.fv::<u64, _>(fix44::REF_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(36));
+        assert_eq!(
+            msg.fv::<fix44::SessionRejectReason, _>(fix44::SESSION_REJECT_REASON),
+            Ok(fix44::SessionRejectReason::ValueIsIncorrect)
+        );
+    }
+
+    #[test]
+    fn gap_fill_must_move_inbound_seq_num_forward() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        let gap_fill = &[(fix44::GAP_FILL_FLAG, "Y"), (fix44::NEW_SEQ_NO, "2")];
+        let outbound = feed(conn, backend, &counterparty_msg(b"4", 2, gap_fill));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 3);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(msg.fv::<u64, _>(fix44::REF_SEQ_NUM), Ok(2));
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(36));
+        assert_eq!(
+            msg.fv::<fix44::SessionRejectReason, _>(fix44::SESSION_REJECT_REASON),
+            Ok(fix44::SessionRejectReason::ValueIsIncorrect)
+        );
+        // The same gap fill, resent, is ignored.
+        let gap_fill = &[
+            (fix44::POSS_DUP_FLAG, "Y"),
+            (fix44::GAP_FILL_FLAG, "Y"),
+            (fix44::NEW_SEQ_NO, "3"),
+        ];
+        let outbound = feed(conn, backend, &counterparty_msg(b"4", 2, gap_fill));
+        assert!(outbound.is_empty());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 3);
+    }
+
+    #[test]
+    fn sequence_reset_without_new_seq_no_is_rejected() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let gap_fill = &[(fix44::GAP_FILL_FLAG, "Y")];
+        let outbound = feed(conn, backend, &counterparty_msg(b"4", 1, gap_fill));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(36));
+        assert_eq!(
+            msg.fv::<fix44::SessionRejectReason, _>(fix44::SESSION_REJECT_REASON),
+            Ok(fix44::SessionRejectReason::RequiredTagMissing)
+        );
+    }
+
+    #[test]
+    fn new_gap_within_queued_messages_is_recovered() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 2, &[]));
+        feed(conn, backend, &counterparty_msg(b"D", 4, &[]));
+        let poss_dup = &[
+            (fix44::POSS_DUP_FLAG, "Y"),
+            (fix44::ORIG_SENDING_TIME, "20210101-00:00:00.000"),
+        ];
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 1, poss_dup));
+        assert_eq!(backend.app_msg_seq_nums, vec![1, 2]);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<u64, _>(fix44::BEGIN_SEQ_NO), Ok(3));
+        assert_eq!(msg.fv::<u64, _>(fix44::END_SEQ_NO), Ok(3));
+    }
+
+    #[test]
+    fn duplicate_with_poss_dup_flag_is_ignored() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        let outbound = feed(
+            conn,
+            backend,
+            &counterparty_msg(b"D", 1, &[(fix44::POSS_DUP_FLAG, "Y")]),
+        );
+        assert!(outbound.is_empty());
+        assert_eq!(backend.app_msg_seq_nums, vec![1]);
+    }
+
+    #[test]
+    fn resend_request_replays_app_messages_and_gap_fills_admin_ones() {
+        let conn = &mut conn();
+        conn.store_decoder = Some(decoder());
+        let backend = &mut Recorder::default();
+        conn.on_heartbeat_is_due();
+        let order = counterparty_msg(b"D", 2, &[(fix44::CL_ORD_ID, "foo")]);
+        conn.store().set(2, &order).unwrap();
+        conn.msg_seq_num_outbound = MsgSeqNumCounter(2);
+        conn.on_heartbeat_is_due();
+        let resend_request = &[(fix44::BEGIN_SEQ_NO, "1"), (fix44::END_SEQ_NO, "0")];
+        let outbound = feed(conn, backend, &counterparty_msg(b"2", 1, resend_request));
+        assert_eq!(outbound.len(), 3);
+        let mut decoder = decoder();
+        let expected_gap_fills = [(0, 1, 2), (2, 3, 4)];
+        for (i, msg_seq_num, new_seq_no) in expected_gap_fills.iter().copied() {
+            let msg = decoder.decode(outbound[i].as_slice()).unwrap();
+            assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("4"));
+            assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(msg_seq_num));
+            assert_eq!(msg.fv::<bool, _>(fix44::GAP_FILL_FLAG), Ok(true));
+            assert_eq!(msg.fv::<u64, _>(fix44::NEW_SEQ_NO), Ok(new_seq_no));
+        }
+        let msg = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("D"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+        assert_eq!(msg.fv::<bool, _>(fix44::POSS_DUP_FLAG), Ok(true));
+        assert_eq!(msg.fv::<&str, _>(fix44::CL_ORD_ID), Ok("foo"));
+        assert!(msg.fv_raw(fix44::ORIG_SENDING_TIME).is_some());
+        // Replayed messages don't consume new seq. numbers.
+        assert_eq!(conn.msg_seq_num_outbound.expected(), 4);
+    }
+
+    #[test]
+    fn seq_numbers_are_restored_from_file_store() {
+        let prefix = std::env::temp_dir()
+            .join(format!("fefix-{}", Uuid::new_v4()))
+            .join("FIX.4.4-SENDER-TARGET");
+        {
+            let mut builder = FixConnectionBuilder::default();
+            builder.set_message_store(FileStore::open(&prefix).unwrap());
+            let conn = &mut builder.build();
+            let backend = &mut Recorder::default();
+            conn.on_heartbeat_is_due();
+            feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        }
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_message_store(FileStore::open(&prefix).unwrap());
+        let conn = builder.build();
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+        assert_eq!(conn.msg_seq_num_outbound.expected(), 2);
+    }
+
+    fn acceptor(heartbeat_rule: HeartbeatRule) -> FixConnection {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_heartbeat_rule(heartbeat_rule);
+        builder.build()
+    }
+
+    /// Runs the Logon handshake of `conn` against the inbound `bytes`.
+    /// Returns whether it succeeded, together with all outbound messages.
+    fn handshake(conn: &mut FixConnection, bytes: &[u8]) -> (bool, Vec<Vec<u8>>) {
+        handshake_with(conn, &mut Recorder::default(), bytes)
+    }
+
+    fn handshake_with(
+        conn: &mut FixConnection,
+        backend: &mut Recorder,
+        bytes: &[u8],
+    ) -> (bool, Vec<Vec<u8>>) {
+        let now = Utc::now();
+        let mut actions = conn.connect(backend, decoder(), now);
+        actions.extend(conn.handle(backend, Input::Bytes(bytes), now));
+        (conn.established, writes(actions))
+    }
+
+    fn writes(actions: Vec<Action>) -> Vec<Vec<u8>> {
+        actions
+            .into_iter()
+            .filter_map(|action| match action {
+                Action::Write(bytes) => Some(bytes),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn acceptor_replies_to_valid_logon() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(msg.fv::<u64, _>(fix44::HEART_BT_INT), Ok(10));
+        assert_eq!(conn.heartbeat, Duration::from_secs(10));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+    }
+
+    #[test]
+    fn acceptor_rejects_invalid_heartbeat() {
+        let conn = &mut acceptor(HeartbeatRule::Exact(Duration::from_secs(30)));
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(!established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::heartbeat_exact(30).as_str())
+        );
+    }
+
+    #[test]
+    fn acceptor_rejects_unknown_comp_id() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        conn.target_comp_id = "OTHER".to_string();
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(!established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::comp_id("SenderCompID", 49).as_str())
+        );
+    }
+
+    #[test]
+    fn acceptor_disconnects_if_first_message_is_not_logon() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let (established, outbound) = handshake(conn, &counterparty_msg(b"0", 1, &[]));
+        assert!(!established);
+        assert!(outbound.is_empty());
+    }
+
+    #[test]
+    fn acceptor_recovers_gap_after_logon() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let logon = counterparty_msg(b"A", 3, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        assert_eq!(outbound.len(), 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("2"));
+        assert_eq!(msg.fv::<u64, _>(fix44::BEGIN_SEQ_NO), Ok(1));
+        assert_eq!(msg.fv::<u64, _>(fix44::END_SEQ_NO), Ok(2));
+    }
+
+    #[test]
+    fn acceptor_refuses_logon_outside_schedule() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let now = Utc::now().time();
+        conn.schedule = Some(Schedule::daily(
+            now + chrono::Duration::hours(1),
+            now + chrono::Duration::hours(2),
+        ));
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(!established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::outside_schedule().as_str())
+        );
+    }
+
+    #[test]
+    fn store_is_reset_at_new_session() {
+        let prefix = std::env::temp_dir()
+            .join(format!("fefix-{}", Uuid::new_v4()))
+            .join("FIX.4.4-SENDER-TARGET");
+        let mut store = FileStore::open(&prefix).unwrap();
+        store
+            .set_seq_numbers(SeqNumbers {
+                next_inbound: 10,
+                next_outbound: 20,
+            })
+            .unwrap();
+        // The store was created during a past session.
+        let mut session_file = prefix.clone().into_os_string();
+        session_file.push(".session");
+        std::fs::write(session_file, "20000101-00:00:00").unwrap();
+        let midnight = chrono::NaiveTime::from_hms(0, 0, 0);
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_schedule(Schedule::daily(midnight, midnight));
+        builder.set_message_store(FileStore::open(&prefix).unwrap());
+        let conn = &mut builder.build();
+        assert_eq!(conn.msg_seq_num_outbound.expected(), 20);
+        conn.reset_if_new_session();
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 1);
+        assert_eq!(conn.msg_seq_num_outbound.expected(), 1);
+        assert_eq!(conn.store().seq_numbers().next_outbound(), 1);
+    }
+
+    #[test]
+    fn schedule_resets_follow_the_clock() {
+        let day = |day, hour| Utc.ymd(2021, 3, day).and_hms(hour, 0, 0);
+        let clock = MockClock::new(day(1, 9));
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_role(Role::Acceptor);
+        builder.set_schedule(Schedule::daily(
+            chrono::NaiveTime::from_hms(8, 0, 0),
+            chrono::NaiveTime::from_hms(17, 0, 0),
+        ));
+        builder.set_clock(clock.clone());
+        let seq_numbers = SeqNumbers {
+            next_inbound: 5,
+            next_outbound: 5,
+        };
+        builder.set_message_store(MemoryStore::with_clock(seq_numbers, clock.clone()));
+        let conn = &mut builder.build();
+        let backend = &mut Recorder::default();
+        // Same session as the creation of the store.
+        clock.set(day(1, 10));
+        conn.connect(backend, decoder(), clock.now());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 5);
+        // The next day brings a new session.
+        clock.set(day(2, 9));
+        conn.connect(backend, decoder(), clock.now());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 1);
+        assert_eq!(conn.store().creation_time(), day(2, 9));
+    }
+
+    #[test]
+    fn inaccurate_sending_time_is_rejected_before_logging_out() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_clock(MockClock::new(Utc::now() + chrono::Duration::seconds(10)));
+        let conn = &mut builder.build();
+        let backend = &mut Recorder::default();
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        assert_eq!(outbound.len(), 2);
+        let mut decoder = decoder();
+        let reject = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(reject.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(reject.fv::<u32, _>(fix44::SESSION_REJECT_REASON), Ok(10));
+        assert_eq!(reject.fv::<u32, _>(fix44::REF_TAG_ID), Ok(52));
+        let logout = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(logout.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert!(backend.app_msg_seq_nums.is_empty());
+        // The rejected message still counts.
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+    }
+
+    #[test]
+    fn missing_sending_time_is_rejected_without_logging_out() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let mut encoder = Encoder::<crate::tagvalue::Config>::default();
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(b"FIX.4.4", &mut buffer, b"D");
+        msg.set(fix44::SENDER_COMP_ID, "TARGET");
+        msg.set(fix44::TARGET_COMP_ID, "SENDER");
+        msg.set(fix44::MSG_SEQ_NUM, 1u64);
+        let msg = msg.wrap().to_vec();
+        let outbound = feed(conn, backend, &msg);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let reject = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(reject.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(reject.fv::<u32, _>(fix44::SESSION_REJECT_REASON), Ok(1));
+        assert_eq!(reject.fv::<u32, _>(fix44::REF_TAG_ID), Ok(52));
+        assert!(backend.app_msg_seq_nums.is_empty());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+    }
+
+    #[test]
+    fn sending_time_within_max_allowed_latency_is_accepted() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        let mut config = Config::default();
+        config.set_max_allowed_latency(Duration::from_secs(60));
+        builder.set_config(config);
+        builder.set_clock(MockClock::new(Utc::now() + chrono::Duration::seconds(10)));
+        let conn = &mut builder.build();
+        let backend = &mut Recorder::default();
+        let outbound = feed(conn, backend, &counterparty_msg(b"D", 1, &[]));
+        assert!(outbound.is_empty());
+        assert_eq!(backend.app_msg_seq_nums, vec![1]);
+    }
+
+    #[test]
+    fn orig_sending_time_later_than_sending_time_is_rejected() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let msg = counterparty_msg(
+            b"D",
+            1,
+            &[
+                (fix44::POSS_DUP_FLAG, "Y"),
+                (fix44::ORIG_SENDING_TIME, "29990101-00:00:00.000"),
+            ],
+        );
+        let outbound = feed(conn, backend, &msg);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let reject = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(reject.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(reject.fv::<u32, _>(fix44::SESSION_REJECT_REASON), Ok(10));
+        assert_eq!(reject.fv::<u32, _>(fix44::REF_TAG_ID), Ok(122));
+        assert!(backend.app_msg_seq_nums.is_empty());
+    }
+
+    #[test]
+    fn test_request_is_answered_with_heartbeat() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let msg = counterparty_msg(b"1", 1, &[(fix44::TEST_REQ_ID, "foobar")]);
+        let outbound = feed(conn, backend, &msg);
+        let mut decoder = decoder();
+        let heartbeat = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(heartbeat.fv::<&str, _>(fix44::MSG_TYPE), Ok("0"));
+        assert_eq!(heartbeat.fv::<&str, _>(fix44::TEST_REQ_ID), Ok("foobar"));
+    }
+
+    #[test]
+    fn heartbeat_with_matching_test_req_id_clears_test_request() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let test_request = conn.on_test_request_is_due().to_vec();
+        let mut decoder = decoder();
+        let test_request = decoder.decode(test_request.as_slice()).unwrap();
+        assert_eq!(test_request.fv::<&str, _>(fix44::MSG_TYPE), Ok("1"));
+        let test_req_id = test_request
+            .fv::<&str, _>(fix44::TEST_REQ_ID)
+            .unwrap()
+            .to_string();
+        feed(conn, backend, &counterparty_msg(b"0", 1, &[]));
+        assert!(conn.pending_test_req_id.is_some());
+        let heartbeat = counterparty_msg(b"0", 2, &[(fix44::TEST_REQ_ID, test_req_id.as_str())]);
+        feed(conn, backend, &heartbeat);
+        assert!(conn.pending_test_req_id.is_none());
+    }
+
+    #[test]
+    fn test_req_ids_are_unique() {
+        let conn = &mut conn();
+        let mut decoder = decoder();
+        let mut test_req_id = || {
+            let test_request = conn.on_test_request_is_due().to_vec();
+            let test_request = decoder.decode(test_request.as_slice()).unwrap();
+            test_request
+                .fv::<&str, _>(fix44::TEST_REQ_ID)
+                .unwrap()
+                .to_string()
+        };
+        assert_ne!(test_req_id(), test_req_id());
+    }
+
+    #[test]
+    fn logout_is_confirmed() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let outbound = feed(conn, backend, &counterparty_msg(b"5", 1, &[]));
+        let mut decoder = decoder();
+        let logout = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(logout.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert!(conn.is_logged_out());
+    }
+
+    #[test]
+    fn logout_confirmation_terminates_session() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        conn.make_logout("Bye".to_string());
+        assert!(!conn.is_logged_out());
+        let outbound = feed(conn, backend, &counterparty_msg(b"5", 1, &[]));
+        assert!(outbound.is_empty());
+        assert!(conn.is_logged_out());
+    }
+
+    #[test]
+    fn outbound_app_messages_are_stamped_and_persisted() {
+        let conn = &mut conn();
+        conn.on_heartbeat_is_due();
+        let mut message = OutboundMessage::new(b"D");
+        message.set(fix44::CL_ORD_ID, "foo");
+        let (msg_seq_num, bytes) = conn.on_outbound_app_message(&message);
+        let bytes = bytes.to_vec();
+        assert_eq!(msg_seq_num, 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(bytes.as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("D"));
+        assert_eq!(msg.fv::<&str, _>(fix44::SENDER_COMP_ID), Ok("SENDER"));
+        assert_eq!(msg.fv::<&str, _>(fix44::TARGET_COMP_ID), Ok("TARGET"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+        assert_eq!(msg.fv::<&str, _>(fix44::CL_ORD_ID), Ok("foo"));
+        assert_eq!(conn.store().get(2..3).unwrap(), vec![(2, bytes)]);
+    }
+
+    #[test]
+    fn initiator_logon_carries_session_options_and_custom_fields() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_seq_numbers(7, 7);
+        let mut config = Config::default();
+        config.set_reset_seq_num_on_logon(true);
+        config.set_enable_next_expected_msg_seq_num(true);
+        builder.set_config(config);
+        let conn = &mut builder.build();
+        let backend = &mut Recorder {
+            username: Some("alice"),
+            ..Recorder::default()
+        };
+        let reply = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::RESET_SEQ_NUM_FLAG, "Y"),
+            ],
+        );
+        let (established, outbound) = handshake_with(conn, backend, &reply);
+        assert!(established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<bool, _>(fix44::RESET_SEQ_NUM_FLAG), Ok(true));
+        assert_eq!(msg.fv::<u64, _>(fix44::NEXT_EXPECTED_MSG_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<&str, _>(fix44::USERNAME), Ok("alice"));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+    }
+
+    #[test]
+    fn initiator_rejects_logon_with_different_heartbeat() {
+        let conn = &mut conn();
+        let reply = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "10")]);
+        let (established, outbound) = handshake(conn, &reply);
+        assert!(!established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::heartbeat_exact(30).as_str())
+        );
+    }
+
+    #[test]
+    fn acceptor_honours_reset_seq_num_flag() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_seq_numbers(5, 5);
+        let conn = &mut builder.build();
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::RESET_SEQ_NUM_FLAG, "Y"),
+            ],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<bool, _>(fix44::RESET_SEQ_NUM_FLAG), Ok(true));
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+    }
+
+    #[test]
+    fn acceptor_resends_from_next_expected_msg_seq_num() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_seq_numbers(1, 4);
+        let conn = &mut builder.build();
+        conn.store_decoder = Some(decoder());
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::NEXT_EXPECTED_MSG_SEQ_NUM, "2"),
+            ],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        assert_eq!(outbound.len(), 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(4));
+        let msg = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("4"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+        assert_eq!(msg.fv::<u64, _>(fix44::NEW_SEQ_NO), Ok(4));
+    }
+
+    fn initiator_with_next_expected_msg_seq_num(next_expected: &str) -> (bool, Vec<Vec<u8>>) {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_seq_numbers(1, 4);
+        let conn = &mut builder.build();
+        conn.store_decoder = Some(decoder());
+        let reply = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::NEXT_EXPECTED_MSG_SEQ_NUM, next_expected),
+            ],
+        );
+        handshake(conn, &reply)
+    }
+
+    #[test]
+    fn initiator_accepts_next_expected_msg_seq_num_after_its_logon() {
+        let (established, outbound) = initiator_with_next_expected_msg_seq_num("5");
+        assert!(established);
+        assert_eq!(outbound.len(), 1);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(4));
+    }
+
+    #[test]
+    fn initiator_resends_from_next_expected_msg_seq_num() {
+        let (established, outbound) = initiator_with_next_expected_msg_seq_num("2");
+        assert!(established);
+        assert_eq!(outbound.len(), 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[1].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("4"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+        assert_eq!(msg.fv::<u64, _>(fix44::NEW_SEQ_NO), Ok(5));
+    }
+
+    #[test]
+    fn acceptor_rejects_next_expected_msg_seq_num_too_high() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::NEXT_EXPECTED_MSG_SEQ_NUM, "10"),
+            ],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(!established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::inbound_seqnum().as_str())
+        );
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected_when_validation_is_enabled() {
+        let conn = &mut conn();
+        conn.validator = Some(Validator::new(Dictionary::fix44()));
+        let backend = &mut Recorder::default();
+        let order = counterparty_msg(b"D", 1, &[(fix44::CL_ORD_ID, "foo")]);
+        let outbound = feed(conn, backend, &order);
+        assert!(backend.app_msg_seq_nums.is_empty());
+        assert_eq!(conn.msg_seq_num_inbound.expected(), 2);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(msg.fv::<u64, _>(fix44::REF_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<&str, _>(fix44::REF_MSG_TYPE), Ok("D"));
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(54));
+        assert_eq!(
+            msg.fv::<fix44::SessionRejectReason, _>(fix44::SESSION_REJECT_REASON),
+            Ok(fix44::SessionRejectReason::RequiredTagMissing)
+        );
+    }
+
+    #[test]
+    fn unsupported_msg_types_get_business_message_reject() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let outbound = feed(conn, backend, &counterparty_msg(b"F", 1, &[]));
+        assert!(backend.app_msg_seq_nums.is_empty());
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("j"));
+        assert_eq!(msg.fv::<u64, _>(fix44::REF_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<&str, _>(fix44::REF_MSG_TYPE), Ok("F"));
+        assert_eq!(
+            msg.fv::<fix44::BusinessRejectReason, _>(fix44::BUSINESS_REJECT_REASON),
+            Ok(fix44::BusinessRejectReason::UnsupportedMessageType)
+        );
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::unsupported_msg_type().as_str())
+        );
+    }
+
+    #[test]
+    fn backend_errors_can_be_turned_into_business_message_rejects() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let order = counterparty_msg(b"D", 1, &[(fix44::CL_ORD_ID, "unknown")]);
+        let outbound = feed(conn, backend, &order);
+        assert_eq!(backend.app_msg_seq_nums, vec![1]);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("j"));
+        assert_eq!(msg.fv::<&str, _>(fix44::REF_MSG_TYPE), Ok("D"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::BUSINESS_REJECT_REF_ID),
+            Ok("unknown")
+        );
+        assert_eq!(
+            msg.fv::<fix44::BusinessRejectReason, _>(fix44::BUSINESS_REJECT_REASON),
+            Ok(fix44::BusinessRejectReason::UnkownId)
+        );
+    }
+
+    fn fixt_conn(role: Role) -> FixConnection {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_begin_string("FIXT.1.1");
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(role);
+        builder.set_default_appl_ver_id(ApplVerId::Fix44);
+        builder.add_appl_dictionary(ApplVerId::Fix44, Dictionary::fix44());
+        builder.build()
+    }
+
+    #[test]
+    fn fixt_logon_negotiates_default_appl_ver_id() {
+        let conn = &mut fixt_conn(Role::Acceptor);
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[(fix44::HEART_BT_INT, "30"), (DEFAULT_APPL_VER_ID, "6")],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(msg.fv::<&str, _>(DEFAULT_APPL_VER_ID), Ok("6"));
+
+        let conn = &mut fixt_conn(Role::Acceptor);
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[(fix44::HEART_BT_INT, "30"), (DEFAULT_APPL_VER_ID, "9")],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(!established);
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(
+            msg.fv::<&str, _>(fix44::TEXT),
+            Ok(errs::unsupported_appl_ver_id().as_str())
+        );
+    }
+
+    #[test]
+    fn fixt_app_messages_are_decoded_by_appl_ver_id() {
+        use crate::session::fixt::APPL_VER_ID;
+
+        let conn = &mut fixt_conn(Role::Initiator);
+        conn.appl_versions
+            .set_validation(Some(&Dictionary::fix44()));
+        let backend = &mut Recorder::default();
+        // No `ApplVerID <1128>`, so the default one applies.
+        let order = counterparty_msg(b"D", 1, &[(fix44::CL_ORD_ID, "foo")]);
+        let outbound = feed(conn, backend, &order);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(54));
+        // No dictionary for FIX 5.0 SP2.
+        let order = counterparty_msg(b"D", 2, &[(APPL_VER_ID, "9")]);
+        let outbound = feed(conn, backend, &order);
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("3"));
+        assert_eq!(msg.fv::<u32, _>(fix44::REF_TAG_ID), Ok(1128));
+        assert!(backend.app_msg_seq_nums.is_empty());
+
+        conn.appl_versions.set_validation(None);
+        let order = counterparty_msg(b"D", 3, &[(APPL_VER_ID, "6")]);
+        assert!(feed(conn, backend, &order).is_empty());
+        assert_eq!(backend.app_msg_seq_nums, vec![3]);
+    }
+
+    #[cfg(all(feature = "fixt11", feature = "fix50sp2"))]
+    #[test]
+    fn fixt_app_messages_are_validated_against_their_dictionary() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_begin_string("FIXT.1.1");
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_default_appl_ver_id(ApplVerId::Fix50Sp2);
+        builder.add_appl_dictionary(ApplVerId::Fix50Sp2, Dictionary::fix50sp2());
+        let conn = &mut builder.build();
+        let fixt11 = Dictionary::fixt11();
+        conn.validator = Some(Validator::new(fixt11.clone()));
+        conn.appl_versions.set_validation(Some(&fixt11));
+        let backend = &mut Recorder::default();
+        let mut decoder: Decoder = Decoder::new(fixt11);
+        let fields = [
+            (fix44::CL_ORD_ID, "foo"),
+            (fix44::SIDE, "1"),
+            (fix44::TRANSACT_TIME, "20210301-10:00:00"),
+            (fix44::ORD_TYPE, "1"),
+        ];
+        let order = counterparty_msg(b"D", 1, &fields);
+        let msg = decoder.decode(order.as_slice()).unwrap();
+        let mut outbound = vec![];
+        collect_outbound(conn.on_inbound_message(msg, backend), &mut outbound);
+        assert!(outbound.is_empty());
+        assert_eq!(backend.app_msg_seq_nums, vec![1]);
+    }
+
+    fn msg_types(outbound: &[Vec<u8>]) -> Vec<String> {
+        let mut decoder = decoder();
+        outbound
+            .iter()
+            .map(|bytes| {
+                let msg = decoder.decode(bytes.as_slice()).unwrap();
+                msg.fv::<&str, _>(fix44::MSG_TYPE).unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inbound_bytes_are_buffered_until_complete() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let backend = &mut Recorder::default();
+        let now = Utc::now();
+        assert!(conn.connect(backend, decoder(), now).is_empty());
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        let (first, second) = logon.split_at(20);
+        assert!(conn.handle(backend, Input::Bytes(first), now).is_empty());
+        let outbound = writes(conn.handle(backend, Input::Bytes(second), now));
+        assert_eq!(msg_types(&outbound), vec!["A"]);
+        assert!(conn.established);
+    }
+
+    #[test]
+    fn garbled_bytes_close_the_transport() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let backend = &mut Recorder::default();
+        let now = Utc::now();
+        conn.connect(backend, decoder(), now);
+        let actions = conn.handle(backend, Input::Bytes(b"garbage"), now);
+        assert_eq!(actions, vec![Action::Disconnect]);
+    }
+
+    #[test]
+    fn garbled_messages_are_ignored() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let backend = &mut Recorder::default();
+        let now = Utc::now();
+        conn.connect(backend, decoder(), now);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        conn.handle(backend, Input::Bytes(&logon), now);
+        let mut garbled = counterparty_msg(b"D", 2, &[]);
+        let len = garbled.len();
+        garbled[len - 2] = if garbled[len - 2] == b'0' { b'1' } else { b'0' };
+        assert!(writes(conn.handle(backend, Input::Bytes(&garbled), now)).is_empty());
+        conn.handle(backend, Input::Bytes(&counterparty_msg(b"D", 2, &[])), now);
+        assert_eq!(backend.app_msg_seq_nums, vec![2]);
+    }
+
+    #[test]
+    fn timers_drive_heartbeats_test_requests_and_logout() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let backend = &mut Recorder::default();
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        conn.connect(backend, decoder(), start);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        let actions = conn.handle(backend, Input::Bytes(&logon), start);
+        assert_eq!(actions.last(), Some(&Action::ArmTimer(at(30))));
+        // We've been silent for `HeartBtInt <108>`.
+        let actions = conn.handle(backend, Input::Tick, at(30));
+        assert_eq!(actions.last(), Some(&Action::ArmTimer(at(36))));
+        assert_eq!(msg_types(&writes(actions)), vec!["0"]);
+        // The counterparty has been silent for 1.2 times `HeartBtInt <108>`.
+        let actions = conn.handle(backend, Input::Tick, at(36));
+        assert_eq!(actions.last(), Some(&Action::ArmTimer(at(66))));
+        assert_eq!(msg_types(&writes(actions)), vec!["1"]);
+        let actions = conn.handle(backend, Input::Tick, at(66));
+        assert_eq!(actions.last(), Some(&Action::Disconnect));
+        assert_eq!(msg_types(&writes(actions)), vec!["5"]);
+    }
+
+    #[test]
+    fn app_messages_are_queued_until_logon() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let now = Utc::now();
+        let outbound = writes(conn.connect(backend, decoder(), now));
+        assert_eq!(msg_types(&outbound), vec!["A"]);
+        let mut order = OutboundMessage::new(b"D");
+        order.set(fix44::CL_ORD_ID, "foo");
+        let send = Input::Send {
+            id: 0,
+            message: order,
+        };
+        assert!(writes(conn.handle(backend, send, now)).is_empty());
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        let outbound = writes(conn.handle(backend, Input::Bytes(&logon), now));
+        assert_eq!(msg_types(&outbound), vec!["D"]);
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+    }
+
+    #[test]
+    fn throttled_app_messages_are_delayed_or_refused() {
+        let mut throttle = Throttle::new();
+        throttle.set_msg_type_limit(b"D", RateLimit::per_second(1));
+        throttle.set_max_queue_depth(1);
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_throttle(throttle);
+        let conn = &mut builder.build();
+        let backend = &mut Recorder::default();
+        let start = Utc::now();
+        conn.connect(backend, decoder(), start);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        conn.handle(backend, Input::Bytes(&logon), start);
+        let send = |conn: &mut FixConnection, backend: &mut Recorder, id| {
+            let message = OutboundMessage::new(b"D");
+            conn.handle(backend, Input::Send { id, message }, start)
+        };
+        let actions = send(conn, backend, 10);
+        let sent = Action::Sent {
+            id: 10,
+            msg_seq_num: 2,
+        };
+        assert_eq!(actions[1], sent);
+        let actions = send(conn, backend, 11);
+        let release = start + chrono::Duration::seconds(1);
+        assert_eq!(actions, vec![Action::ArmTimer(release)]);
+        assert_eq!(conn.throttle_queue_depth(), 1);
+        assert_eq!(send(conn, backend, 12)[0], Action::Throttled { id: 12 });
+        let actions = conn.handle(backend, Input::Tick, release);
+        assert_eq!(msg_types(&writes(actions.clone())), vec!["D"]);
+        let sent = Action::Sent {
+            id: 11,
+            msg_seq_num: 3,
+        };
+        assert_eq!(actions[1], sent);
+        assert_eq!(conn.throttle_queue_depth(), 0);
+    }
+
+    #[test]
+    fn send_replies_match_their_messages_when_throttled() {
+        use crate::session::MemoryTransport;
+
+        let clock = MockClock::new(Utc::now());
+        let mut throttle = Throttle::new();
+        throttle.set_msg_type_limit(b"D", RateLimit::per_second(1));
+        throttle.set_max_queue_depth(1);
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_throttle(throttle);
+        builder.set_clock(clock.clone());
+        let conn = &mut builder.build();
+        let sender = conn.sender();
+        let (local, mut remote) = MemoryTransport::pair_with_clock(clock.clone());
+        let (input, output) = local.split();
+        let session = conn.start(Recorder::default(), input, output, decoder());
+        let counterparty = async move {
+            let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+            remote.write_all(&logon).await.unwrap();
+            let mut buffer = [0; 1024];
+            assert!(remote.read(&mut buffer).await.unwrap() > 0);
+            let first = sender.send(OutboundMessage::new(b"D")).await;
+            // The second message is held back, then the third one is
+            // refused because the queue is full.
+            let second = sender.send(OutboundMessage::new(b"D"));
+            let third = async {
+                let third = sender.send(OutboundMessage::new(b"D")).await;
+                clock.advance(chrono::Duration::seconds(1));
+                third
+            };
+            let (second, third) = future::join(second, third).await;
+            (first, second, third)
+        };
+        let (established, replies) =
+            futures::executor::block_on(future::join(session, counterparty));
+        assert!(established);
+        assert_eq!(replies, (Ok(2), Ok(3), Err(SendError::Throttled)));
+    }
+
+    #[test]
+    fn acceptor_refuses_logon_which_fails_authentication() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let backend = &mut Recorder::default();
+        let peer_addr = "127.0.0.1:9876".parse().unwrap();
+        conn.set_peer_addr(Some(peer_addr));
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[(fix44::HEART_BT_INT, "30"), (fix44::PASSWORD, "guess")],
+        );
+        let (established, outbound) = handshake_with(conn, backend, &logon);
+        assert!(!established);
+        assert_eq!(backend.peer_addr, Some(peer_addr));
+        let mut decoder = decoder();
+        let logout = decoder.decode(&outbound[0]).unwrap();
+        assert_eq!(logout.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(logout.fv::<&str, _>(fix44::TEXT), Ok("Invalid password"));
+        assert_eq!(logout.fv_raw(SESSION_STATUS), Some(&b"5"[..]));
+    }
+
+    #[test]
+    fn acceptor_confirms_password_change() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let logon = counterparty_msg(
+            b"A",
+            1,
+            &[
+                (fix44::HEART_BT_INT, "30"),
+                (fix44::PASSWORD, "secret"),
+                (fix44::NEW_PASSWORD, "s3cr3t"),
+            ],
+        );
+        let (established, outbound) = handshake(conn, &logon);
+        assert!(established);
+        let mut decoder = decoder();
+        let logon = decoder.decode(&outbound[0]).unwrap();
+        assert_eq!(logon.fv::<&str, _>(fix44::MSG_TYPE), Ok("A"));
+        assert_eq!(logon.fv_raw(SESSION_STATUS), Some(&b"1"[..]));
+    }
+
+    #[test]
+    fn mock_clock_drives_timers_of_running_sessions() {
+        use futures::TryStreamExt;
+        use std::future::Future;
+        use std::task::{Context, Poll};
+
+        let clock = MockClock::new(Utc::now());
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_clock(clock.clone());
+        let conn = &mut builder.build();
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        // The counterparty logs on and then goes silent, without closing the
+        // transport.
+        let silence = futures::stream::pending::<std::io::Result<Vec<u8>>>().into_async_read();
+        let input = futures::io::Cursor::new(logon).chain(silence);
+        let mut output = Vec::new();
+        {
+            let session = conn.start(Recorder::default(), input, &mut output, decoder());
+            futures::pin_mut!(session);
+            let waker = futures::task::noop_waker();
+            let cx = &mut Context::from_waker(&waker);
+            for secs in &[0, 30, 6, 29] {
+                clock.advance(chrono::Duration::seconds(*secs));
+                assert_eq!(session.as_mut().poll(cx), Poll::Pending);
+            }
+            clock.advance(chrono::Duration::seconds(1));
+            assert_eq!(session.as_mut().poll(cx), Poll::Ready(true));
+        }
+        let mut outbound = vec![];
+        let mut bytes = output.as_slice();
+        while let Some(i) = bytes.windows(4).position(|w| w == b"\x0110=") {
+            let (msg, rest) = bytes.split_at(i + 8);
+            outbound.push(msg.to_vec());
+            bytes = rest;
+        }
+        // Heartbeat after 30 seconds, TestRequest after 36 and Logout after 66.
+        assert_eq!(msg_types(&outbound), vec!["A", "0", "1", "5"]);
+    }
+
+    #[test]
+    fn metrics_track_traffic_timeouts_and_seq_numbers() {
+        let registry = MetricsRegistry::new();
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_metrics_registry(registry.clone());
+        let conn = &mut builder.build();
+        let backend = &mut Recorder::default();
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        conn.connect(backend, decoder(), start);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        conn.handle(backend, Input::Bytes(&logon), start);
+        conn.handle(backend, Input::Tick, at(36));
+        let metrics = conn.metrics();
+        assert_eq!(metrics.messages_received("A"), 1);
+        assert_eq!(metrics.messages_sent("A"), 1);
+        assert_eq!(metrics.messages_sent("1"), 1);
+        let text = registry.render();
+        let session = "session=\"FIX.4.4:SENDER->TARGET\"";
+        assert!(text.contains(&format!("fix_heartbeat_timeouts_total{{{}}} 1\n", session)));
+        assert!(text.contains(&format!("fix_next_inbound_seq_num{{{}}} 2\n", session)));
+        assert!(text.contains(&format!("fix_next_outbound_seq_num{{{}}} 3\n", session)));
+        assert!(text.contains(&format!(
+            "fix_inbound_latency_seconds_count{{{}}} 1\n",
+            session
+        )));
+        // The TestRequest goes unanswered.
+        conn.handle(backend, Input::Tick, at(66));
+        let text = registry.render();
+        assert!(text.contains(&format!(
+            "fix_test_request_timeouts_total{{{}}} 1\n",
+            session
+        )));
+    }
+
+    /// Keeps all log lines in memory, prefixed by their kind.
+    #[derive(Debug, Clone, Default)]
+    struct MemoryLog(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl SessionLog for MemoryLog {
+        fn on_incoming(&mut self, _now: DateTime<Utc>, message: &[u8]) -> std::io::Result<()> {
+            let msg_type = msg_types(&[message.to_vec()]).remove(0);
+            self.0.lock().unwrap().push(format!("in {}", msg_type));
+            Ok(())
+        }
+
+        fn on_outgoing(&mut self, _now: DateTime<Utc>, message: &[u8]) -> std::io::Result<()> {
+            let msg_type = msg_types(&[message.to_vec()]).remove(0);
+            self.0.lock().unwrap().push(format!("out {}", msg_type));
+            Ok(())
+        }
+
+        fn on_event(&mut self, _now: DateTime<Utc>, text: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(text.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn messages_and_session_events_are_logged() {
+        let log = MemoryLog::default();
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_log(log.clone());
+        let conn = &mut builder.build();
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        let (established, _) = handshake(conn, &logon);
+        assert!(established);
+        conn.handle(&mut Recorder::default(), Input::Disconnect, Utc::now());
+        assert_eq!(
+            *log.0.lock().unwrap(),
+            vec![
+                "in A",
+                "Received Logon (MsgSeqNum=1)",
+                "Logon contact established",
+                "out A",
+                "Sent Logon (MsgSeqNum=1)",
+                "Disconnected by the transport",
+            ]
+        );
+    }
+
+    #[test]
+    fn mirrors_see_messages_rejected_by_the_session() {
+        let mut mirror = Mirror::new(SessionId {
+            begin_string: "FIX.4.4".to_string(),
+            sender_comp_id: "SENDER".to_string(),
+            target_comp_id: "TARGET".to_string(),
+        });
+        let (sender, mut receiver) = mpsc::unbounded();
+        let mut task = Box::pin(mirror.add_sink(sender, 16));
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_role(Role::Acceptor);
+        builder.set_mirror(mirror);
+        let conn = &mut builder.build();
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        let (established, _) = handshake(conn, &logon);
+        assert!(established);
+        // `SendingTime <52>` is an hour off.
+        let order = counterparty_msg(b"D", 2, &[]);
+        let later = Utc::now() + chrono::Duration::hours(1);
+        let backend = &mut Recorder::default();
+        conn.handle(backend, Input::Bytes(&order), later);
+        assert!(backend.app_msg_seq_nums.is_empty());
+        assert_eq!((&mut task).now_or_never(), None);
+        let mut mirrored = vec![];
+        while let Ok(Some(message)) = receiver.try_next() {
+            let direction = match message.direction() {
+                Direction::Inbound => "in",
+                Direction::Outbound => "out",
+            };
+            let msg_type = msg_types(&[message.message().to_vec()]).remove(0);
+            mirrored.push(format!("{} {}", direction, msg_type));
+        }
+        assert_eq!(mirrored, vec!["in A", "out A", "in D", "out 3", "out 5"]);
+    }
+
+    fn admin(conn: &mut FixConnection, command: AdminCommand) -> Vec<Action> {
+        conn.handle(&mut Recorder::default(), Input::Admin(command), Utc::now())
+    }
+
+    #[test]
+    fn admin_commands_are_validated() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let refused = Action::Admin(Err(AdminError::NotLoggedOn));
+        assert_eq!(admin(conn, AdminCommand::TestRequest), vec![refused]);
+        let invalid = Action::Admin(Err(AdminError::InvalidSeqNum));
+        assert_eq!(
+            admin(conn, AdminCommand::SetNextInbound(0)),
+            vec![invalid.clone()]
+        );
+        let resend_request = AdminCommand::ResendRequest {
+            begin_seq_no: 5,
+            end_seq_no: 4,
+        };
+        assert_eq!(admin(conn, resend_request), vec![invalid]);
+        assert_eq!(
+            admin(conn, AdminCommand::SetNextOutbound(7)),
+            vec![Action::Admin(Ok(()))]
+        );
+        assert_eq!(conn.state().next_outbound, 7);
+        assert_eq!(conn.store().seq_numbers().next_outbound(), 7);
+    }
+
+    #[test]
+    fn admin_commands_send_session_messages() {
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        assert!(handshake(conn, &logon).0);
+        assert!(conn.state().is_logged_on);
+        let actions = admin(conn, AdminCommand::TestRequest);
+        assert_eq!(actions[0], Action::Admin(Ok(())));
+        assert_eq!(msg_types(&writes(actions)), vec!["1"]);
+        let resend_request = AdminCommand::ResendRequest {
+            begin_seq_no: 3,
+            end_seq_no: 0,
+        };
+        let outbound = writes(admin(conn, resend_request));
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("2"));
+        assert_eq!(msg.fv::<u64, _>(fix44::BEGIN_SEQ_NO), Ok(3));
+        assert_eq!(msg.fv::<u64, _>(fix44::END_SEQ_NO), Ok(0));
+        admin(conn, AdminCommand::SetNextInbound(10));
+        admin(conn, AdminCommand::SetNextOutbound(20));
+        let outbound = writes(admin(conn, AdminCommand::Logout("Bye".to_string())));
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(20));
+        assert_eq!(msg.fv::<&str, _>(fix44::TEXT), Ok("Bye"));
+        let state = conn.state();
+        assert_eq!((state.next_inbound, state.next_outbound), (10, 21));
+        let actions = admin(conn, AdminCommand::Disconnect);
+        assert_eq!(actions, vec![Action::Admin(Ok(())), Action::Disconnect]);
+        assert!(!conn.state().is_logged_on);
+    }
+
+    #[test]
+    fn admin_reset_logs_out_and_resets_seq_numbers_on_next_logon() {
+        let conn = &mut conn();
+        let backend = &mut Recorder::default();
+        let now = Utc::now();
+        conn.connect(backend, decoder(), now);
+        let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+        conn.handle(backend, Input::Bytes(&logon), now);
+        let actions = admin(conn, AdminCommand::Reset);
+        assert_eq!(actions.last(), Some(&Action::Disconnect));
+        assert_eq!(msg_types(&writes(actions)), vec!["5"]);
+        let state = conn.state();
+        assert_eq!((state.next_inbound, state.next_outbound), (1, 1));
+        let outbound = writes(conn.connect(backend, decoder(), now));
+        let mut decoder = decoder();
+        let msg = decoder.decode(outbound[0].as_slice()).unwrap();
+        assert_eq!(msg.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(1));
+        assert_eq!(msg.fv::<bool, _>(fix44::RESET_SEQ_NUM_FLAG), Ok(true));
+    }
+
+    #[test]
+    fn session_admin_controls_running_sessions() {
+        use crate::session::MemoryTransport;
+
+        let conn = &mut acceptor(HeartbeatRule::Any);
+        let admin = conn.admin();
+        let (local, mut remote) = MemoryTransport::pair();
+        let (input, output) = local.split();
+        let session = conn.start(Recorder::default(), input, output, decoder());
+        let operator = async {
+            let logon = counterparty_msg(b"A", 1, &[(fix44::HEART_BT_INT, "30")]);
+            remote.write_all(&logon).await.unwrap();
+            // Wait for the Logon reply.
+            let mut buffer = [0; 1024];
+            assert!(remote.read(&mut buffer).await.unwrap() > 0);
+            let state = admin.state().await.unwrap();
+            assert!(state.is_logged_on);
+            assert_eq!((state.next_inbound, state.next_outbound), (2, 2));
+            admin.set_next_outbound(5).await.unwrap();
+            admin.disconnect().await.unwrap();
+        };
+        let (established, ()) = futures::executor::block_on(future::join(session, operator));
+        assert!(established);
+        assert_eq!(conn.state().next_outbound, 5);
+    }
+}
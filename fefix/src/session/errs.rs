@@ -25,6 +25,20 @@ pub fn msg_seq_num(seq_number: u64) -> String {
     format!("Invalid MsgSeqNum <34>, expected value {}", seq_number)
 }
 
+pub fn new_seq_no_too_low(new_seq_no: u64, expected: u64) -> String {
+    format!(
+        "Attempt to lower sequence number, invalid value NewSeqNo(36)={} (expected {})",
+        new_seq_no, expected
+    )
+}
+
+pub fn new_seq_no_unchanged(new_seq_no: u64) -> String {
+    format!(
+        "SequenceReset with NewSeqNo(36)={} doesn't change the expected sequence number",
+        new_seq_no
+    )
+}
+
 pub fn production_env() -> String {
     "TestMessageIndicator(464) was set to 'Y' but the environment is a production environment"
         .to_string()
@@ -33,3 +47,82 @@ pub fn production_env() -> String {
 pub fn missing_field(name: &str, tag: u32) -> String {
     format!("Missing mandatory field {}({})", name, tag)
 }
+
+pub fn comp_id(name: &str, tag: u32) -> String {
+    format!("Invalid {}({}), unknown CompID", name, tag)
+}
+
+pub fn unknown_session() -> String {
+    "Unknown session, check BeginString(8), SenderCompID(49) and TargetCompID(56)".to_string()
+}
+
+pub fn session_already_active() -> String {
+    "Session is already logged on".to_string()
+}
+
+pub fn outside_schedule() -> String {
+    "Logon attempt not within session time".to_string()
+}
+
+pub fn session_end() -> String {
+    "End of session time".to_string()
+}
+
+pub fn session_reset() -> String {
+    "Session reset by the operator".to_string()
+}
+
+pub fn sending_time_accuracy() -> String {
+    "SendingTime(52) accuracy problem".to_string()
+}
+
+pub fn orig_sending_time() -> String {
+    "OrigSendingTime(122) is later than SendingTime(52)".to_string()
+}
+
+pub fn heartbeat_timeout() -> String {
+    "No Heartbeat(0) received in response to TestRequest(1)".to_string()
+}
+
+pub fn invalid_msg_type() -> String {
+    "Invalid MsgType(35)".to_string()
+}
+
+pub fn invalid_tag_number(tag: u32) -> String {
+    format!("Invalid tag number ({})", tag)
+}
+
+pub fn tag_not_defined_for_msg_type(name: &str, tag: u32) -> String {
+    format!("Tag {}({}) not defined for this message type", name, tag)
+}
+
+pub fn empty_value(name: &str, tag: u32) -> String {
+    format!("Tag {}({}) specified without a value", name, tag)
+}
+
+pub fn incorrect_data_format(name: &str, tag: u32) -> String {
+    format!("Incorrect data format for value of {}({})", name, tag)
+}
+
+pub fn value_out_of_range(name: &str, tag: u32) -> String {
+    format!("Value is incorrect (out of range) for {}({})", name, tag)
+}
+
+pub fn duplicate_tag(name: &str, tag: u32) -> String {
+    format!("Tag {}({}) appears more than once", name, tag)
+}
+
+pub fn num_in_group_count(name: &str, tag: u32) -> String {
+    format!(
+        "Incorrect NumInGroup count for repeating group {}({})",
+        name, tag
+    )
+}
+
+pub fn unsupported_msg_type() -> String {
+    "Unsupported message type".to_string()
+}
+
+pub fn unsupported_appl_ver_id() -> String {
+    "Unsupported ApplVerID".to_string()
+}
@@ -1,16 +1,26 @@
+#![allow(deprecated)]
+
+use super::connection::{add_duration, logout_deadline, test_request_deadline};
+use super::{Clock, SessionMetrics, SystemClock};
 use crate::tagvalue::{DecoderBuffered, Message};
+use chrono::{DateTime, Utc};
+use futures::future::{self, BoxFuture, Either};
 use futures::select;
 use futures::{AsyncRead, AsyncReadExt, FutureExt};
-use futures_timer::Delay;
 use std::io;
 use std::time::Duration;
-use std::time::Instant;
 
 /// Asynchronous, executor-agnostic low-level event loop for FIX connectors.
 ///
 /// This event loop allows FIX connectors to delegate event-tracking logic to a
 /// single entity. This event loop keeps track of such events within a FIX
 /// session. See [`LlEvent`] for more information.
+///
+/// `TestRequest <1>` and `Logout <5>` are due after the same delays as in
+/// [`FixConnection`](super::FixConnection), which supersedes this event loop.
+#[deprecated(
+    note = "use `FixConnection::start`, or `FixConnection::handle` to drive sessions manually"
+)]
 #[derive(Debug)]
 pub struct LlEventLoop<I>
 where
@@ -19,10 +29,11 @@ where
     decoder: DecoderBuffered,
     input: I,
     heartbeat: Duration,
-    heartbeat_soft_tolerance: Duration,
-    heartbeat_hard_tolerance: Duration,
-    last_reset: Instant,
-    last_heartbeat: Instant,
+    clock: Box<dyn Clock>,
+    last_reset: DateTime<Utc>,
+    last_heartbeat: DateTime<Utc>,
+    is_test_request_pending: bool,
+    metrics: Option<SessionMetrics>,
 }
 
 impl<I> LlEventLoop<I>
@@ -30,17 +41,44 @@ where
     I: AsyncRead + std::marker::Unpin,
 {
     pub fn new(decoder: DecoderBuffered, input: I, heartbeat: Duration) -> Self {
-        let heartbeat_soft_tolerance = heartbeat + Duration::from_secs(10);
-        let heartbeat_hard_tolerance = heartbeat + Duration::from_secs(30);
-        Self {
+        let now = Utc::now();
+        let mut event_loop = Self {
             decoder,
             input,
             heartbeat,
-            heartbeat_soft_tolerance,
-            heartbeat_hard_tolerance,
-            last_reset: Instant::now(),
-            last_heartbeat: Instant::now() - heartbeat / 2,
-        }
+            clock: Box::new(SystemClock),
+            last_reset: now,
+            last_heartbeat: now,
+            is_test_request_pending: false,
+            metrics: None,
+        };
+        event_loop.reset_timers();
+        event_loop
+    }
+
+    /// Sets the [`Clock`] which drives all timers, and restarts them. A
+    /// [`SystemClock`] is used by default.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Box::new(clock);
+        self.reset_timers();
+    }
+
+    /// Records inbound messages and missed heartbeats into `metrics`.
+    pub fn set_metrics(&mut self, metrics: SessionMetrics) {
+        self.metrics = Some(metrics);
+    }
+
+    fn reset_timers(&mut self) {
+        let now = self.clock.now();
+        let half_heartbeat = chrono::Duration::from_std(self.heartbeat / 2).ok();
+        self.last_reset = now;
+        self.last_heartbeat = half_heartbeat
+            .and_then(|half_heartbeat| now.checked_sub_signed(half_heartbeat))
+            .unwrap_or(now);
+        self.is_test_request_pending = false;
     }
 
     pub async fn next<'a>(&'a mut self) -> LlEvent<'a> {
@@ -55,6 +93,9 @@ where
                 match decoder {
                     Ok(decoder) => {
                         let msg = decoder.message();
+                        if let Some(metrics) = &self.metrics {
+                            metrics.on_inbound(&msg, self.clock.now());
+                        }
                         return LlEvent::Message { msg };
                     }
                     Err(err) => return LlEvent::IoError {
@@ -63,37 +104,64 @@ where
                 }
             },
             () = timer_heartbeat => {
-                self.last_heartbeat = Instant::now();
+                self.last_heartbeat = self.clock.now();
                 return LlEvent::Heartbeat;
             },
             () = timer_test_request => {
+                self.is_test_request_pending = true;
+                if let Some(metrics) = &self.metrics {
+                    metrics.on_heartbeat_timeout();
+                }
                 return LlEvent::TestRequest;
             },
             () = timer_logout => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.on_test_request_timeout();
+                }
                 return LlEvent::Logout;
             }
         }
     }
 
-    /// Resets the FIX counterparty's `Heartbeat <0>` -associated timers.
+    /// Resets the FIX counterparty's `Heartbeat <0>` -associated timers. This
+    /// should be called whenever the counterparty sends a message, as any
+    /// inbound message is proof that the counterparty is alive.
     pub fn ping_heartbeat(&mut self) {
-        self.last_reset = Instant::now();
+        self.last_reset = self.clock.now();
+        self.is_test_request_pending = false;
     }
 
-    fn timer_heartbeat(&self) -> Delay {
-        Delay::new(self.last_heartbeat + self.heartbeat - Instant::now())
+    fn timer_heartbeat(&self) -> BoxFuture<'static, ()> {
+        self.sleep(add_duration(self.last_heartbeat, self.heartbeat))
     }
 
-    fn timer_test_request(&self) -> Delay {
-        Delay::new(self.last_reset + self.heartbeat_soft_tolerance - Instant::now())
+    /// Only one `TestRequest <1>` is due until the timers are reset again.
+    fn timer_test_request(&self) -> Either<BoxFuture<'static, ()>, future::Pending<()>> {
+        if self.is_test_request_pending {
+            Either::Right(future::pending())
+        } else {
+            Either::Left(self.sleep(test_request_deadline(self.last_reset, self.heartbeat)))
+        }
     }
 
-    fn timer_logout(&self) -> Delay {
-        Delay::new(self.last_reset + self.heartbeat_hard_tolerance - Instant::now())
+    fn timer_logout(&self) -> BoxFuture<'static, ()> {
+        self.sleep(logout_deadline(self.last_reset, self.heartbeat))
+    }
+
+    /// Sleeps until `deadline`, or forever if it's beyond the range of
+    /// [`DateTime`].
+    fn sleep(&self, deadline: Option<DateTime<Utc>>) -> BoxFuture<'static, ()> {
+        match deadline {
+            Some(deadline) => self.clock.sleep_until(deadline),
+            None => Box::pin(future::pending()),
+        }
     }
 }
 
 /// A low level event produced by a [`LlEventLoop`].
+#[deprecated(
+    note = "use `FixConnection::start`, or `FixConnection::handle` to drive sessions manually"
+)]
 #[derive(Debug)]
 pub enum LlEvent<'a> {
     /// Incoming  FIX message.
@@ -124,7 +192,7 @@ where
         if buffer.is_empty() {
             return Ok(decoder); // FIXME
         }
-        input.read_exact(buffer).await.ok();
+        input.read_exact(buffer).await?;
         if let Ok(Some(())) = decoder.state() {
             return Ok(decoder);
         }
@@ -0,0 +1,211 @@
+use super::errs;
+use super::validator::{Validator, Violation};
+use crate::definitions::{fix44, HardCodedFixFieldDefinition};
+use crate::dict::{FieldLocation, FixDatatype, IsFieldDefinition};
+use crate::tagvalue::{Decoder, FieldAccess, Message};
+use crate::Dictionary;
+use std::collections::HashMap;
+
+// FIXT.1.1 fields, which aren't part of `fix44`.
+
+pub(crate) const APPL_VER_ID: &HardCodedFixFieldDefinition = &HardCodedFixFieldDefinition {
+    name: "ApplVerID",
+    tag: 1128,
+    is_group_leader: false,
+    data_type: FixDatatype::String,
+    location: FieldLocation::Header,
+};
+
+pub(crate) const DEFAULT_APPL_VER_ID: &HardCodedFixFieldDefinition = &HardCodedFixFieldDefinition {
+    name: "DefaultApplVerID",
+    tag: 1137,
+    is_group_leader: false,
+    data_type: FixDatatype::String,
+    location: FieldLocation::Body,
+};
+
+/// The version of the application layer carried over a FIXT.1.1 session, as
+/// encoded by `ApplVerID <1128>` and `DefaultApplVerID <1137>`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ApplVerId {
+    /// FIX 4.0, i.e. `ApplVerID <1128>` "2".
+    Fix40,
+    /// FIX 4.1, i.e. `ApplVerID <1128>` "3".
+    Fix41,
+    /// FIX 4.2, i.e. `ApplVerID <1128>` "4".
+    Fix42,
+    /// FIX 4.3, i.e. `ApplVerID <1128>` "5".
+    Fix43,
+    /// FIX 4.4, i.e. `ApplVerID <1128>` "6".
+    Fix44,
+    /// FIX 5.0, i.e. `ApplVerID <1128>` "7".
+    Fix50,
+    /// FIX 5.0 SP1, i.e. `ApplVerID <1128>` "8".
+    Fix50Sp1,
+    /// FIX 5.0 SP2, i.e. `ApplVerID <1128>` "9".
+    Fix50Sp2,
+}
+
+impl ApplVerId {
+    /// Parses the value of an `ApplVerID <1128>` field.
+    pub fn from_value(value: &[u8]) -> Option<Self> {
+        Some(match value {
+            b"2" => Self::Fix40,
+            b"3" => Self::Fix41,
+            b"4" => Self::Fix42,
+            b"5" => Self::Fix43,
+            b"6" => Self::Fix44,
+            b"7" => Self::Fix50,
+            b"8" => Self::Fix50Sp1,
+            b"9" => Self::Fix50Sp2,
+            _ => return None,
+        })
+    }
+
+    /// Returns the value of `ApplVerID <1128>` for `self`.
+    pub fn value(&self) -> &'static str {
+        match self {
+            Self::Fix40 => "2",
+            Self::Fix41 => "3",
+            Self::Fix42 => "4",
+            Self::Fix43 => "5",
+            Self::Fix44 => "6",
+            Self::Fix50 => "7",
+            Self::Fix50Sp1 => "8",
+            Self::Fix50Sp2 => "9",
+        }
+    }
+}
+
+/// The application dictionaries of a FIXT.1.1 session. Each application
+/// message is decoded again (and validated, if enabled) with the dictionary
+/// of its `ApplVerID <1128>`, or else of the default `ApplVerID` negotiated at
+/// Logon.
+#[derive(Debug, Default)]
+pub(crate) struct ApplVersions {
+    // Our own `DefaultApplVerID <1137>`.
+    default: Option<ApplVerId>,
+    // The counterparty's `DefaultApplVerID <1137>`, once logged on.
+    negotiated: Option<ApplVerId>,
+    decoders: HashMap<ApplVerId, (Decoder, Option<Validator>)>,
+}
+
+impl Clone for ApplVersions {
+    fn clone(&self) -> Self {
+        // Decoders aren't `Clone`, so they are created again from their
+        // dictionaries.
+        let decoders = self
+            .decoders
+            .iter()
+            .map(|(appl_ver_id, (decoder, validator))| {
+                let decoder = Decoder::new(decoder.dictionary().clone());
+                (*appl_ver_id, (decoder, validator.clone()))
+            })
+            .collect();
+        Self {
+            default: self.default,
+            negotiated: self.negotiated,
+            decoders,
+        }
+    }
+}
+
+impl ApplVersions {
+    pub fn set_default(&mut self, appl_ver_id: ApplVerId) {
+        self.default = Some(appl_ver_id);
+    }
+
+    pub fn add(&mut self, appl_ver_id: ApplVerId, dictionary: Dictionary) {
+        self.decoders
+            .insert(appl_ver_id, (Decoder::new(dictionary), None));
+    }
+
+    /// Returns our own `DefaultApplVerID <1137>`, to be sent at Logon.
+    pub fn default_appl_ver_id(&self) -> Option<ApplVerId> {
+        self.default
+    }
+
+    /// Returns `true` if application messages must be decoded with their own
+    /// dictionaries, rather than with the session dictionary.
+    pub fn is_enabled(&self) -> bool {
+        !self.decoders.is_empty()
+    }
+
+    /// Enables or disables validation of application messages. The header and
+    /// trailer are validated against the `transport` dictionary.
+    pub fn set_validation(&mut self, transport: Option<&Dictionary>) {
+        for (decoder, validator) in self.decoders.values_mut() {
+            *validator = transport.map(|transport| {
+                Validator::with_transport(decoder.dictionary().clone(), transport.clone())
+            });
+        }
+    }
+
+    /// Records the `DefaultApplVerID <1137>` of the counterparty's
+    /// `Logon <A>`. On failure, the text of the `Logout <5>` to send is
+    /// returned instead.
+    pub fn on_logon(&mut self, logon: &Message<&[u8]>) -> Result<(), String> {
+        self.negotiated = None;
+        let value = match logon.fv_raw(DEFAULT_APPL_VER_ID) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        match ApplVerId::from_value(value) {
+            Some(appl_ver_id) if !self.is_enabled() || self.decoders.contains_key(&appl_ver_id) => {
+                self.negotiated = Some(appl_ver_id);
+                Ok(())
+            }
+            _ => Err(errs::unsupported_appl_ver_id()),
+        }
+    }
+
+    /// Decodes `msg` with the dictionary of its application version. Messages
+    /// with an unknown or unsupported `ApplVerID <1128>` are refused.
+    pub fn decode<'a>(
+        &'a mut self,
+        msg: &'a Message<&[u8]>,
+    ) -> Result<(Message<'a, &'a [u8]>, Option<&'a mut Validator>), Violation> {
+        let appl_ver_id = match msg.fv_raw(APPL_VER_ID) {
+            Some(value) => ApplVerId::from_value(value),
+            None => self.negotiated.or(self.default),
+        };
+        let unsupported = || Violation {
+            reason: fix44::SessionRejectReason::ValueIsIncorrect as u32,
+            ref_tag: Some(APPL_VER_ID.tag().get().into()),
+            text: errs::unsupported_appl_ver_id(),
+        };
+        let (decoder, validator) = appl_ver_id
+            .and_then(move |appl_ver_id| self.decoders.get_mut(&appl_ver_id))
+            .ok_or_else(unsupported)?;
+        let msg = decoder.decode(msg.as_bytes()).map_err(|_| Violation {
+            reason: fix44::SessionRejectReason::Other as u32,
+            ref_tag: None,
+            text: errs::invalid_msg_type(),
+        })?;
+        Ok((msg, validator.as_mut()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn appl_ver_id_values_roundtrip() {
+        let all = [
+            ApplVerId::Fix40,
+            ApplVerId::Fix41,
+            ApplVerId::Fix42,
+            ApplVerId::Fix43,
+            ApplVerId::Fix44,
+            ApplVerId::Fix50,
+            ApplVerId::Fix50Sp1,
+            ApplVerId::Fix50Sp2,
+        ];
+        for appl_ver_id in all.iter() {
+            let value = appl_ver_id.value().as_bytes();
+            assert_eq!(ApplVerId::from_value(value), Some(*appl_ver_id));
+        }
+        assert_eq!(ApplVerId::from_value(b"10"), None);
+    }
+}
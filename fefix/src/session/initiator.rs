@@ -0,0 +1,543 @@
+use super::connection::add_duration;
+use super::{Backend, Clock, FixConnection};
+use crate::tagvalue::Decoder;
+use crate::Dictionary;
+use futures::{AsyncRead, AsyncWrite};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::marker::Unpin;
+use std::time::Duration;
+
+/// The network address of a FIX counterparty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    /// Host name or IP address.
+    pub host: String,
+    /// TCP port.
+    pub port: u16,
+}
+
+impl Endpoint {
+    /// Creates a new [`Endpoint`] from `host` and `port`.
+    pub fn new<S>(host: S, port: u16) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+///
+/// The n-th consecutive failed attempt is followed by a delay of `initial *
+/// multiplier^(n-1)`, capped at `max`. Sessions which end within
+/// [`Backoff::set_min_uptime`] of their Logon count as failed attempts too,
+/// so that a counterparty which drops the connection right after Logon isn't
+/// flooded with reconnections. Longer sessions reset the delay, but they're
+/// still followed by `initial` before reconnecting.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: u32,
+    max_attempts: Option<u32>,
+    min_uptime: Duration,
+}
+
+impl Backoff {
+    /// Sets the delay after the first failed attempt. One second by default.
+    pub fn set_initial(&mut self, initial: Duration) {
+        self.initial = initial;
+    }
+
+    /// Sets the upper bound of all delays. One minute by default.
+    pub fn set_max(&mut self, max: Duration) {
+        self.max = max;
+    }
+
+    /// Sets the factor by which delays grow after each failed attempt. 2 by
+    /// default.
+    pub fn set_multiplier(&mut self, multiplier: u32) {
+        self.multiplier = multiplier;
+    }
+
+    /// Gives up after `max_attempts` consecutive failed attempts. By default,
+    /// reconnection is attempted forever.
+    pub fn set_max_attempts(&mut self, max_attempts: u32) {
+        self.max_attempts = Some(max_attempts);
+    }
+
+    /// Sets how long a session must last for its attempt to be successful,
+    /// which resets the delay. Ten seconds by default.
+    pub fn set_min_uptime(&mut self, min_uptime: Duration) {
+        self.min_uptime = min_uptime;
+    }
+
+    /// Returns the delay that follows `failures` consecutive failed attempts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fefix::session::Backoff;
+    /// use std::time::Duration;
+    ///
+    /// let backoff = Backoff::default();
+    /// assert_eq!(backoff.delay(0), Duration::from_secs(0));
+    /// assert_eq!(backoff.delay(1), Duration::from_secs(1));
+    /// assert_eq!(backoff.delay(3), Duration::from_secs(4));
+    /// assert_eq!(backoff.delay(100), Duration::from_secs(60));
+    /// ```
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::default();
+        }
+        let factor = self
+            .multiplier
+            .checked_pow(failures - 1)
+            .unwrap_or(u32::MAX);
+        self.initial
+            .checked_mul(factor)
+            .map(|delay| delay.min(self.max))
+            .unwrap_or(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+            max_attempts: None,
+            min_uptime: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A supervisor for initiator [`FixConnection`]s which reconnects whenever
+/// the transport drops.
+///
+/// Endpoints are tried in the order they were added, i.e. primary endpoint
+/// first and backup endpoints later. Each failed attempt moves on to the next
+/// endpoint and waits according to [`Backoff`]; once a session is over, the
+/// primary endpoint is tried again. The same [`FixConnection`] is used
+/// throughout, so seq. numbers are preserved across connections. Delays and
+/// session durations are measured with its [`Clock`](super::Clock).
+///
+/// [`FixInitiator`] is executor-agnostic: opening connections is delegated to
+/// a user-provided function.
+#[derive(Debug)]
+pub struct FixInitiator<B> {
+    connection: FixConnection,
+    backend: B,
+    dictionary: Dictionary,
+    endpoints: Vec<Endpoint>,
+    backoff: Backoff,
+}
+
+impl<B> FixInitiator<B>
+where
+    B: Backend,
+{
+    /// Creates a new [`FixInitiator`] with no endpoints. Inbound messages will
+    /// be decoded according to `dictionary`.
+    pub fn new(connection: FixConnection, backend: B, dictionary: Dictionary) -> Self {
+        Self {
+            connection,
+            backend,
+            dictionary,
+            endpoints: Vec::new(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Adds a new [`Endpoint`] after all existing ones. The first endpoint
+    /// is the primary one.
+    pub fn add_endpoint(&mut self, endpoint: Endpoint) {
+        self.endpoints.push(endpoint);
+    }
+
+    /// Sets the [`Backoff`] policy between reconnection attempts.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// Returns an immutable reference to the supervised [`FixConnection`].
+    pub fn connection(&self) -> &FixConnection {
+        &self.connection
+    }
+
+    /// Connects to the counterparty via `connect` and keeps reconnecting,
+    /// after the delay of the [`Backoff`], whenever the session is over. Each
+    /// attempt is reported to the
+    /// [`Backend`] via [`Backend::on_connection_attempt`]. Connections with a
+    /// [`Schedule`](super::Schedule) are only opened once the session starts.
+    ///
+    /// This only returns once [`Backoff::set_max_attempts`] consecutive
+    /// attempts have failed, with the last error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no endpoint was added.
+    pub async fn run<C, F, I, O>(&mut self, mut connect: C) -> io::Error
+    where
+        C: FnMut(&Endpoint) -> F,
+        F: Future<Output = io::Result<(I, O)>>,
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        assert!(!self.endpoints.is_empty(), "No endpoints to connect to");
+        let mut index = 0;
+        let mut failures = 0;
+        loop {
+            if let Some(schedule) = self.connection.schedule() {
+                let now = self.connection.clock().now();
+                sleep(self.connection.clock(), schedule.time_until_start(now)).await;
+            }
+            let endpoint = &self.endpoints[index % self.endpoints.len()];
+            let error = match connect(endpoint).await {
+                Ok((input, output)) => {
+                    self.backend.on_connection_attempt(endpoint, Ok(())).ok();
+                    let decoder = Decoder::new(self.dictionary.clone());
+                    let backend = self.backend.clone();
+                    let connected_at = self.connection.clock().now();
+                    if self.connection.start(backend, input, output, decoder).await {
+                        let uptime = (self.connection.clock().now() - connected_at)
+                            .to_std()
+                            .unwrap_or_default();
+                        if uptime >= self.backoff.min_uptime {
+                            index = 0;
+                            failures = 0;
+                            sleep(self.connection.clock(), self.backoff.delay(1)).await;
+                            continue;
+                        }
+                        io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "Session ended right after Logon",
+                        )
+                    } else {
+                        io::Error::new(io::ErrorKind::ConnectionRefused, "Logon failed")
+                    }
+                }
+                Err(err) => {
+                    self.backend.on_connection_attempt(endpoint, Err(&err)).ok();
+                    err
+                }
+            };
+            failures += 1;
+            if matches!(self.backoff.max_attempts, Some(max) if failures >= max) {
+                return error;
+            }
+            index += 1;
+            sleep(self.connection.clock(), self.backoff.delay(failures)).await;
+        }
+    }
+}
+
+async fn sleep(clock: &dyn Clock, duration: Duration) {
+    match add_duration(clock.now(), duration) {
+        Some(deadline) => clock.sleep_until(deadline).await,
+        // Too far in the future to ever come.
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::fix44;
+    use crate::session::{FixConnectionBuilder, MockClock, Schedule};
+    use crate::tagvalue::{Encoder, FieldAccess, Message};
+    use chrono::{DateTime, Utc};
+    use futures::future::BoxFuture;
+    use std::ops::Range;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        attempts: Arc<Mutex<Vec<(String, bool)>>>,
+    }
+
+    impl Backend for Recorder {
+        type Error = ();
+
+        fn on_inbound_app_message(&mut self, _message: Message<&[u8]>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_outbound_message(&mut self, _message: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_resend_request(&mut self, _range: Range<u64>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_connection_attempt(
+            &mut self,
+            endpoint: &Endpoint,
+            result: Result<(), &io::Error>,
+        ) -> Result<(), Self::Error> {
+            let mut attempts = self.attempts.lock().unwrap();
+            attempts.push((endpoint.to_string(), result.is_ok()));
+            Ok(())
+        }
+    }
+
+    /// An [`AsyncWrite`] which collects all written bytes.
+    #[derive(Debug, Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl AsyncWrite for Output {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// A [`MockClock`] which jumps straight to the deadline of each sleep,
+    /// recording how long it would have lasted.
+    #[derive(Debug, Clone)]
+    struct Sleepless {
+        clock: MockClock,
+        sleeps: Arc<Mutex<Vec<chrono::Duration>>>,
+    }
+
+    impl Clock for Sleepless {
+        fn now(&self) -> DateTime<Utc> {
+            self.clock.now()
+        }
+
+        fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'static, ()> {
+            self.sleeps.lock().unwrap().push(deadline - self.now());
+            self.clock.set(deadline);
+            Box::pin(futures::future::ready(()))
+        }
+    }
+
+    fn logon(msg_seq_num: u64) -> Vec<u8> {
+        let mut encoder = Encoder::<crate::tagvalue::Config>::default();
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(b"FIX.4.4", &mut buffer, b"A");
+        msg.set(fix44::SENDER_COMP_ID, "TARGET");
+        msg.set(fix44::TARGET_COMP_ID, "SENDER");
+        msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+        msg.set(fix44::SENDING_TIME, chrono::Utc::now());
+        msg.set(fix44::ENCRYPT_METHOD, fix44::EncryptMethod::None);
+        msg.set(fix44::HEART_BT_INT, 30u64);
+        msg.wrap().to_vec()
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let mut backoff = Backoff::default();
+        backoff.set_initial(Duration::from_millis(100));
+        backoff.set_multiplier(3);
+        backoff.set_max(Duration::from_secs(1));
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(300));
+        assert_eq!(backoff.delay(3), Duration::from_millis(900));
+        assert_eq!(backoff.delay(4), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_sleeps_on_the_connection_clock() {
+        let clock = Sleepless {
+            clock: MockClock::new(Utc::now()),
+            sleeps: Arc::default(),
+        };
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_clock(clock.clone());
+        let mut initiator =
+            FixInitiator::new(builder.build(), Recorder::default(), Dictionary::fix44());
+        initiator.add_endpoint(Endpoint::new("down", 1));
+        let mut backoff = Backoff::default();
+        backoff.set_max_attempts(4);
+        initiator.set_backoff(backoff);
+        futures::executor::block_on(initiator.run(|_endpoint| {
+            let result: io::Result<(futures::io::Cursor<Vec<u8>>, Output)> =
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+            futures::future::ready(result)
+        }));
+        let sleeps = clock.sleeps.lock().unwrap().clone();
+        let expected: Vec<_> = [1, 2, 4]
+            .iter()
+            .map(|secs| chrono::Duration::seconds(*secs))
+            .collect();
+        assert_eq!(sleeps, expected);
+    }
+
+    #[test]
+    fn scheduled_sessions_connect_once_they_start() {
+        let start = Utc::now();
+        let clock = Sleepless {
+            clock: MockClock::new(start),
+            sleeps: Arc::default(),
+        };
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_clock(clock.clone());
+        let in_one_hour = (start + chrono::Duration::hours(1)).time();
+        builder.set_schedule(Schedule::daily(
+            in_one_hour,
+            in_one_hour + chrono::Duration::hours(1),
+        ));
+        let mut initiator =
+            FixInitiator::new(builder.build(), Recorder::default(), Dictionary::fix44());
+        initiator.add_endpoint(Endpoint::new("venue", 1));
+        let mut backoff = Backoff::default();
+        backoff.set_max_attempts(1);
+        initiator.set_backoff(backoff);
+        let mut connected_at = None;
+        futures::executor::block_on(initiator.run(|_endpoint| {
+            connected_at = Some(clock.now());
+            let result: io::Result<(futures::io::Cursor<Vec<u8>>, Output)> =
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+            futures::future::ready(result)
+        }));
+        assert_eq!(connected_at, Some(start + chrono::Duration::hours(1)));
+    }
+
+    #[test]
+    fn sessions_dropped_right_after_logon_are_failed_attempts() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        let backend = Recorder::default();
+        let mut initiator =
+            FixInitiator::new(builder.build(), backend.clone(), Dictionary::fix44());
+        initiator.add_endpoint(Endpoint::new("flaky", 1));
+        let mut backoff = Backoff::default();
+        backoff.set_initial(Duration::from_millis(1));
+        backoff.set_max_attempts(3);
+        initiator.set_backoff(backoff);
+        let mut msg_seq_num = 0;
+        let error = futures::executor::block_on(initiator.run(|_endpoint| {
+            // The counterparty accepts each Logon and hangs up right away.
+            msg_seq_num += 1;
+            let input = futures::io::Cursor::new(logon(msg_seq_num));
+            futures::future::ready(Ok((input, Output::default())))
+        }));
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(backend.attempts.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn sessions_are_followed_by_a_delay_too() {
+        let clock = Sleepless {
+            clock: MockClock::new(Utc::now()),
+            sleeps: Arc::default(),
+        };
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        builder.set_clock(clock.clone());
+        let backend = Recorder::default();
+        let mut initiator =
+            FixInitiator::new(builder.build(), backend.clone(), Dictionary::fix44());
+        initiator.add_endpoint(Endpoint::new("venue", 1));
+        let mut backoff = Backoff::default();
+        backoff.set_max_attempts(2);
+        initiator.set_backoff(backoff);
+        let mut logons = vec![logon(1)];
+        futures::executor::block_on(initiator.run(|_endpoint| {
+            let result = match logons.pop() {
+                Some(logon) => Ok((futures::io::Cursor::new(logon), Output::default())),
+                None => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            };
+            futures::future::ready(result)
+        }));
+        assert_eq!(backend.attempts.lock().unwrap().len(), 3);
+        // The session outlives its minimum uptime thanks to its own timers,
+        // which sleep first.
+        let sleeps = clock.sleeps.lock().unwrap().clone();
+        let expected: Vec<_> = [1, 1]
+            .iter()
+            .map(|secs| chrono::Duration::seconds(*secs))
+            .collect();
+        assert_eq!(sleeps[sleeps.len() - 2..], expected[..]);
+    }
+
+    #[test]
+    fn failover_to_backup_preserves_seq_numbers() {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SENDER");
+        builder.set_target_comp_id("TARGET");
+        let backend = Recorder::default();
+        let mut initiator =
+            FixInitiator::new(builder.build(), backend.clone(), Dictionary::fix44());
+        initiator.add_endpoint(Endpoint::new("primary", 1));
+        initiator.add_endpoint(Endpoint::new("backup", 2));
+        let mut backoff = Backoff::default();
+        backoff.set_initial(Duration::from_millis(1));
+        backoff.set_max_attempts(3);
+        // Sessions are short-lived here, but they still count as successful.
+        backoff.set_min_uptime(Duration::from_secs(0));
+        initiator.set_backoff(backoff);
+        let output = Output::default();
+        let mut logons = vec![logon(2), logon(1)];
+        let error = futures::executor::block_on(initiator.run(|endpoint| {
+            // The primary endpoint is down, while the backup one accepts two
+            // sessions and then goes down as well.
+            let logon = match endpoint.host.as_str() {
+                "backup" => logons.pop(),
+                _ => None,
+            };
+            let result = match logon {
+                Some(logon) => Ok((futures::io::Cursor::new(logon), output.clone())),
+                None => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            };
+            futures::future::ready(result)
+        }));
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        let attempts = backend.attempts.lock().unwrap().clone();
+        let expected = vec![
+            ("primary:1", false),
+            ("backup:2", true),
+            ("primary:1", false),
+            ("backup:2", true),
+            ("primary:1", false),
+            ("backup:2", false),
+            ("primary:1", false),
+        ];
+        let expected: Vec<(String, bool)> = expected
+            .into_iter()
+            .map(|(endpoint, ok)| (endpoint.to_string(), ok))
+            .collect();
+        assert_eq!(attempts, expected);
+        // Both our Logons were sent, one after the other.
+        let output = output.0.lock().unwrap();
+        let mut decoder = Decoder::<crate::tagvalue::Config>::new(Dictionary::fix44());
+        let i = output.windows(4).position(|w| w == b"\x0110=").unwrap() + 8;
+        let second_logon = decoder.decode(&output[i..]).unwrap();
+        assert_eq!(second_logon.fv::<u64, _>(fix44::MSG_SEQ_NUM), Ok(2));
+    }
+}
@@ -0,0 +1,291 @@
+use super::SessionId;
+use crate::definitions::{fix44, HardCodedFixFieldDefinition};
+use crate::dict::IsFieldDefinition;
+use crate::tagvalue::{FieldAccess, Message};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A sink for the audit trail of a FIX session, i.e. all inbound and
+/// outbound messages together with session events (logons, logouts,
+/// rejects, resends, disconnects, etc.).
+///
+/// [`FixConnection`](super::FixConnection) calls it with raw messages exactly
+/// as they go over the wire, and timestamps everything with the time of the
+/// input that caused it. Errors are ignored by the connection.
+pub trait SessionLog: Debug + Send {
+    /// Records a message received from the counterparty.
+    fn on_incoming(&mut self, now: DateTime<Utc>, message: &[u8]) -> io::Result<()>;
+
+    /// Records a message sent to the counterparty.
+    fn on_outgoing(&mut self, now: DateTime<Utc>, message: &[u8]) -> io::Result<()>;
+
+    /// Records a human-readable session event.
+    fn on_event(&mut self, now: DateTime<Utc>, text: &str) -> io::Result<()>;
+}
+
+/// A [`SessionLog`] which writes log files in the same layout as QuickFIX,
+/// so that existing tooling can parse them.
+///
+/// Messages go to `<BeginString>-<SenderCompID>-<TargetCompID>.messages.current.log`
+/// and events to `<BeginString>-<SenderCompID>-<TargetCompID>.event.current.log`,
+/// one line each, prefixed by a UTC timestamp (e.g. `20210301-12:00:00.000 :
+/// `). Both files are rotated daily: at the first line of a new UTC day, the
+/// current files are renamed by replacing `current` with the date of their
+/// contents, e.g. `FIX.4.4-SENDER-TARGET.messages.20210301.log`.
+#[derive(Debug)]
+pub struct FileLog {
+    messages: LogFile,
+    events: LogFile,
+}
+
+impl FileLog {
+    /// Opens (or creates) the log files of `session_id` within `dir`, which
+    /// is created if needed.
+    pub fn open<P>(dir: P, session_id: &SessionId) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let prefix = format!(
+            "{}-{}-{}",
+            session_id.begin_string, session_id.sender_comp_id, session_id.target_comp_id
+        );
+        Ok(Self {
+            messages: LogFile::open(dir, format!("{}.messages", prefix))?,
+            events: LogFile::open(dir, format!("{}.event", prefix))?,
+        })
+    }
+}
+
+impl SessionLog for FileLog {
+    fn on_incoming(&mut self, now: DateTime<Utc>, message: &[u8]) -> io::Result<()> {
+        self.messages.write_line(now, message)
+    }
+
+    fn on_outgoing(&mut self, now: DateTime<Utc>, message: &[u8]) -> io::Result<()> {
+        self.messages.write_line(now, message)
+    }
+
+    fn on_event(&mut self, now: DateTime<Utc>, text: &str) -> io::Result<()> {
+        self.events.write_line(now, text.as_bytes())
+    }
+}
+
+#[derive(Debug)]
+struct LogFile {
+    dir: PathBuf,
+    // E.g. `FIX.4.4-SENDER-TARGET.messages`.
+    name: String,
+    file: File,
+    // The UTC day of the last line, if any.
+    day: Option<NaiveDate>,
+}
+
+impl LogFile {
+    fn open(dir: &Path, name: String) -> io::Result<Self> {
+        let path = dir.join(format!("{}.current.log", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // Lines left over by a previous run belong to the day of the last
+        // modification.
+        let day = if metadata.len() > 0 {
+            let modified: DateTime<Utc> = metadata.modified()?.into();
+            Some(modified.date().naive_utc())
+        } else {
+            None
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            name,
+            file,
+            day,
+        })
+    }
+
+    fn write_line(&mut self, now: DateTime<Utc>, line: &[u8]) -> io::Result<()> {
+        let today = now.date().naive_utc();
+        match self.day {
+            Some(day) if day != today => self.rotate(day)?,
+            _ => {}
+        }
+        self.day = Some(today);
+        let mut buffer = now
+            .format("%Y%m%d-%H:%M:%S%.3f : ")
+            .to_string()
+            .into_bytes();
+        buffer.extend_from_slice(line);
+        buffer.push(b'\n');
+        self.file.write_all(&buffer)?;
+        self.file.flush()
+    }
+
+    /// Moves the contents of the current file to the backup of `day`,
+    /// appending to it if it exists already.
+    fn rotate(&mut self, day: NaiveDate) -> io::Result<()> {
+        let current = self.dir.join(format!("{}.current.log", self.name));
+        let backup = self
+            .dir
+            .join(format!("{}.{}.log", self.name, day.format("%Y%m%d")));
+        if backup.exists() {
+            let contents = fs::read(&current)?;
+            OpenOptions::new()
+                .append(true)
+                .open(&backup)?
+                .write_all(&contents)?;
+            fs::remove_file(&current)?;
+        } else {
+            fs::rename(&current, &backup)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&current)?;
+        Ok(())
+    }
+}
+
+/// Describes the session-level meaning of an inbound message, if any.
+pub(crate) fn inbound_event(msg: &Message<&[u8]>) -> Option<String> {
+    let field = |field| {
+        msg.fv_raw(field)
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    };
+    let msg_type = msg.fv_raw(fix44::MSG_TYPE)?;
+    let seq_num = field(fix44::MSG_SEQ_NUM).unwrap_or_default();
+    describe(msg_type, &seq_num, false, field)
+}
+
+/// Describes the session-level meaning of an outbound message, if any.
+pub(crate) fn outbound_event(message: &[u8]) -> Option<String> {
+    let field = |tag: &str| {
+        message
+            .split(|byte| *byte == 0x1)
+            .find_map(|field| field.strip_prefix(tag.as_bytes())?.strip_prefix(b"="))
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    };
+    let msg_type = field("35")?;
+    let seq_num = field("34").unwrap_or_default();
+    describe(msg_type.as_bytes(), &seq_num, true, |f| {
+        field(&f.tag().get().to_string())
+    })
+}
+
+fn describe<F>(msg_type: &[u8], seq_num: &str, outbound: bool, field: F) -> Option<String>
+where
+    F: Fn(&'static HardCodedFixFieldDefinition) -> Option<String>,
+{
+    let verb = if outbound { "Sent" } else { "Received" };
+    let text = || {
+        field(fix44::TEXT)
+            .map(|text| format!(": {}", text))
+            .unwrap_or_default()
+    };
+    let value = |f| field(f).unwrap_or_default();
+    Some(match msg_type {
+        b"A" => format!("{} Logon (MsgSeqNum={})", verb, seq_num),
+        b"5" => format!("{} Logout (MsgSeqNum={}){}", verb, seq_num, text()),
+        b"3" => format!(
+            "{} Reject of message {} (MsgSeqNum={}){}",
+            verb,
+            value(fix44::REF_SEQ_NUM),
+            seq_num,
+            text()
+        ),
+        b"j" => format!(
+            "{} BusinessMessageReject of message {} (MsgSeqNum={}){}",
+            verb,
+            value(fix44::REF_SEQ_NUM),
+            seq_num,
+            text()
+        ),
+        b"2" => format!(
+            "{} ResendRequest FROM: {} TO: {}",
+            verb,
+            value(fix44::BEGIN_SEQ_NO),
+            value(fix44::END_SEQ_NO)
+        ),
+        b"4" => {
+            let kind = if field(fix44::GAP_FILL_FLAG).as_deref() == Some("Y") {
+                "SequenceReset-GapFill"
+            } else {
+                "SequenceReset-Reset"
+            };
+            format!(
+                "{} {} FROM: {} TO: {}",
+                verb,
+                kind,
+                seq_num,
+                value(fix44::NEW_SEQ_NO)
+            )
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::test_utils::session_id;
+    use chrono::TimeZone;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fefix-log-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn lines_are_timestamped_in_quickfix_layout() {
+        let dir = temp_dir("layout");
+        let mut log = FileLog::open(&dir, &session_id()).unwrap();
+        let now = Utc.ymd(2021, 3, 1).and_hms_milli(12, 0, 0, 42);
+        log.on_outgoing(now, b"8=FIX.4.4\x0135=0\x01").unwrap();
+        log.on_event(now, "Sent Logout (MsgSeqNum=2)").unwrap();
+        let messages = fs::read(dir.join("FIX.4.4-SENDER-TARGET.messages.current.log")).unwrap();
+        assert_eq!(
+            &messages[..],
+            &b"20210301-12:00:00.042 : 8=FIX.4.4\x0135=0\x01\n"[..]
+        );
+        let events =
+            fs::read_to_string(dir.join("FIX.4.4-SENDER-TARGET.event.current.log")).unwrap();
+        assert_eq!(
+            events,
+            "20210301-12:00:00.042 : Sent Logout (MsgSeqNum=2)\n"
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn files_are_rotated_daily() {
+        let dir = temp_dir("rotation");
+        let mut log = FileLog::open(&dir, &session_id()).unwrap();
+        log.on_event(Utc.ymd(2021, 3, 1).and_hms(23, 59, 59), "foo")
+            .unwrap();
+        log.on_event(Utc.ymd(2021, 3, 2).and_hms(0, 0, 1), "bar")
+            .unwrap();
+        let backup =
+            fs::read_to_string(dir.join("FIX.4.4-SENDER-TARGET.event.20210301.log")).unwrap();
+        assert_eq!(backup, "20210301-23:59:59.000 : foo\n");
+        let current =
+            fs::read_to_string(dir.join("FIX.4.4-SENDER-TARGET.event.current.log")).unwrap();
+        assert_eq!(current, "20210302-00:00:01.000 : bar\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn outbound_events_describe_admin_messages() {
+        assert_eq!(
+            outbound_event(b"8=FIX.4.4\x0135=2\x0134=3\x017=5\x0116=0\x01").as_deref(),
+            Some("Sent ResendRequest FROM: 5 TO: 0")
+        );
+        assert_eq!(
+            outbound_event(b"8=FIX.4.4\x0135=4\x0134=5\x01123=Y\x0136=9\x01").as_deref(),
+            Some("Sent SequenceReset-GapFill FROM: 5 TO: 9")
+        );
+        assert_eq!(outbound_event(b"8=FIX.4.4\x0135=D\x0134=5\x01"), None);
+    }
+}
@@ -0,0 +1,405 @@
+use super::{Clock, SeqNumbers, SystemClock};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Persistent storage for outbound FIX messages and seq. numbers of a FIX
+/// session.
+///
+/// Outbound messages are indexed by `MsgSeqNum <34>`, so that they can be
+/// replayed in response to a `ResendRequest <2>`.
+pub trait MessageStore: Debug {
+    /// Persists the outbound `message` with seq. number `msg_seq_num`.
+    fn set(&mut self, msg_seq_num: u64, message: &[u8]) -> io::Result<()>;
+
+    /// Returns all persisted outbound messages with a seq. number within
+    /// `range`, sorted by seq. number. Seq. numbers without an associated
+    /// message are skipped.
+    fn get(&self, range: Range<u64>) -> io::Result<Vec<(u64, Vec<u8>)>>;
+
+    /// Returns the next inbound and outbound seq. numbers.
+    fn seq_numbers(&self) -> SeqNumbers;
+
+    /// Persists the next inbound and outbound seq. numbers.
+    fn set_seq_numbers(&mut self, seq_numbers: SeqNumbers) -> io::Result<()>;
+
+    /// Returns the time at which `self` was created or last reset, i.e. the
+    /// start of the FIX session it belongs to.
+    fn creation_time(&self) -> DateTime<Utc>;
+
+    /// Erases all messages and resets seq. numbers to 1.
+    fn reset(&mut self) -> io::Result<()>;
+}
+
+/// A volatile [`MessageStore`] which keeps everything in memory.
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    messages: BTreeMap<u64, Vec<u8>>,
+    seq_numbers: SeqNumbers,
+    creation_time: DateTime<Utc>,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemoryStore {
+    /// Creates an empty [`MemoryStore`] starting from `seq_numbers`.
+    pub fn new(seq_numbers: SeqNumbers) -> Self {
+        Self::with_clock(seq_numbers, SystemClock)
+    }
+
+    /// Creates an empty [`MemoryStore`] starting from `seq_numbers`, which
+    /// reads its creation and reset times from `clock`.
+    pub fn with_clock<C>(seq_numbers: SeqNumbers, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        Self {
+            messages: BTreeMap::new(),
+            seq_numbers,
+            creation_time: clock.now(),
+            clock: Arc::new(clock),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(SeqNumbers::default())
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn set(&mut self, msg_seq_num: u64, message: &[u8]) -> io::Result<()> {
+        self.messages.insert(msg_seq_num, message.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, range: Range<u64>) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        Ok(self
+            .messages
+            .range(range)
+            .map(|(msg_seq_num, message)| (*msg_seq_num, message.clone()))
+            .collect())
+    }
+
+    fn seq_numbers(&self) -> SeqNumbers {
+        self.seq_numbers
+    }
+
+    fn set_seq_numbers(&mut self, seq_numbers: SeqNumbers) -> io::Result<()> {
+        self.seq_numbers = seq_numbers;
+        Ok(())
+    }
+
+    fn creation_time(&self) -> DateTime<Utc> {
+        self.creation_time
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.messages.clear();
+        self.seq_numbers = SeqNumbers::default();
+        self.creation_time = self.clock.now();
+        Ok(())
+    }
+}
+
+/// An append-only, file-backed [`MessageStore`] which survives process
+/// restarts.
+///
+/// The on-disk layout follows QuickFIX's `FileStore`. Given a path prefix
+/// (e.g. `store/FIX.4.4-SENDER-TARGET`), four files are used:
+///
+/// - `<prefix>.body`, with all outbound messages one after the other.
+/// - `<prefix>.header`, with a `MsgSeqNum,offset,size` entry for each message
+///   in `<prefix>.body`.
+/// - `<prefix>.seqnums`, with the next outbound and inbound seq. numbers (in
+///   this order) separated by `" : "`.
+/// - `<prefix>.session`, with the creation time of the store as a UTC
+///   timestamp (e.g. `20210301-08:00:00`).
+#[derive(Debug)]
+pub struct FileStore {
+    body: File,
+    header: File,
+    seqnums: File,
+    session: File,
+    body_len: u64,
+    index: BTreeMap<u64, (u64, usize)>,
+    seq_numbers: SeqNumbers,
+    creation_time: DateTime<Utc>,
+    clock: Arc<dyn Clock>,
+}
+
+impl FileStore {
+    /// Opens the [`FileStore`] with the given path `prefix`, creating its files
+    /// if they don't exist yet. Existing messages and seq. numbers are loaded
+    /// from disk.
+    pub fn open<P>(prefix: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::open_with_clock(prefix, SystemClock)
+    }
+
+    /// Like [`FileStore::open`], but the creation and reset times of the
+    /// store are read from `clock`.
+    pub fn open_with_clock<P, C>(prefix: P, clock: C) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        C: Clock + 'static,
+    {
+        let prefix = prefix.as_ref();
+        if let Some(parent) = prefix.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let body = open_rw(&with_extension(prefix, "body"), true)?;
+        let mut header = open_rw(&with_extension(prefix, "header"), true)?;
+        let mut seqnums = open_rw(&with_extension(prefix, "seqnums"), false)?;
+        let mut session = open_rw(&with_extension(prefix, "session"), false)?;
+        let body_len = body.metadata()?.len();
+        let index = {
+            let mut contents = String::new();
+            header.read_to_string(&mut contents)?;
+            parse_header(&contents)?
+        };
+        let seq_numbers = {
+            let mut contents = String::new();
+            seqnums.read_to_string(&mut contents)?;
+            if contents.trim().is_empty() {
+                SeqNumbers::default()
+            } else {
+                parse_seqnums(&contents)?
+            }
+        };
+        let creation_time = {
+            let mut contents = String::new();
+            session.read_to_string(&mut contents)?;
+            if contents.trim().is_empty() {
+                None
+            } else {
+                Some(parse_creation_time(&contents)?)
+            }
+        };
+        let mut store = Self {
+            body,
+            header,
+            seqnums,
+            session,
+            body_len,
+            index,
+            seq_numbers,
+            creation_time: creation_time.unwrap_or_else(|| clock.now()),
+            clock: Arc::new(clock),
+        };
+        if creation_time.is_none() {
+            store.write_creation_time()?;
+        }
+        Ok(store)
+    }
+
+    fn write_creation_time(&mut self) -> io::Result<()> {
+        let contents = self.creation_time.format(CREATION_TIME_FORMAT).to_string();
+        self.session.seek(SeekFrom::Start(0))?;
+        self.session.write_all(contents.as_bytes())?;
+        self.session.set_len(contents.len() as u64)?;
+        self.session.flush()
+    }
+
+    fn write_seqnums(&mut self) -> io::Result<()> {
+        let contents = format!(
+            "{:010} : {:010}",
+            self.seq_numbers.next_outbound(),
+            self.seq_numbers.next_inbound()
+        );
+        self.seqnums.seek(SeekFrom::Start(0))?;
+        self.seqnums.write_all(contents.as_bytes())?;
+        self.seqnums.set_len(contents.len() as u64)?;
+        self.seqnums.flush()
+    }
+}
+
+impl MessageStore for FileStore {
+    fn set(&mut self, msg_seq_num: u64, message: &[u8]) -> io::Result<()> {
+        let offset = self.body_len;
+        self.body.write_all(message)?;
+        self.body.flush()?;
+        let entry = format!("{},{},{} ", msg_seq_num, offset, message.len());
+        self.header.write_all(entry.as_bytes())?;
+        self.header.flush()?;
+        self.body_len += message.len() as u64;
+        self.index.insert(msg_seq_num, (offset, message.len()));
+        Ok(())
+    }
+
+    fn get(&self, range: Range<u64>) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        let mut body = &self.body;
+        let mut messages = Vec::new();
+        for (msg_seq_num, (offset, len)) in self.index.range(range) {
+            let mut message = vec![0; *len];
+            body.seek(SeekFrom::Start(*offset))?;
+            body.read_exact(&mut message[..])?;
+            messages.push((*msg_seq_num, message));
+        }
+        Ok(messages)
+    }
+
+    fn seq_numbers(&self) -> SeqNumbers {
+        self.seq_numbers
+    }
+
+    fn set_seq_numbers(&mut self, seq_numbers: SeqNumbers) -> io::Result<()> {
+        self.seq_numbers = seq_numbers;
+        self.write_seqnums()
+    }
+
+    fn creation_time(&self) -> DateTime<Utc> {
+        self.creation_time
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.body.set_len(0)?;
+        self.header.set_len(0)?;
+        self.body_len = 0;
+        self.index.clear();
+        self.seq_numbers = SeqNumbers::default();
+        self.creation_time = self.clock.now();
+        self.write_seqnums()?;
+        self.write_creation_time()
+    }
+}
+
+const CREATION_TIME_FORMAT: &str = "%Y%m%d-%H:%M:%S";
+
+fn with_extension(prefix: &Path, extension: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+fn open_rw(path: &Path, append: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(!append)
+        .append(append)
+        .create(true)
+        .open(path)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse_header(contents: &str) -> io::Result<BTreeMap<u64, (u64, usize)>> {
+    const ERR: &str = "Invalid entry in message store header file";
+    let mut index = BTreeMap::new();
+    for entry in contents.split_whitespace() {
+        let mut values = entry.split(',');
+        let mut next_value = || values.next().ok_or_else(|| invalid_data(ERR));
+        let msg_seq_num = next_value()?.parse().map_err(|_| invalid_data(ERR))?;
+        let offset = next_value()?.parse().map_err(|_| invalid_data(ERR))?;
+        let len = next_value()?.parse().map_err(|_| invalid_data(ERR))?;
+        index.insert(msg_seq_num, (offset, len));
+    }
+    Ok(index)
+}
+
+fn parse_seqnums(contents: &str) -> io::Result<SeqNumbers> {
+    const ERR: &str = "Invalid message store seq. numbers file";
+    let mut values = contents.split(':').map(|s| s.trim().parse::<u64>());
+    let next_outbound = values.next().and_then(|x| x.ok());
+    let next_inbound = values.next().and_then(|x| x.ok());
+    match (next_outbound, next_inbound) {
+        (Some(next_outbound), Some(next_inbound)) if next_outbound > 0 && next_inbound > 0 => {
+            Ok(SeqNumbers {
+                next_inbound,
+                next_outbound,
+            })
+        }
+        _ => Err(invalid_data(ERR)),
+    }
+}
+
+fn parse_creation_time(contents: &str) -> io::Result<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(contents.trim(), CREATION_TIME_FORMAT)
+        .map(|time| DateTime::from_utc(time, Utc))
+        .map_err(|_| invalid_data("Invalid message store session file"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_prefix() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("fefix-{}", uuid::Uuid::new_v4()))
+            .join("FIX.4.4-SENDER-TARGET")
+    }
+
+    #[test]
+    fn memory_store_skips_missing_messages() {
+        let store = &mut MemoryStore::default();
+        store.set(1, b"foo").unwrap();
+        store.set(3, b"bar").unwrap();
+        let messages = store.get(1..4).unwrap();
+        assert_eq!(messages, vec![(1, b"foo".to_vec()), (3, b"bar".to_vec())]);
+    }
+
+    #[test]
+    fn file_store_survives_reopening() {
+        let prefix = temp_prefix();
+        {
+            let mut store = FileStore::open(&prefix).unwrap();
+            assert_eq!(store.seq_numbers().next_outbound(), 1);
+            store.set(1, b"foo").unwrap();
+            store.set(2, b"spam").unwrap();
+            store
+                .set_seq_numbers(SeqNumbers {
+                    next_inbound: 5,
+                    next_outbound: 3,
+                })
+                .unwrap();
+        }
+        let store = FileStore::open(&prefix).unwrap();
+        assert_eq!(store.seq_numbers().next_inbound(), 5);
+        assert_eq!(store.seq_numbers().next_outbound(), 3);
+        assert_eq!(store.get(2..3).unwrap(), vec![(2, b"spam".to_vec())],);
+        let seqnums = std::fs::read_to_string(with_extension(&prefix, "seqnums")).unwrap();
+        assert_eq!(seqnums, "0000000003 : 0000000005");
+        let header = std::fs::read_to_string(with_extension(&prefix, "header")).unwrap();
+        assert_eq!(header, "1,0,3 2,3,4 ");
+    }
+
+    #[test]
+    fn file_store_reset() {
+        let prefix = temp_prefix();
+        let mut store = FileStore::open(&prefix).unwrap();
+        store.set(1, b"foo").unwrap();
+        store.reset().unwrap();
+        store.set(1, b"bar").unwrap();
+        let store = FileStore::open(&prefix).unwrap();
+        assert_eq!(store.get(1..2).unwrap(), vec![(1, b"bar".to_vec())]);
+        assert_eq!(store.seq_numbers().next_inbound(), 1);
+    }
+
+    #[test]
+    fn file_store_creation_time_survives_reopening() {
+        let prefix = temp_prefix();
+        std::fs::create_dir_all(prefix.parent().unwrap()).unwrap();
+        std::fs::write(with_extension(&prefix, "session"), "20210301-08:00:00").unwrap();
+        let store = FileStore::open(&prefix).unwrap();
+        assert_eq!(
+            store
+                .creation_time()
+                .format(CREATION_TIME_FORMAT)
+                .to_string(),
+            "20210301-08:00:00"
+        );
+    }
+}
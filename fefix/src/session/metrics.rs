@@ -0,0 +1,431 @@
+use super::SessionId;
+use crate::definitions::fix44;
+use crate::fix_values::Timestamp;
+use crate::tagvalue::{FieldAccess, Message};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Upper bounds (in seconds) of the buckets of the inbound latency histogram.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// A collection of [`SessionMetrics`], which can be rendered in the
+/// Prometheus text exposition format.
+///
+/// The registry itself is cheap to clone and all clones share the same
+/// sessions, so it can be handed over to many
+/// [`FixConnectionBuilder`](super::FixConnectionBuilder)s while e.g. an HTTP
+/// endpoint serves [`MetricsRegistry::render`].
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    sessions: Arc<Mutex<BTreeMap<String, SessionMetrics>>>,
+}
+
+impl MetricsRegistry {
+    /// Creates a new, empty [`MetricsRegistry`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the [`SessionMetrics`] of `session_id`, which are created on
+    /// first use.
+    pub fn session(&self, session_id: &SessionId) -> SessionMetrics {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Renders the metrics of all sessions in the Prometheus text exposition
+    /// format. Each sample is labelled with `session="<SessionId>"`.
+    pub fn render(&self) -> String {
+        let sessions: Vec<(String, Snapshot)> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(label, metrics)| (escape(label), metrics.snapshot()))
+            .collect();
+        let mut out = String::new();
+        let by_msg_type = |out: &mut String, name, help, get: fn(&Snapshot) -> &Counters| {
+            family(out, name, "counter", help);
+            for (session, snapshot) in sessions.iter() {
+                for (msg_type, value) in get(snapshot).iter() {
+                    let labels =
+                        format!("session=\"{}\",msg_type=\"{}\"", session, escape(msg_type));
+                    sample(out, name, &labels, *value);
+                }
+            }
+        };
+        by_msg_type(
+            &mut out,
+            "fix_messages_received_total",
+            "Inbound messages, by MsgType.",
+            |s| &s.received,
+        );
+        by_msg_type(
+            &mut out,
+            "fix_messages_sent_total",
+            "Outbound messages, by MsgType.",
+            |s| &s.sent,
+        );
+        let simple: &[Family] = &[
+            (
+                "fix_rejects_received_total",
+                "counter",
+                "Inbound Reject and BusinessMessageReject messages.",
+                |s| s.rejects_received,
+            ),
+            (
+                "fix_rejects_sent_total",
+                "counter",
+                "Outbound Reject and BusinessMessageReject messages.",
+                |s| s.rejects_sent,
+            ),
+            (
+                "fix_resend_requests_received_total",
+                "counter",
+                "Inbound ResendRequest messages.",
+                |s| s.resend_requests_received,
+            ),
+            (
+                "fix_resend_requests_sent_total",
+                "counter",
+                "Outbound ResendRequest messages.",
+                |s| s.resend_requests_sent,
+            ),
+            (
+                "fix_gap_fills_received_total",
+                "counter",
+                "Inbound SequenceReset-GapFill messages.",
+                |s| s.gap_fills_received,
+            ),
+            (
+                "fix_gap_fills_sent_total",
+                "counter",
+                "Outbound SequenceReset-GapFill messages.",
+                |s| s.gap_fills_sent,
+            ),
+            (
+                "fix_heartbeat_timeouts_total",
+                "counter",
+                "Times the counterparty missed a Heartbeat and got a TestRequest.",
+                |s| s.heartbeat_timeouts,
+            ),
+            (
+                "fix_test_request_timeouts_total",
+                "counter",
+                "Times the counterparty didn't answer a TestRequest in time.",
+                |s| s.test_request_timeouts,
+            ),
+            (
+                "fix_next_inbound_seq_num",
+                "gauge",
+                "The next expected inbound MsgSeqNum.",
+                |s| s.next_inbound_seq_num,
+            ),
+            (
+                "fix_next_outbound_seq_num",
+                "gauge",
+                "The next outbound MsgSeqNum.",
+                |s| s.next_outbound_seq_num,
+            ),
+        ];
+        for (name, kind, help, get) in simple {
+            family(&mut out, name, kind, help);
+            for (session, snapshot) in sessions.iter() {
+                let labels = format!("session=\"{}\"", session);
+                sample(&mut out, name, &labels, get(snapshot));
+            }
+        }
+        let name = "fix_inbound_latency_seconds";
+        family(
+            &mut out,
+            name,
+            "histogram",
+            "Time between SendingTime and reception of inbound messages.",
+        );
+        for (session, snapshot) in sessions.iter() {
+            let latency = &snapshot.latency;
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+                cumulative += count;
+                let labels = format!("session=\"{}\",le=\"{}\"", session, bound);
+                sample(
+                    &mut out,
+                    "fix_inbound_latency_seconds_bucket",
+                    &labels,
+                    cumulative,
+                );
+            }
+            let labels = format!("session=\"{}\",le=\"+Inf\"", session);
+            sample(
+                &mut out,
+                "fix_inbound_latency_seconds_bucket",
+                &labels,
+                latency.count,
+            );
+            let labels = format!("session=\"{}\"", session);
+            sample(
+                &mut out,
+                "fix_inbound_latency_seconds_sum",
+                &labels,
+                latency.sum,
+            );
+            sample(
+                &mut out,
+                "fix_inbound_latency_seconds_count",
+                &labels,
+                latency.count,
+            );
+        }
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn sample<V>(out: &mut String, name: &str, labels: &str, value: V)
+where
+    V: std::fmt::Display,
+{
+    writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+}
+
+/// Escapes a label value, as required by the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+type Counters = BTreeMap<String, u64>;
+
+// Name, type, help text and value of a metric family with one sample per
+// session.
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&Snapshot) -> u64,
+);
+
+/// The metrics of a single FIX session, shared by all its clones.
+///
+/// Metrics are recorded by [`FixConnection`](super::FixConnection), timeouts
+/// included, and by the deprecated [`LlEventLoop`](super::LlEventLoop); see
+/// [`MetricsRegistry`] for exporting them.
+#[derive(Debug, Clone, Default)]
+pub struct SessionMetrics {
+    inner: Arc<Mutex<Snapshot>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    received: Counters,
+    sent: Counters,
+    rejects_received: u64,
+    rejects_sent: u64,
+    resend_requests_received: u64,
+    resend_requests_sent: u64,
+    gap_fills_received: u64,
+    gap_fills_sent: u64,
+    heartbeat_timeouts: u64,
+    test_request_timeouts: u64,
+    next_inbound_seq_num: u64,
+    next_outbound_seq_num: u64,
+    latency: Histogram,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl SessionMetrics {
+    /// Returns the number of inbound messages of type `msg_type`.
+    pub fn messages_received(&self, msg_type: &str) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner.received.get(msg_type).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of outbound messages of type `msg_type`.
+    pub fn messages_sent(&self, msg_type: &str) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner.sent.get(msg_type).copied().unwrap_or(0)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// Records an inbound message, received at time `now`.
+    pub(crate) fn on_inbound(&self, msg: &Message<&[u8]>, now: DateTime<Utc>) {
+        let msg_type = msg.fv_raw(fix44::MSG_TYPE).unwrap_or(b"");
+        let is_gap_fill = msg.fv::<bool, _>(fix44::GAP_FILL_FLAG) == Ok(true);
+        let sending_time = msg
+            .fv::<Timestamp, _>(fix44::SENDING_TIME)
+            .ok()
+            .and_then(|timestamp| timestamp.to_chrono_utc());
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        count(&mut inner.received, msg_type);
+        match msg_type {
+            b"3" | b"j" => inner.rejects_received += 1,
+            b"2" => inner.resend_requests_received += 1,
+            b"4" if is_gap_fill => inner.gap_fills_received += 1,
+            _ => {}
+        }
+        if let Some(sending_time) = sending_time {
+            let latency = (now - sending_time).num_microseconds().unwrap_or(0);
+            inner.latency.observe(latency.max(0) as f64 / 1e6);
+        }
+    }
+
+    /// Records an encoded outbound message.
+    pub(crate) fn on_outbound(&self, bytes: &[u8]) {
+        let field = |tag: &[u8]| {
+            bytes
+                .split(|byte| *byte == 0x1)
+                .find(|field| field.starts_with(tag))
+                .map(|field| &field[tag.len()..])
+        };
+        let msg_type = field(b"35=").unwrap_or(b"");
+        let is_gap_fill = field(b"123=") == Some(b"Y");
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        count(&mut inner.sent, msg_type);
+        match msg_type {
+            b"3" | b"j" => inner.rejects_sent += 1,
+            b"2" => inner.resend_requests_sent += 1,
+            b"4" if is_gap_fill => inner.gap_fills_sent += 1,
+            _ => {}
+        }
+    }
+
+    /// Records a `TestRequest <1>` sent to a silent counterparty.
+    pub(crate) fn on_heartbeat_timeout(&self) {
+        self.inner.lock().unwrap().heartbeat_timeouts += 1;
+    }
+
+    /// Records an unanswered `TestRequest <1>`.
+    pub(crate) fn on_test_request_timeout(&self) {
+        self.inner.lock().unwrap().test_request_timeouts += 1;
+    }
+
+    pub(crate) fn set_seq_numbers(&self, next_inbound: u64, next_outbound: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_inbound_seq_num = next_inbound;
+        inner.next_outbound_seq_num = next_outbound;
+    }
+}
+
+fn count(counters: &mut Counters, msg_type: &[u8]) {
+    let msg_type = String::from_utf8_lossy(msg_type);
+    match counters.get_mut(msg_type.as_ref()) {
+        Some(counter) => *counter += 1,
+        None => {
+            counters.insert(msg_type.into_owned(), 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::test_utils::session_id;
+
+    #[test]
+    fn sessions_are_shared_by_clones() {
+        let registry = MetricsRegistry::new();
+        let metrics = registry.clone().session(&session_id());
+        metrics.on_outbound(b"8=FIX.4.4\x019=5\x0135=0\x0110=000\x01");
+        assert_eq!(registry.session(&session_id()).messages_sent("0"), 1);
+    }
+
+    #[test]
+    fn render_in_prometheus_text_format() {
+        let registry = MetricsRegistry::new();
+        let metrics = registry.session(&session_id());
+        metrics.on_outbound(b"8=FIX.4.4\x019=5\x0135=4\x01123=Y\x0110=000\x01");
+        metrics.set_seq_numbers(3, 7);
+        let text = registry.render();
+        let session = "session=\"FIX.4.4:SENDER->TARGET\"";
+        assert!(text.contains("# TYPE fix_messages_sent_total counter\n"));
+        assert!(text.contains(&format!(
+            "fix_messages_sent_total{{{},msg_type=\"4\"}} 1\n",
+            session
+        )));
+        assert!(text.contains(&format!("fix_gap_fills_sent_total{{{}}} 1\n", session)));
+        assert!(text.contains("# TYPE fix_next_outbound_seq_num gauge\n"));
+        assert!(text.contains(&format!("fix_next_outbound_seq_num{{{}}} 7\n", session)));
+        assert!(text.contains(&format!(
+            "fix_inbound_latency_seconds_bucket{{{},le=\"+Inf\"}} 0\n",
+            session
+        )));
+    }
+
+    #[test]
+    fn latency_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.003);
+        histogram.observe(0.2);
+        histogram.observe(60.0);
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), 2);
+        let registry = MetricsRegistry::new();
+        registry
+            .session(&session_id())
+            .inner
+            .lock()
+            .unwrap()
+            .latency = histogram;
+        let text = registry.render();
+        let bucket = |le: &str, count: u64| {
+            format!(
+                "fix_inbound_latency_seconds_bucket{{session=\"FIX.4.4:SENDER->TARGET\",le=\"{}\"}} {}\n",
+                le, count
+            )
+        };
+        assert!(text.contains(&bucket("0.001", 0)));
+        assert!(text.contains(&bucket("0.005", 1)));
+        assert!(text.contains(&bucket("0.25", 2)));
+        assert!(text.contains(&bucket("+Inf", 3)));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
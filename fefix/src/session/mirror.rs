@@ -0,0 +1,621 @@
+use super::{
+    Authentication, Backend, BusinessReject, Clock, Endpoint, LogonRequest, OutboundMessage,
+    SessionId, SessionSender, SystemClock,
+};
+use crate::definitions::fix44;
+use crate::tagvalue::{EncoderHandle, Message};
+use crate::TagU16;
+use chrono::{DateTime, Utc};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, BoxFuture};
+use futures::{Future, FutureExt, SinkExt, StreamExt};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::thread;
+
+/// Whether a [`MirroredMessage`] was received or sent by the session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Received from the counterparty.
+    Inbound,
+    /// Sent to the counterparty.
+    Outbound,
+}
+
+/// A copy of a message that went over a mirrored FIX session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredMessage {
+    direction: Direction,
+    session_id: SessionId,
+    timestamp: DateTime<Utc>,
+    message: Vec<u8>,
+}
+
+impl MirroredMessage {
+    /// Returns whether the message was received or sent.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Returns the identifier of the mirrored session.
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    /// Returns the time at which the message was received or sent.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// Returns the raw message, exactly as it went over the wire.
+    pub fn message(&self) -> &[u8] {
+        &self.message[..]
+    }
+}
+
+/// A destination for the [`MirroredMessage`]s of a [`Mirror`], e.g. a file,
+/// a channel or another FIX session.
+///
+/// Each sink is driven by its own task, so a slow sink never holds back the
+/// mirrored session nor other sinks. Errors are ignored.
+pub trait MirrorSink: Send + 'static {
+    /// Forwards `message`. Messages are forwarded one at a time, in order.
+    fn send(&mut self, message: MirroredMessage) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Called before the next message whenever `dropped` messages had to be
+    /// discarded because the queue of this sink was full. `timestamp` is the
+    /// [`MirroredMessage::timestamp`] of that next message. Does nothing by
+    /// default.
+    fn on_overflow(
+        &mut self,
+        dropped: u64,
+        timestamp: DateTime<Utc>,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        let _ = (dropped, timestamp);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+/// Copies all messages of a FIX session, e.g. for real-time risk and
+/// compliance checks, to one or more [`MirrorSink`]s.
+///
+/// A [`Mirror`] is attached to a session either with
+/// [`FixConnectionBuilder::set_mirror`](super::FixConnectionBuilder::set_mirror),
+/// so that it sees every message as it goes over the wire, or by wrapping
+/// the [`Backend`] of the session with [`Mirror::wrap`], so that it sees
+/// exactly the messages that the [`Backend`] sees through
+/// [`Backend::on_inbound_message`], [`Backend::on_inbound_app_message`] and
+/// [`Backend::on_outbound_message`]. The latter misses inbound messages which
+/// the session rejects or ignores (e.g. because of `SendingTime <52>`
+/// accuracy, validation errors or `PossDupFlag <43>` duplicates), and
+/// mirrors out-of-order messages only once the gap is filled.
+///
+/// Each sink has a bounded queue. Mirroring never blocks: when the queue of a
+/// sink is full, messages are dropped for that sink only, counted by
+/// [`Mirror::dropped`] and reported to the sink via
+/// [`MirrorSink::on_overflow`].
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    session_id: SessionId,
+    clock: Arc<dyn Clock>,
+    sinks: Vec<SinkQueue>,
+}
+
+#[derive(Debug, Clone)]
+struct SinkQueue {
+    sender: mpsc::Sender<MirroredMessage>,
+    overflow: Arc<Overflow>,
+}
+
+#[derive(Debug, Default)]
+struct Overflow {
+    total: AtomicU64,
+    // Dropped messages which haven't been reported to the sink yet.
+    unreported: AtomicU64,
+}
+
+impl Mirror {
+    /// Creates a new [`Mirror`] of the session `session_id`, without any
+    /// sinks.
+    pub fn new(session_id: SessionId) -> Self {
+        Self {
+            session_id,
+            clock: Arc::new(SystemClock),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Sets the [`Clock`] which timestamps mirrored messages. [`SystemClock`]
+    /// by default.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Adds `sink`, with a queue of at least `capacity` messages.
+    ///
+    /// The returned [`Future`] drives the sink and must be spawned by the
+    /// caller on the executor of its choice. It completes once `self` and
+    /// all its clones are dropped and all queued messages are forwarded.
+    pub fn add_sink<S>(&mut self, sink: S, capacity: usize) -> impl Future<Output = ()> + Send
+    where
+        S: MirrorSink,
+    {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let overflow = Arc::new(Overflow::default());
+        self.sinks.push(SinkQueue {
+            sender,
+            overflow: overflow.clone(),
+        });
+        run_sink(sink, receiver, overflow)
+    }
+
+    /// Returns the total number of messages dropped so far because of full
+    /// queues, across all sinks.
+    pub fn dropped(&self) -> u64 {
+        self.sinks
+            .iter()
+            .map(|sink| sink.overflow.total.load(atomic::Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Wraps `backend` so that all messages it sees are mirrored.
+    pub fn wrap<B>(self, backend: B) -> Mirrored<B>
+    where
+        B: Backend,
+    {
+        Mirrored {
+            inner: backend,
+            mirror: self,
+        }
+    }
+
+    fn mirror(&mut self, direction: Direction, message: &[u8]) {
+        let now = self.clock.now();
+        self.mirror_at(direction, message, now);
+    }
+
+    pub(crate) fn mirror_at(&mut self, direction: Direction, message: &[u8], now: DateTime<Utc>) {
+        let mirrored = MirroredMessage {
+            direction,
+            session_id: self.session_id.clone(),
+            timestamp: now,
+            message: message.to_vec(),
+        };
+        for sink in self.sinks.iter_mut() {
+            match sink.sender.try_send(mirrored.clone()) {
+                Ok(()) => {}
+                // The task of the sink is gone, so nobody cares.
+                Err(err) if err.is_disconnected() => {}
+                Err(_) => {
+                    sink.overflow.total.fetch_add(1, atomic::Ordering::Relaxed);
+                    sink.overflow
+                        .unreported
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+async fn run_sink<S>(
+    mut sink: S,
+    mut receiver: mpsc::Receiver<MirroredMessage>,
+    overflow: Arc<Overflow>,
+) where
+    S: MirrorSink,
+{
+    while let Some(message) = receiver.next().await {
+        let dropped = overflow.unreported.swap(0, atomic::Ordering::Relaxed);
+        if dropped > 0 {
+            sink.on_overflow(dropped, message.timestamp).await.ok();
+        }
+        sink.send(message).await.ok();
+    }
+}
+
+/// A [`Backend`] which copies all messages to the sinks of a [`Mirror`]
+/// before handing them over to the wrapped [`Backend`]. See [`Mirror::wrap`].
+#[derive(Debug, Clone)]
+pub struct Mirrored<B> {
+    inner: B,
+    mirror: Mirror,
+}
+
+impl<B> Mirrored<B> {
+    /// Returns the wrapped [`Backend`].
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Returns the [`Mirror`] of `self`.
+    pub fn mirror(&self) -> &Mirror {
+        &self.mirror
+    }
+}
+
+impl<B> Backend for Mirrored<B>
+where
+    B: Backend,
+{
+    type Error = B::Error;
+
+    fn on_heartbeat_is_due(&mut self) -> Result<(), Self::Error> {
+        self.inner.on_heartbeat_is_due()
+    }
+
+    fn on_inbound_app_message(&mut self, message: Message<&[u8]>) -> Result<(), Self::Error> {
+        self.mirror.mirror(Direction::Inbound, message.as_bytes());
+        self.inner.on_inbound_app_message(message)
+    }
+
+    fn supports_msg_type(&self, msg_type: &[u8]) -> bool {
+        self.inner.supports_msg_type(msg_type)
+    }
+
+    fn business_reject(&self, error: &Self::Error) -> Option<BusinessReject> {
+        self.inner.business_reject(error)
+    }
+
+    fn on_outbound_message(&mut self, message: &[u8]) -> Result<(), Self::Error> {
+        self.mirror.mirror(Direction::Outbound, message);
+        self.inner.on_outbound_message(message)
+    }
+
+    fn on_inbound_message(
+        &mut self,
+        message: Message<&[u8]>,
+        is_app: bool,
+    ) -> Result<(), Self::Error> {
+        if is_app {
+            // Mirrored by `on_inbound_app_message`.
+            self.on_inbound_app_message(message)
+        } else {
+            self.mirror.mirror(Direction::Inbound, message.as_bytes());
+            self.inner.on_inbound_message(message, is_app)
+        }
+    }
+
+    fn on_resend_request(&mut self, range: Range<u64>) -> Result<(), Self::Error> {
+        self.inner.on_resend_request(range)
+    }
+
+    fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+        self.inner.on_successful_handshake()
+    }
+
+    fn on_outbound_logon(&mut self, logon: &mut EncoderHandle<Vec<u8>>) -> Result<(), Self::Error> {
+        self.inner.on_outbound_logon(logon)
+    }
+
+    fn authenticate(&mut self, logon: &LogonRequest) -> Authentication {
+        self.inner.authenticate(logon)
+    }
+
+    fn on_test_request_timeout(&mut self) -> Result<(), Self::Error> {
+        self.inner.on_test_request_timeout()
+    }
+
+    fn on_throttle_queue_depth(&mut self, depth: usize) -> Result<(), Self::Error> {
+        self.inner.on_throttle_queue_depth(depth)
+    }
+
+    fn on_connection_attempt(
+        &mut self,
+        endpoint: &Endpoint,
+        result: Result<(), &io::Error>,
+    ) -> Result<(), Self::Error> {
+        self.inner.on_connection_attempt(endpoint, result)
+    }
+}
+
+/// A [`MirrorSink`] which appends messages to a file, one per line, e.g.
+/// `20210301-12:00:00.000 IN FIX.4.4:SENDER->TARGET : 8=FIX.4.4|...`.
+/// Overflows are recorded as `OVERFLOW` lines.
+///
+/// The file is written by a dedicated thread, so that blocking file I/O
+/// never stalls the executor which drives the sink.
+#[derive(Debug)]
+pub struct FileSink {
+    writer: std_mpsc::Sender<(Vec<u8>, oneshot::Sender<io::Result<()>>)>,
+}
+
+impl FileSink {
+    /// Opens (or creates) the file at `path` for appending, and starts the
+    /// thread which writes it. The thread stops once `self` is dropped.
+    pub fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (writer, lines) = std_mpsc::channel::<(Vec<u8>, oneshot::Sender<_>)>();
+        thread::Builder::new()
+            .name("fefix-file-sink".to_string())
+            .spawn(move || {
+                for (line, reply) in lines {
+                    let result = file.write_all(&line).and_then(|()| file.flush());
+                    reply.send(result).ok();
+                }
+            })?;
+        Ok(Self { writer })
+    }
+
+    fn write_line(
+        &mut self,
+        now: DateTime<Utc>,
+        line: &[u8],
+    ) -> BoxFuture<'static, io::Result<()>> {
+        let mut buffer = now.format("%Y%m%d-%H:%M:%S%.3f ").to_string().into_bytes();
+        buffer.extend_from_slice(line);
+        buffer.push(b'\n');
+        let (reply, result) = oneshot::channel();
+        let sent = self.writer.send((buffer, reply));
+        async move {
+            let stopped = || io::Error::from(io::ErrorKind::BrokenPipe);
+            sent.map_err(|_| stopped())?;
+            result.await.map_err(|_| stopped())?
+        }
+        .boxed()
+    }
+}
+
+impl MirrorSink for FileSink {
+    fn send(&mut self, message: MirroredMessage) -> BoxFuture<'_, io::Result<()>> {
+        let direction = match message.direction {
+            Direction::Inbound => "IN",
+            Direction::Outbound => "OUT",
+        };
+        let mut line = format!("{} {} : ", direction, message.session_id).into_bytes();
+        line.extend_from_slice(&message.message);
+        self.write_line(message.timestamp, &line)
+    }
+
+    fn on_overflow(
+        &mut self,
+        dropped: u64,
+        timestamp: DateTime<Utc>,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        let line = format!("OVERFLOW : {} messages dropped", dropped);
+        self.write_line(timestamp, line.as_bytes())
+    }
+}
+
+impl MirrorSink for mpsc::Sender<MirroredMessage> {
+    fn send(&mut self, message: MirroredMessage) -> BoxFuture<'_, io::Result<()>> {
+        SinkExt::send(self, message)
+            .map(|result| result.map_err(|_| io::ErrorKind::BrokenPipe.into()))
+            .boxed()
+    }
+}
+
+impl MirrorSink for mpsc::UnboundedSender<MirroredMessage> {
+    fn send(&mut self, message: MirroredMessage) -> BoxFuture<'_, io::Result<()>> {
+        let result = self
+            .unbounded_send(message)
+            .map_err(|_| io::ErrorKind::BrokenPipe.into());
+        future::ready(result).boxed()
+    }
+}
+
+/// A [`MirrorSink`] which re-sends the application messages of the mirrored
+/// session over another FIX session, as drop copies.
+///
+/// Each copy has the same `MsgType <35>` and body as the original message,
+/// with `CopyMsgIndicator <797>` set to `Y`; the standard header and trailer
+/// are those of the drop-copy session. Session-level messages aren't copied.
+#[derive(Debug, Clone)]
+pub struct DropCopySink {
+    sender: SessionSender,
+    directions: Vec<Direction>,
+}
+
+impl DropCopySink {
+    /// Creates a new [`DropCopySink`] which sends copies via `sender`.
+    pub fn new(sender: SessionSender) -> Self {
+        Self {
+            sender,
+            directions: vec![Direction::Inbound, Direction::Outbound],
+        }
+    }
+
+    /// Only copies messages in the given `direction`. Messages are copied in
+    /// both directions by default.
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.directions = vec![direction];
+    }
+}
+
+impl MirrorSink for DropCopySink {
+    fn send(&mut self, message: MirroredMessage) -> BoxFuture<'_, io::Result<()>> {
+        let copy = if self.directions.contains(&message.direction) {
+            drop_copy(&message.message)
+        } else {
+            None
+        };
+        async move {
+            if let Some(copy) = copy {
+                self.sender
+                    .send(copy)
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Re-wraps the body of `message` for a drop-copy session. Returns `None` for
+/// session-level messages.
+fn drop_copy(message: &[u8]) -> Option<OutboundMessage> {
+    let mut fields = message
+        .split(|byte| *byte == 0x1)
+        .filter(|field| !field.is_empty())
+        .filter_map(|field| {
+            let mut parts = field.splitn(2, |byte| *byte == b'=');
+            let tag = std::str::from_utf8(parts.next()?).ok()?.parse().ok()?;
+            Some((tag, parts.next()?))
+        });
+    let msg_type = fields.find(|(tag, _)| *tag == 35).map(|(_, value)| value)?;
+    if matches!(msg_type, b"0" | b"1" | b"2" | b"3" | b"4" | b"5" | b"A") {
+        return None;
+    }
+    let mut copy = OutboundMessage::new(msg_type);
+    for (tag, value) in fields.filter(|(tag, _)| !is_header_or_trailer(*tag)) {
+        if let Some(tag) = TagU16::new(tag) {
+            copy.set_any(tag, value);
+        }
+    }
+    copy.set(fix44::COPY_MSG_INDICATOR, true);
+    Some(copy)
+}
+
+/// Returns `true` for the fields of the standard header and trailer, which
+/// are set by the drop-copy session itself.
+fn is_header_or_trailer(tag: u16) -> bool {
+    matches!(
+        tag,
+        8 | 9
+            | 10
+            | 34
+            | 35
+            | 43
+            | 49
+            | 50
+            | 52
+            | 56
+            | 57
+            | 89
+            | 90
+            | 91
+            | 93
+            | 97
+            | 115
+            | 116
+            | 122
+            | 128
+            | 129
+            | 142
+            | 143
+            | 144
+            | 145
+            | 212
+            | 213
+            | 347
+            | 369
+            | 627
+            | 628
+            | 629
+            | 630
+            | 797
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::test_utils::session_id;
+    use crate::session::MockClock;
+    use chrono::TimeZone;
+
+    const ORDER: &[u8] =
+        b"8=FIX.4.4\x019=40\x0135=D\x0149=SENDER\x0156=TARGET\x0134=2\x0111=foo\x0154=1\x0110=000\x01";
+
+    #[test]
+    fn messages_are_mirrored_with_direction_and_timestamp() {
+        let now = Utc::now();
+        let mut mirror = Mirror::new(session_id());
+        mirror.set_clock(MockClock::new(now));
+        let (sender, receiver) = mpsc::unbounded();
+        let task = mirror.add_sink(sender, 16);
+        mirror.mirror(Direction::Outbound, ORDER);
+        drop(mirror);
+        futures::executor::block_on(task);
+        let mirrored: Vec<MirroredMessage> = futures::executor::block_on(receiver.collect());
+        assert_eq!(mirrored.len(), 1);
+        assert_eq!(mirrored[0].direction(), Direction::Outbound);
+        assert_eq!(mirrored[0].session_id(), &session_id());
+        assert_eq!(mirrored[0].timestamp(), now);
+        assert_eq!(mirrored[0].message(), ORDER);
+    }
+
+    #[test]
+    fn full_queues_drop_messages_and_report_overflow() {
+        #[derive(Debug, Default)]
+        struct Recorder {
+            sent: Arc<AtomicU64>,
+            dropped: Arc<AtomicU64>,
+        }
+
+        impl MirrorSink for Recorder {
+            fn send(&mut self, _message: MirroredMessage) -> BoxFuture<'_, io::Result<()>> {
+                self.sent.fetch_add(1, atomic::Ordering::Relaxed);
+                future::ready(Ok(())).boxed()
+            }
+
+            fn on_overflow(
+                &mut self,
+                dropped: u64,
+                _timestamp: DateTime<Utc>,
+            ) -> BoxFuture<'_, io::Result<()>> {
+                self.dropped.fetch_add(dropped, atomic::Ordering::Relaxed);
+                future::ready(Ok(())).boxed()
+            }
+        }
+
+        let recorder = Recorder::default();
+        let (sent, dropped) = (recorder.sent.clone(), recorder.dropped.clone());
+        let mut mirror = Mirror::new(session_id());
+        // The queue holds one message per sender on top of its capacity.
+        let task = mirror.add_sink(recorder, 1);
+        for _ in 0..5 {
+            mirror.mirror(Direction::Inbound, ORDER);
+        }
+        assert_eq!(mirror.dropped(), 3);
+        drop(mirror);
+        futures::executor::block_on(task);
+        assert_eq!(sent.load(atomic::Ordering::Relaxed), 2);
+        assert_eq!(dropped.load(atomic::Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn file_sinks_stamp_overflows_with_the_mirror_clock() {
+        let path = std::env::temp_dir().join(format!("fefix-mirror-{}.log", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let mut mirror = Mirror::new(session_id());
+        mirror.set_clock(MockClock::new(now));
+        let task = mirror.add_sink(FileSink::open(&path).unwrap(), 0);
+        for _ in 0..3 {
+            mirror.mirror(Direction::Inbound, b"8=FIX.4.4\x0135=0\x01");
+        }
+        drop(mirror);
+        futures::executor::block_on(task);
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "20210301-12:00:00.000 OVERFLOW : 2 messages dropped"
+        );
+        assert_eq!(
+            lines[1],
+            "20210301-12:00:00.000 IN FIX.4.4:SENDER->TARGET : 8=FIX.4.4\x0135=0\x01"
+        );
+    }
+
+    #[test]
+    fn drop_copies_keep_the_body_only() {
+        let copy = drop_copy(ORDER).unwrap();
+        assert_eq!(copy.msg_type(), b"D");
+        assert_eq!(copy.body(), b"11=foo\x0154=1\x01797=Y\x01");
+        assert_eq!(drop_copy(b"8=FIX.4.4\x0135=0\x0134=3\x01"), None);
+    }
+}
@@ -5,23 +5,63 @@
 //! The above is a conceptual view of the FIX Session layer, complete with its
 //! state machine and transitions between initiator and acceptor.
 
+mod admin;
+mod auth;
 pub mod backends;
+mod business_reject;
+mod clock;
 mod config;
 mod connection;
 mod errs;
 mod event_loop;
+mod fixt;
 mod heartbeat_rule;
+mod initiator;
+mod log;
+mod message_store;
+mod metrics;
+mod mirror;
 mod resend_request_range;
+mod schedule;
+mod sender;
 mod seq_numbers;
+mod server;
+mod settings;
+#[cfg(test)]
+mod test_utils;
+mod throttle;
+mod transport;
+mod validator;
+mod zone_info;
 
+pub use admin::{AdminCommand, AdminError, SessionAdmin, SessionState};
+pub use auth::{Authentication, LogonRequest, SessionStatus};
+pub use business_reject::BusinessReject;
+pub use clock::{Clock, MockClock, SystemClock};
 pub use config::{Config, Configure};
 pub use connection::*;
 pub use event_loop::*;
+pub use fixt::ApplVerId;
 pub use heartbeat_rule::HeartbeatRule;
+pub use initiator::{Backoff, Endpoint, FixInitiator};
+pub use log::{FileLog, SessionLog};
+pub use message_store::{FileStore, MemoryStore, MessageStore};
+pub use metrics::{MetricsRegistry, SessionMetrics};
+pub use mirror::{
+    Direction, DropCopySink, FileSink, Mirror, MirrorSink, Mirrored, MirroredMessage,
+};
 pub use resend_request_range::ResendRequestRange;
+pub use schedule::Schedule;
+pub use sender::{OutboundMessage, SendError, SessionSender};
 pub use seq_numbers::{SeqNumberError, SeqNumbers};
+pub use server::{FixServer, SessionId};
+pub use settings::{SessionSettings, Settings, SettingsError};
+pub use throttle::{RateLimit, Throttle, ThrottlePolicy};
+pub use transport::{DisconnectHandle, MemoryTransport};
+pub use zone_info::{ZoneInfo, ZoneInfoOffset};
 
-use crate::tagvalue::Message;
+use crate::tagvalue::{EncoderHandle, Message};
+use std::io;
 use std::ops::Range;
 
 pub trait Backend: Clone {
@@ -34,6 +74,25 @@ pub trait Backend: Clone {
 
     fn on_inbound_app_message(&mut self, message: Message<&[u8]>) -> Result<(), Self::Error>;
 
+    /// Returns `false` if the application doesn't process messages of type
+    /// `msg_type`, which are then refused with a `BusinessMessageReject <j>`
+    /// without reaching [`Backend::on_inbound_app_message`]. All message
+    /// types are supported by default.
+    #[inline]
+    fn supports_msg_type(&self, msg_type: &[u8]) -> bool {
+        let _ = msg_type;
+        true
+    }
+
+    /// Called when [`Backend::on_inbound_app_message`] fails. If a
+    /// [`BusinessReject`] is returned, the message is refused with a
+    /// `BusinessMessageReject <j>`; otherwise, the error is ignored.
+    #[inline]
+    fn business_reject(&self, error: &Self::Error) -> Option<BusinessReject> {
+        let _ = error;
+        None
+    }
+
     fn on_outbound_message(&mut self, message: &[u8]) -> Result<(), Self::Error>;
 
     #[inline]
@@ -53,9 +112,54 @@ pub trait Backend: Clone {
 
     fn on_successful_handshake(&mut self) -> Result<(), Self::Error>;
 
-    fn fetch_messages(&mut self) -> Result<&[&[u8]], Self::Error>;
+    /// Called while building each outbound `Logon <A>`, after all session
+    /// fields have been set. It allows adding custom fields, e.g.
+    /// `Username <553>` and `Password <554>`.
+    #[inline]
+    fn on_outbound_logon(&mut self, logon: &mut EncoderHandle<Vec<u8>>) -> Result<(), Self::Error> {
+        let _ = logon;
+        Ok(())
+    }
+
+    /// Called by acceptors upon the counterparty's `Logon <A>`, once its
+    /// CompIDs and `HeartBtInt <108>` are found valid. Refused Logons are
+    /// answered with a `Logout <5>`, and the transport is closed. Password
+    /// changes (i.e. `NewPassword <925>`) are up to the implementor, who
+    /// should then reply with [`SessionStatus::SessionPasswordChanged`].
+    ///
+    /// All Logons are accepted by default.
+    #[inline]
+    fn authenticate(&mut self, logon: &LogonRequest) -> Authentication {
+        let _ = logon;
+        Authentication::accept()
+    }
 
-    fn pending_message(&mut self) -> Option<&[u8]>;
+    /// Called when the counterparty doesn't answer a `TestRequest <1>` in
+    /// time. The session is then terminated.
+    #[inline]
+    fn on_test_request_timeout(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called whenever the number of application messages held back by the
+    /// [`Throttle`] of the session changes.
+    #[inline]
+    fn on_throttle_queue_depth(&mut self, depth: usize) -> Result<(), Self::Error> {
+        let _ = depth;
+        Ok(())
+    }
+
+    /// Called by [`FixInitiator`] after each attempt to connect to
+    /// `endpoint`, successful or not.
+    #[inline]
+    fn on_connection_attempt(
+        &mut self,
+        endpoint: &Endpoint,
+        result: Result<(), &io::Error>,
+    ) -> Result<(), Self::Error> {
+        let _ = (endpoint, result);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,355 @@
+use chrono::{
+    DateTime, Datelike, LocalResult, NaiveDateTime, NaiveTime, Offset, TimeZone, Timelike, Utc,
+    Weekday,
+};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+const NANOS_PER_DAY: i64 = 86_400_000_000_000;
+
+/// The time window during which a FIX session is active, e.g. the trading
+/// hours of an exchange.
+///
+/// Sessions can either be daily, starting and ending at the same time every
+/// day, or weekly, starting and ending on specific days of the week. Start and
+/// end times are expressed in a configurable time zone (UTC by default). If
+/// the end time comes before the start time, the session spans across
+/// midnight (or across the end of the week); if they're equal, the session
+/// lasts 24 hours (or a full week).
+///
+/// Start and end times are local times, so they follow daylight saving time
+/// changes of time zones such as [`ZoneInfo`](super::ZoneInfo). Start and end
+/// times which are skipped by such a change are moved forward, and those
+/// which happen twice refer to the earliest instant.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    start_day: Option<Weekday>,
+    start_time: NaiveTime,
+    end_day: Option<Weekday>,
+    end_time: NaiveTime,
+    time_zone: Arc<dyn LocalTime>,
+}
+
+impl Schedule {
+    /// Creates a new daily [`Schedule`] in UTC.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::{NaiveTime, TimeZone, Utc};
+    /// use fefix::session::Schedule;
+    ///
+    /// let schedule = Schedule::daily(
+    ///     NaiveTime::from_hms(8, 0, 0),
+    ///     NaiveTime::from_hms(17, 30, 0),
+    /// );
+    /// assert!(schedule.is_active(Utc.ymd(2021, 3, 1).and_hms(12, 0, 0)));
+    /// assert!(!schedule.is_active(Utc.ymd(2021, 3, 1).and_hms(18, 0, 0)));
+    /// ```
+    pub fn daily(start_time: NaiveTime, end_time: NaiveTime) -> Self {
+        Self {
+            start_day: None,
+            start_time,
+            end_day: None,
+            end_time,
+            time_zone: Arc::new(Utc),
+        }
+    }
+
+    /// Creates a new weekly [`Schedule`] in UTC.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::{NaiveTime, TimeZone, Utc, Weekday};
+    /// use fefix::session::Schedule;
+    ///
+    /// // From Sunday evening to Friday evening.
+    /// let schedule = Schedule::weekly(
+    ///     Weekday::Sun,
+    ///     NaiveTime::from_hms(22, 0, 0),
+    ///     Weekday::Fri,
+    ///     NaiveTime::from_hms(22, 0, 0),
+    /// );
+    /// // Wednesday.
+    /// assert!(schedule.is_active(Utc.ymd(2021, 3, 3).and_hms(3, 0, 0)));
+    /// // Saturday.
+    /// assert!(!schedule.is_active(Utc.ymd(2021, 3, 6).and_hms(12, 0, 0)));
+    /// ```
+    pub fn weekly(
+        start_day: Weekday,
+        start_time: NaiveTime,
+        end_day: Weekday,
+        end_time: NaiveTime,
+    ) -> Self {
+        Self {
+            start_day: Some(start_day),
+            start_time,
+            end_day: Some(end_day),
+            end_time,
+            time_zone: Arc::new(Utc),
+        }
+    }
+
+    /// Sets the time zone of start and end times, e.g. a
+    /// [`ZoneInfo`](super::ZoneInfo) or a [`FixedOffset`](chrono::FixedOffset).
+    pub fn set_time_zone<Tz>(&mut self, time_zone: Tz)
+    where
+        Tz: TimeZone + Debug + Send + Sync + 'static,
+    {
+        self.time_zone = Arc::new(time_zone);
+    }
+
+    /// Returns `true` if the session is active at `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.elapsed_since_start(now) < self.duration()
+    }
+
+    /// Returns the start of the latest session that started at or before
+    /// `now`. The session might be over already.
+    pub fn session_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let start = self.local(now) - nanos(self.elapsed_since_start(now));
+        self.utc(start)
+    }
+
+    /// Returns `true` if `a` and `b` belong to the same session.
+    pub fn is_same_session(&self, a: DateTime<Utc>, b: DateTime<Utc>) -> bool {
+        self.is_active(a) && self.is_active(b) && self.session_start(a) == self.session_start(b)
+    }
+
+    /// Returns how long it takes for the next session to start after `now`,
+    /// or [`Duration::default`] if the session is already active.
+    pub fn time_until_start(&self, now: DateTime<Utc>) -> Duration {
+        if self.is_active(now) {
+            Duration::default()
+        } else {
+            let remaining = self.period() - self.elapsed_since_start(now);
+            let start = self.utc(self.local(now) + nanos(remaining));
+            (start - now).to_std().unwrap_or_default()
+        }
+    }
+
+    /// Returns how long it takes for the current session to end after `now`,
+    /// or `None` if the session isn't active.
+    pub fn time_until_end(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let elapsed = self.elapsed_since_start(now);
+        if elapsed < self.duration() {
+            let end = self.utc(self.local(now) + nanos(self.duration() - elapsed));
+            Some((end - now).to_std().unwrap_or_default())
+        } else {
+            None
+        }
+    }
+
+    fn local(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        self.time_zone.local(&now.naive_utc())
+    }
+
+    fn utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        DateTime::from_utc(self.time_zone.utc(&local), Utc)
+    }
+
+    fn period(&self) -> i64 {
+        if self.start_day.is_some() {
+            NANOS_PER_DAY * 7
+        } else {
+            NANOS_PER_DAY
+        }
+    }
+
+    /// The length of a session in nanoseconds.
+    fn duration(&self) -> i64 {
+        let start = self.offset_within_period(self.start_day, self.start_time);
+        let end = self.offset_within_period(self.end_day, self.end_time);
+        match (end - start).rem_euclid(self.period()) {
+            0 => self.period(),
+            duration => duration,
+        }
+    }
+
+    /// Nanoseconds of local time elapsed between the latest session start
+    /// and `now`.
+    fn elapsed_since_start(&self, now: DateTime<Utc>) -> i64 {
+        let local = self.local(now);
+        let weekday = self.start_day.map(|_| local.weekday());
+        let position = self.offset_within_period(weekday, local.time());
+        let start = self.offset_within_period(self.start_day, self.start_time);
+        (position - start).rem_euclid(self.period())
+    }
+
+    /// Nanoseconds between the beginning of the period (midnight, or Monday
+    /// midnight for weekly schedules) and `day` at `time`.
+    fn offset_within_period(&self, day: Option<Weekday>, time: NaiveTime) -> i64 {
+        let days = day.map(|d| d.num_days_from_monday() as i64).unwrap_or(0);
+        let nanos =
+            time.num_seconds_from_midnight() as i64 * 1_000_000_000 + time.nanosecond() as i64;
+        days * NANOS_PER_DAY + nanos
+    }
+}
+
+fn nanos(nanos: i64) -> chrono::Duration {
+    chrono::Duration::nanoseconds(nanos)
+}
+
+/// The conversions between UTC and local time needed by [`Schedule`], for any
+/// [`TimeZone`].
+trait LocalTime: Debug + Send + Sync {
+    fn local(&self, utc: &NaiveDateTime) -> NaiveDateTime;
+
+    /// Local times which don't exist are moved forward, and those which are
+    /// ambiguous resolve to the earliest instant.
+    fn utc(&self, local: &NaiveDateTime) -> NaiveDateTime;
+}
+
+impl<Tz> LocalTime for Tz
+where
+    Tz: TimeZone + Debug + Send + Sync,
+{
+    fn local(&self, utc: &NaiveDateTime) -> NaiveDateTime {
+        *utc + offset(self.offset_from_utc_datetime(utc))
+    }
+
+    fn utc(&self, local: &NaiveDateTime) -> NaiveDateTime {
+        match self.offset_from_local_datetime(local) {
+            LocalResult::Single(earliest) | LocalResult::Ambiguous(earliest, _) => {
+                *local - offset(earliest)
+            }
+            LocalResult::None => {
+                // The offset before the change maps `local` to after it.
+                let before = *local - chrono::Duration::days(1);
+                *local - offset(self.offset_from_utc_datetime(&before))
+            }
+        }
+    }
+}
+
+fn offset<O>(offset: O) -> chrono::Duration
+where
+    O: Offset,
+{
+    chrono::Duration::seconds(offset.fix().local_minus_utc() as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::zone_info::test::new_york;
+    use chrono::FixedOffset;
+
+    fn hms(h: u32, m: u32, s: u32) -> NaiveTime {
+        NaiveTime::from_hms(h, m, s)
+    }
+
+    #[test]
+    fn daily_schedule() {
+        let schedule = Schedule::daily(hms(8, 0, 0), hms(17, 0, 0));
+        let day = Utc.ymd(2021, 3, 1);
+        assert!(!schedule.is_active(day.and_hms(7, 59, 59)));
+        assert!(schedule.is_active(day.and_hms(8, 0, 0)));
+        assert!(!schedule.is_active(day.and_hms(17, 0, 0)));
+        assert_eq!(
+            schedule.time_until_start(day.and_hms(7, 0, 0)),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            schedule.time_until_start(day.and_hms(18, 0, 0)),
+            Duration::from_secs(14 * 3600)
+        );
+        assert_eq!(
+            schedule.time_until_end(day.and_hms(16, 0, 0)),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(schedule.time_until_end(day.and_hms(18, 0, 0)), None);
+    }
+
+    #[test]
+    fn daily_schedule_across_midnight() {
+        let schedule = Schedule::daily(hms(22, 0, 0), hms(6, 0, 0));
+        let day = Utc.ymd(2021, 3, 1);
+        assert!(schedule.is_active(day.and_hms(23, 0, 0)));
+        assert!(schedule.is_active(day.and_hms(5, 0, 0)));
+        assert!(!schedule.is_active(day.and_hms(12, 0, 0)));
+        assert_eq!(
+            schedule.session_start(day.and_hms(5, 0, 0)),
+            Utc.ymd(2021, 2, 28).and_hms(22, 0, 0)
+        );
+        assert!(
+            schedule.is_same_session(Utc.ymd(2021, 2, 28).and_hms(23, 0, 0), day.and_hms(5, 0, 0))
+        );
+        assert!(!schedule.is_same_session(day.and_hms(5, 0, 0), day.and_hms(23, 0, 0)));
+    }
+
+    #[test]
+    fn daily_schedule_with_time_zone() {
+        let mut schedule = Schedule::daily(hms(9, 0, 0), hms(17, 0, 0));
+        // New York (EST).
+        schedule.set_time_zone(FixedOffset::west(5 * 3600));
+        let day = Utc.ymd(2021, 3, 1);
+        assert!(!schedule.is_active(day.and_hms(10, 0, 0)));
+        assert!(schedule.is_active(day.and_hms(14, 0, 0)));
+        assert!(!schedule.is_active(day.and_hms(22, 0, 0)));
+    }
+
+    #[test]
+    fn daily_schedule_follows_daylight_saving_time() {
+        let mut schedule = Schedule::daily(hms(9, 0, 0), hms(17, 0, 0));
+        schedule.set_time_zone(new_york());
+        // EST (-05:00) in winter, EDT (-04:00) in summer.
+        let winter = Utc.ymd(2021, 3, 1);
+        let summer = Utc.ymd(2021, 7, 1);
+        assert!(!schedule.is_active(winter.and_hms(13, 30, 0)));
+        assert!(schedule.is_active(winter.and_hms(14, 0, 0)));
+        assert!(!schedule.is_active(summer.and_hms(21, 30, 0)));
+        assert!(schedule.is_active(summer.and_hms(13, 0, 0)));
+        assert_eq!(
+            schedule.session_start(summer.and_hms(14, 0, 0)),
+            summer.and_hms(13, 0, 0)
+        );
+        // Clocks move forward on 2021-03-14: the night before lasts 23 hours.
+        assert_eq!(
+            schedule.time_until_start(Utc.ymd(2021, 3, 13).and_hms(22, 0, 0)),
+            Duration::from_secs(15 * 3600)
+        );
+        assert_eq!(
+            schedule.time_until_end(Utc.ymd(2021, 3, 14).and_hms(13, 0, 0)),
+            Some(Duration::from_secs(8 * 3600))
+        );
+    }
+
+    #[test]
+    fn start_times_skipped_by_daylight_saving_time_move_forward() {
+        let mut schedule = Schedule::daily(hms(2, 30, 0), hms(12, 0, 0));
+        schedule.set_time_zone(new_york());
+        // 02:30 doesn't exist on 2021-03-14; 03:30 EDT is 07:30 UTC.
+        let day = Utc.ymd(2021, 3, 14);
+        assert_eq!(
+            schedule.time_until_start(day.and_hms(6, 0, 0)),
+            Duration::from_secs(3600 + 1800)
+        );
+        assert!(schedule.is_active(day.and_hms(7, 30, 0)));
+    }
+
+    #[test]
+    fn weekly_schedule() {
+        let schedule = Schedule::weekly(Weekday::Sun, hms(17, 0, 0), Weekday::Fri, hms(17, 0, 0));
+        // 2021-03-05 is a Friday.
+        assert!(schedule.is_active(Utc.ymd(2021, 3, 5).and_hms(16, 0, 0)));
+        assert!(!schedule.is_active(Utc.ymd(2021, 3, 5).and_hms(18, 0, 0)));
+        assert!(!schedule.is_active(Utc.ymd(2021, 3, 7).and_hms(16, 0, 0)));
+        assert!(schedule.is_active(Utc.ymd(2021, 3, 7).and_hms(18, 0, 0)));
+        assert!(schedule.is_same_session(
+            Utc.ymd(2021, 3, 1).and_hms(0, 0, 0),
+            Utc.ymd(2021, 3, 5).and_hms(0, 0, 0),
+        ));
+    }
+
+    #[test]
+    fn full_day_schedule() {
+        let schedule = Schedule::daily(hms(0, 0, 0), hms(0, 0, 0));
+        let day = Utc.ymd(2021, 3, 1);
+        assert!(schedule.is_active(day.and_hms(0, 0, 0)));
+        assert!(schedule.is_active(day.and_hms(23, 59, 59)));
+        assert!(!schedule.is_same_session(day.and_hms(12, 0, 0), day.succ().and_hms(12, 0, 0)));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::dict::IsFieldDefinition;
+use crate::{FixValue, TagU16};
+use futures::channel::{mpsc, oneshot};
+use std::fmt;
+
+/// An application message to be sent over a FIX session via a
+/// [`SessionSender`].
+///
+/// Only `MsgType <35>` and the body of the message are provided by the user:
+/// the standard header (`BeginString <8>`, CompIDs, `MsgSeqNum <34>` and
+/// `SendingTime <52>`) and trailer are added by the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundMessage {
+    msg_type: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl OutboundMessage {
+    /// Creates a new [`OutboundMessage`] of type `msg_type` with an empty
+    /// body.
+    pub fn new(msg_type: &[u8]) -> Self {
+        Self {
+            msg_type: msg_type.to_vec(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Appends `field` with the given `value` to the body.
+    pub fn set<'a, F, T>(&mut self, field: &F, value: T)
+    where
+        F: IsFieldDefinition,
+        T: FixValue<'a>,
+    {
+        self.set_any(field.tag(), value)
+    }
+
+    /// Appends a field with the given `tag` and `value` to the body.
+    pub fn set_any<'a, T>(&mut self, tag: TagU16, value: T)
+    where
+        T: FixValue<'a>,
+    {
+        tag.serialize(&mut self.body);
+        self.body.push(b'=');
+        value.serialize(&mut self.body);
+        self.body.push(0x1);
+    }
+
+    /// Returns the `MsgType <35>` of `self`.
+    pub fn msg_type(&self) -> &[u8] {
+        &self.msg_type[..]
+    }
+
+    /// Returns the encoded body of `self`.
+    pub fn body(&self) -> &[u8] {
+        &self.body[..]
+    }
+}
+
+/// A request to send an [`OutboundMessage`], together with the channel over
+/// which its `MsgSeqNum <34>` is sent back.
+pub(crate) type OutboundRequest = (OutboundMessage, Reply);
+
+/// The channel over which the outcome of an [`OutboundRequest`] is sent back.
+pub(crate) type Reply = oneshot::Sender<Result<u64, SendError>>;
+
+/// A cloneable handle to a [`FixConnection`](super::FixConnection) which
+/// allows application code to send messages while the session is running.
+///
+/// Messages sent before the session is established are queued until Logon.
+#[derive(Debug, Clone)]
+pub struct SessionSender {
+    sender: mpsc::UnboundedSender<OutboundRequest>,
+}
+
+impl SessionSender {
+    pub(crate) fn new(sender: mpsc::UnboundedSender<OutboundRequest>) -> Self {
+        Self { sender }
+    }
+
+    /// Sends `message` over the session. Returns the `MsgSeqNum <34>` that the
+    /// session assigned to it, once the message has been persisted and
+    /// written. Messages held back by the [`Throttle`](super::Throttle) of
+    /// the session are only written once its limits allow it.
+    pub async fn send(&self, message: OutboundMessage) -> Result<u64, SendError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.sender
+            .unbounded_send((message, reply_sender))
+            .map_err(|_| SendError::Closed)?;
+        reply_receiver.await.map_err(|_| SendError::Closed)?
+    }
+}
+
+/// The error returned by [`SessionSender::send`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The session is gone, or the transport was closed before the message
+    /// could be written.
+    Closed,
+    /// The message was refused by the [`Throttle`](super::Throttle) of the
+    /// session.
+    Throttled,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "The FIX session is closed"),
+            Self::Throttled => write!(f, "The message exceeds the rate limits of the session"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::fix44;
+
+    #[test]
+    fn body_is_encoded_as_tag_value_pairs() {
+        let mut message = OutboundMessage::new(b"D");
+        message.set(fix44::CL_ORD_ID, "foo");
+        message.set_any(TagU16::new(54).unwrap(), fix44::Side::Buy);
+        assert_eq!(message.msg_type(), b"D");
+        assert_eq!(message.body(), b"11=foo\x0154=1\x01");
+    }
+
+    #[test]
+    fn send_fails_once_session_is_dropped() {
+        let (sender, receiver) = mpsc::unbounded();
+        let sender = SessionSender::new(sender);
+        drop(receiver);
+        let result = futures::executor::block_on(sender.send(OutboundMessage::new(b"D")));
+        assert_eq!(result, Err(SendError::Closed));
+    }
+}
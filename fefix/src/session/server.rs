@@ -0,0 +1,474 @@
+use super::{
+    add_duration, errs, read_message, Backend, Clock, FixConnection, FixConnectionBuilder, Role,
+    SessionAdmin, SessionSender, SystemClock,
+};
+use crate::definitions::fix44;
+use crate::tagvalue::{Decoder, Encoder, FieldAccess, Message};
+use crate::Dictionary;
+use chrono::{DateTime, Utc};
+use futures::future::{self, Either};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::Unpin;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Uniquely identifies a FIX session from the point of view of the local
+/// party, i.e. `sender_comp_id` is our own `SenderCompID <49>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionId {
+    /// The `BeginString <8>` of the session.
+    pub begin_string: String,
+    /// The `SenderCompID <49>` of the local party.
+    pub sender_comp_id: String,
+    /// The `SenderCompID <49>` of the counterparty.
+    pub target_comp_id: String,
+}
+
+impl fmt::Display for SessionId {
+    /// Formats `self` as e.g. `FIX.4.4:SENDER->TARGET`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}->{}",
+            self.begin_string, self.sender_comp_id, self.target_comp_id
+        )
+    }
+}
+
+/// A FIX acceptor which serves many sessions from a single listening port.
+///
+/// The first message of every new connection must be a `Logon <A>`, which is
+/// used to route the connection to the session configured for its
+/// `BeginString <8>`, `SenderCompID <49>` and `TargetCompID <56>`. Each
+/// session owns its [`FixConnection`] (and thus its seq. numbers, message
+/// store and timers) and its [`Backend`]; application code reaches a session
+/// via [`FixServer::sender`] and [`FixServer::admin`]. Connections for unknown sessions, as
+/// well as connections for sessions which are already logged on, are refused
+/// with a `Logout <5>`, and connections which don't send anything within the
+/// Logon timeout (see [`FixServer::set_logon_timeout`]) are closed.
+///
+/// [`FixServer`] doesn't depend on any particular executor or network stack:
+/// you can either feed it a [`Stream`] of connections via [`FixServer::run`],
+/// or hand each connection to [`FixServer::serve`] yourself.
+#[derive(Debug)]
+pub struct FixServer<B> {
+    dictionary: Dictionary,
+    clock: Arc<dyn Clock>,
+    logon_timeout: Duration,
+    sessions: HashMap<SessionId, Session<B>>,
+}
+
+#[derive(Debug)]
+struct Session<B> {
+    clock: Arc<dyn Clock>,
+    sender: SessionSender,
+    admin: SessionAdmin,
+    // `None` while the session is logged on.
+    slot: Mutex<Option<(FixConnection, B)>>,
+}
+
+impl<B> FixServer<B>
+where
+    B: Backend,
+{
+    /// Creates a new [`FixServer`] with no sessions. Inbound messages will be
+    /// decoded according to `dictionary`.
+    pub fn new(dictionary: Dictionary) -> Self {
+        Self {
+            dictionary,
+            clock: Arc::new(SystemClock),
+            logon_timeout: Duration::from_secs(10),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Sets the [`Clock`] used by `self` before connections are handed over
+    /// to their sessions, i.e. for Logon timeouts and for the `Logout <5>`
+    /// messages which refuse unknown sessions. A [`SystemClock`] is used by
+    /// default.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Sets how long new connections are given to send their `Logon <A>`
+    /// before being closed. The default is 10 seconds.
+    pub fn set_logon_timeout(&mut self, timeout: Duration) {
+        self.logon_timeout = timeout;
+    }
+
+    /// Configures a new session, which will be served by `backend`. The
+    /// [`Role`] of `builder` is always set to [`Role::Acceptor`]. Any previous
+    /// session with the same [`SessionId`] is replaced.
+    pub fn add_session(&mut self, mut builder: FixConnectionBuilder, backend: B) {
+        builder.set_role(Role::Acceptor);
+        let connection = builder.build();
+        let session_id = connection.session_id();
+        let session = Session {
+            clock: connection.clock().clone(),
+            sender: connection.sender(),
+            admin: connection.admin(),
+            slot: Mutex::new(Some((connection, backend))),
+        };
+        self.sessions.insert(session_id, session);
+    }
+
+    /// Returns an [`Iterator`] over the [`SessionId`]s of all configured
+    /// sessions.
+    pub fn session_ids(&self) -> impl Iterator<Item = &SessionId> {
+        self.sessions.keys()
+    }
+
+    /// Returns `true` if the session identified by `session_id` is currently
+    /// logged on.
+    pub fn is_active(&self, session_id: &SessionId) -> bool {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.slot.lock().unwrap().is_none())
+            .unwrap_or(false)
+    }
+
+    /// Returns a [`SessionSender`] for sending application messages over the
+    /// session identified by `session_id`, or `None` if there's no such
+    /// session.
+    pub fn sender(&self, session_id: &SessionId) -> Option<SessionSender> {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.sender.clone())
+    }
+
+    /// Returns a [`SessionAdmin`] for the session identified by `session_id`,
+    /// or `None` if there's no such session.
+    pub fn admin(&self, session_id: &SessionId) -> Option<SessionAdmin> {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.admin.clone())
+    }
+
+    /// Serves all connections yielded by `connections` concurrently, until
+    /// the [`Stream`] is exhausted and all sessions are over.
+    pub async fn run<S, I, O>(&self, connections: S)
+    where
+        S: Stream<Item = (I, O)>,
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        connections
+            .for_each_concurrent(None, |(input, output)| self.serve(input, output))
+            .await;
+    }
+
+    /// Serves a single connection, from the initial `Logon <A>` until the
+    /// transport is closed.
+    pub async fn serve<I, O>(&self, input: I, output: O)
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        self.serve_peer(None, input, output).await
+    }
+
+    /// Like [`FixServer::serve`], but the counterparty's address `peer_addr`
+    /// is handed over to [`Backend::authenticate`].
+    pub async fn serve_from<I, O>(&self, peer_addr: SocketAddr, input: I, output: O)
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        self.serve_peer(Some(peer_addr), input, output).await
+    }
+
+    async fn serve_peer<I, O>(&self, peer_addr: Option<SocketAddr>, mut input: I, mut output: O)
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        let mut decoder = Decoder::new(self.dictionary.clone()).buffered();
+        let timeout = match add_duration(self.clock.now(), self.logon_timeout) {
+            Some(deadline) => self.clock.sleep_until(deadline),
+            None => Box::pin(future::pending()),
+        };
+        let logon = {
+            let logon = read_message(&mut input, &mut decoder);
+            futures::pin_mut!(logon);
+            match future::select(logon, timeout).await {
+                Either::Left((Some(logon), _)) => logon,
+                _ => return,
+            }
+        };
+        if logon.fv::<&[u8], _>(fix44::MSG_TYPE) != Ok(b"A") {
+            return;
+        }
+        let session_id = counterparty_session_id(&logon);
+        let session = self.sessions.get(&session_id);
+        let lease = session.and_then(|session| {
+            let slot = &session.slot;
+            let leased = slot.lock().unwrap().take()?;
+            Some(Lease {
+                slot,
+                session: Some(leased),
+            })
+        });
+        let mut lease = match lease {
+            Some(lease) => lease,
+            None => {
+                let (text, now) = match session {
+                    Some(session) => (errs::session_already_active(), session.clock.now()),
+                    None => (errs::unknown_session(), self.clock.now()),
+                };
+                let logout = make_logout(&logon, text.as_str(), now);
+                output.write_all(&logout).await.ok();
+                return;
+            }
+        };
+        // The session processes the `Logon <A>` itself, so we put it back in
+        // front of the rest of the input.
+        let logon = futures::io::Cursor::new(logon.as_bytes().to_vec());
+        let (connection, backend) = lease.session.as_mut().unwrap();
+        connection.set_peer_addr(peer_addr);
+        connection
+            .start(
+                backend.clone(),
+                logon.chain(input),
+                output,
+                Decoder::new(self.dictionary.clone()),
+            )
+            .await;
+    }
+}
+
+/// A session taken out of its slot while logged on. It's put back once
+/// dropped, even if the future serving the connection is dropped before the
+/// session is over.
+struct Lease<'a, B> {
+    slot: &'a Mutex<Option<(FixConnection, B)>>,
+    session: Option<(FixConnection, B)>,
+}
+
+impl<'a, B> Drop for Lease<'a, B> {
+    fn drop(&mut self) {
+        // Don't panic while unwinding because of a poisoned lock.
+        if let Ok(mut slot) = self.slot.lock() {
+            *slot = self.session.take();
+        }
+    }
+}
+
+/// Returns the [`SessionId`] that a `Logon <A>` refers to. CompIDs are swapped,
+/// because the counterparty's `SenderCompID <49>` is our `TargetCompID <56>`.
+fn counterparty_session_id(logon: &Message<&[u8]>) -> SessionId {
+    let field = |field| logon.fv::<&str, _>(field).unwrap_or("").to_string();
+    SessionId {
+        begin_string: field(fix44::BEGIN_STRING),
+        sender_comp_id: field(fix44::TARGET_COMP_ID),
+        target_comp_id: field(fix44::SENDER_COMP_ID),
+    }
+}
+
+/// Builds the `Logout <5>` used to refuse a `Logon <A>` outside of any
+/// session, so it always has `MsgSeqNum <34>` 1.
+fn make_logout(logon: &Message<&[u8]>, text: &str, now: DateTime<Utc>) -> Vec<u8> {
+    let begin_string = logon.fv::<&[u8], _>(fix44::BEGIN_STRING).unwrap_or(b"");
+    let sender_comp_id = logon.fv::<&str, _>(fix44::TARGET_COMP_ID).unwrap_or("");
+    let target_comp_id = logon.fv::<&str, _>(fix44::SENDER_COMP_ID).unwrap_or("");
+    let mut encoder = Encoder::<crate::tagvalue::Config>::default();
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(begin_string, &mut buffer, b"5");
+    msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
+    msg.set(fix44::TARGET_COMP_ID, target_comp_id);
+    msg.set(fix44::MSG_SEQ_NUM, 1u64);
+    msg.set(fix44::SENDING_TIME, now);
+    msg.set(fix44::TEXT, text);
+    msg.wrap().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::backends::Noop;
+    use crate::session::{HeartbeatRule, MemoryTransport, MockClock, OutboundMessage};
+    use crate::tagvalue::DecoderBuffered;
+    use chrono::TimeZone;
+    use futures::FutureExt;
+
+    fn server() -> FixServer<Noop> {
+        let mut server = FixServer::new(Dictionary::fix44());
+        for client in &["ALICE", "BOB"] {
+            let mut builder = FixConnectionBuilder::default();
+            builder.set_sender_comp_id("SERVER");
+            builder.set_target_comp_id(*client);
+            builder.set_heartbeat_rule(HeartbeatRule::Any);
+            server.add_session(builder, Noop);
+        }
+        server
+    }
+
+    fn logon(sender_comp_id: &str, target_comp_id: &str, msg_seq_num: u64) -> Vec<u8> {
+        let mut encoder = Encoder::<crate::tagvalue::Config>::default();
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(b"FIX.4.4", &mut buffer, b"A");
+        msg.set(fix44::SENDER_COMP_ID, sender_comp_id);
+        msg.set(fix44::TARGET_COMP_ID, target_comp_id);
+        msg.set(fix44::MSG_SEQ_NUM, msg_seq_num);
+        msg.set(fix44::SENDING_TIME, chrono::Utc::now());
+        msg.set(fix44::ENCRYPT_METHOD, fix44::EncryptMethod::None);
+        msg.set(fix44::HEART_BT_INT, 30u64);
+        msg.wrap().to_vec()
+    }
+
+    /// Serves a connection which sends `bytes` and then closes. Returns the
+    /// first outbound message.
+    fn serve(server: &FixServer<Noop>, bytes: Vec<u8>) -> (String, String) {
+        let input = futures::io::Cursor::new(bytes);
+        let mut output = futures::io::Cursor::new(Vec::new());
+        futures::executor::block_on(server.serve(input, &mut output));
+        let mut decoder: DecoderBuffered = Decoder::new(Dictionary::fix44()).buffered();
+        let mut input = futures::io::Cursor::new(output.into_inner());
+        let msg = futures::executor::block_on(read_message(&mut input, &mut decoder)).unwrap();
+        (
+            msg.fv::<&str, _>(fix44::MSG_TYPE).unwrap().to_string(),
+            msg.fv::<&str, _>(fix44::TARGET_COMP_ID)
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn logons_are_routed_by_comp_id() {
+        let server = &server();
+        assert_eq!(
+            serve(server, logon("ALICE", "SERVER", 1)),
+            ("A".to_string(), "ALICE".to_string())
+        );
+        assert_eq!(
+            serve(server, logon("BOB", "SERVER", 1)),
+            ("A".to_string(), "BOB".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_sessions_are_refused() {
+        let server = &server();
+        assert_eq!(
+            serve(server, logon("CHARLIE", "SERVER", 1)),
+            ("5".to_string(), "CHARLIE".to_string())
+        );
+    }
+
+    #[test]
+    fn sessions_are_available_again_after_disconnecting() {
+        let server = &server();
+        serve(server, logon("ALICE", "SERVER", 1));
+        assert!(!server.is_active(&alice()));
+        // Seq. numbers are preserved across connections.
+        assert_eq!(
+            serve(server, logon("ALICE", "SERVER", 2)),
+            ("A".to_string(), "ALICE".to_string())
+        );
+    }
+
+    #[test]
+    fn refusals_are_stamped_by_the_clock_of_the_session() {
+        let server_time = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let session_time = Utc.ymd(2021, 3, 1).and_hms(13, 0, 0);
+        let mut server = FixServer::new(Dictionary::fix44());
+        server.set_clock(MockClock::new(server_time));
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id("SERVER");
+        builder.set_target_comp_id("ALICE");
+        builder.set_clock(MockClock::new(session_time));
+        server.add_session(builder, Noop);
+        let sending_time = |bytes: Vec<u8>| {
+            let mut output = futures::io::Cursor::new(Vec::new());
+            let input = futures::io::Cursor::new(bytes);
+            futures::executor::block_on(server.serve(input, &mut output));
+            let mut decoder = Decoder::new(Dictionary::fix44()).buffered();
+            let mut input = futures::io::Cursor::new(output.into_inner());
+            let msg = futures::executor::block_on(read_message(&mut input, &mut decoder)).unwrap();
+            assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("5"));
+            msg.fv::<&str, _>(fix44::SENDING_TIME).unwrap().to_string()
+        };
+        assert_eq!(
+            sending_time(logon("CHARLIE", "SERVER", 1)),
+            "20210301-12:00:00.000"
+        );
+        let (mut client, transport) = MemoryTransport::pair();
+        futures::executor::block_on(client.write_all(&logon("ALICE", "SERVER", 1))).unwrap();
+        let mut serving = Box::pin(server.serve(transport, futures::io::sink()));
+        assert_eq!((&mut serving).now_or_never(), None);
+        assert_eq!(
+            sending_time(logon("ALICE", "SERVER", 1)),
+            "20210301-13:00:00.000"
+        );
+    }
+
+    fn alice() -> SessionId {
+        SessionId {
+            begin_string: "FIX.4.4".to_string(),
+            sender_comp_id: "SERVER".to_string(),
+            target_comp_id: "ALICE".to_string(),
+        }
+    }
+
+    #[test]
+    fn silent_connections_time_out() {
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut server = server();
+        server.set_clock(clock.clone());
+        server.set_logon_timeout(Duration::from_secs(5));
+        let (_client, transport) = MemoryTransport::pair();
+        let mut serving = Box::pin(server.serve(transport, futures::io::sink()));
+        assert_eq!((&mut serving).now_or_never(), None);
+        clock.advance(chrono::Duration::seconds(4));
+        assert_eq!((&mut serving).now_or_never(), None);
+        clock.advance(chrono::Duration::seconds(1));
+        assert_eq!(serving.now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn sessions_are_released_when_serving_is_cancelled() {
+        let server = server();
+        let (mut client, transport) = MemoryTransport::pair();
+        futures::executor::block_on(client.write_all(&logon("ALICE", "SERVER", 1))).unwrap();
+        let mut serving = Box::pin(server.serve(transport, futures::io::sink()));
+        assert_eq!((&mut serving).now_or_never(), None);
+        assert!(server.is_active(&alice()));
+        drop(serving);
+        assert!(!server.is_active(&alice()));
+        assert_eq!(
+            serve(&server, logon("ALICE", "SERVER", 2)),
+            ("A".to_string(), "ALICE".to_string())
+        );
+    }
+
+    #[test]
+    fn sessions_are_reachable_by_session_id() {
+        let server = server();
+        let mut unknown = alice();
+        unknown.target_comp_id = "CHARLIE".to_string();
+        assert!(server.sender(&unknown).is_none());
+        assert!(server.admin(&unknown).is_none());
+        let sender = server.sender(&alice()).unwrap();
+        let admin = server.admin(&alice()).unwrap();
+        let (mut client, transport) = MemoryTransport::pair();
+        futures::executor::block_on(client.write_all(&logon("ALICE", "SERVER", 1))).unwrap();
+        let (input, output) = transport.split();
+        let serving = server.serve(input, output);
+        let requests = async {
+            // Messages are queued until Logon.
+            let msg_seq_num = sender.send(OutboundMessage::new(b"D")).await.unwrap();
+            let state = admin.state().await.unwrap();
+            (state.is_logged_on, msg_seq_num)
+        };
+        futures::pin_mut!(serving, requests);
+        match futures::executor::block_on(future::select(requests, serving)) {
+            Either::Left((outcome, _)) => assert_eq!(outcome, (true, 2)),
+            Either::Right(_) => panic!("The session ended unexpectedly"),
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use super::{
+    ApplVerId, Backoff, Config, Endpoint, FileStore, FixConnectionBuilder, Role, Schedule,
+    SessionId, ZoneInfo,
+};
+use crate::Dictionary;
+use chrono::{FixedOffset, NaiveTime, Weekday};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Session settings in the configuration file format of QuickFIX, i.e. an
+/// INI-like file made of a `[DEFAULT]` section and one `[SESSION]` section
+/// per FIX session.
+///
+/// Settings in `[DEFAULT]` apply to all sessions, unless overridden in their
+/// own `[SESSION]` section. Lines starting with `#` or `;` are comments.
+///
+/// # Examples
+///
+/// ```
+/// use fefix::session::Settings;
+///
+/// let settings = Settings::parse(
+///     "[DEFAULT]\n\
+///      ConnectionType=initiator\n\
+///      SenderCompID=TW\n\
+///      [SESSION]\n\
+///      BeginString=FIX.4.2\n\
+///      TargetCompID=INCA\n\
+///      HeartBtInt=10\n\
+///      SocketConnectHost=127.0.0.1\n\
+///      SocketConnectPort=3859\n",
+/// )
+/// .unwrap();
+/// let session = &settings.sessions()[0];
+/// assert_eq!(session.get("SenderCompID"), Some("TW"));
+/// assert_eq!(session.endpoints().unwrap()[0].to_string(), "127.0.0.1:3859");
+/// let connection = session.builder().unwrap().build();
+/// assert_eq!(connection.session_id().target_comp_id, "INCA");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    sessions: Vec<SessionSettings>,
+}
+
+impl Settings {
+    /// Parses the contents of a QuickFIX configuration file. Every session
+    /// must have a `BeginString`, a `SenderCompID` and a `TargetCompID`,
+    /// which must uniquely identify it.
+    pub fn parse(input: &str) -> Result<Self, SettingsError> {
+        let mut defaults = Values::new();
+        let mut sessions: Vec<(usize, Values)> = Vec::new();
+        // The section which `Key=Value` pairs belong to: `None` for
+        // `[DEFAULT]`, the index of the session otherwise.
+        let mut section: Option<Option<usize>> = None;
+        for (i, line) in input.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                if name.eq_ignore_ascii_case("DEFAULT") {
+                    section = Some(None);
+                } else if name.eq_ignore_ascii_case("SESSION") {
+                    sessions.push((line_number, Values::new()));
+                    section = Some(Some(sessions.len() - 1));
+                } else {
+                    return Err(SettingsError::UnknownSection {
+                        line: line_number,
+                        name: name.to_string(),
+                    });
+                }
+                continue;
+            }
+            let (key, value) = match (line.find('='), section) {
+                (Some(i), Some(_)) if i > 0 => (line[..i].trim(), line[i + 1..].trim()),
+                _ => return Err(SettingsError::Syntax { line: line_number }),
+            };
+            let values = match section {
+                Some(Some(i)) => &mut sessions[i].1,
+                _ => &mut defaults,
+            };
+            values.insert(key.to_string(), (value.to_string(), line_number));
+        }
+        let mut session_ids = HashSet::new();
+        let sessions = sessions
+            .into_iter()
+            .map(|(line, values)| {
+                let mut merged = defaults.clone();
+                merged.extend(values);
+                let session = SessionSettings {
+                    line,
+                    values: merged,
+                };
+                let session_id = session.session_id()?;
+                if !session_ids.insert(session_id) {
+                    return Err(SettingsError::DuplicateSession { line });
+                }
+                Ok(session)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { sessions })
+    }
+
+    /// Reads and parses the QuickFIX configuration file at `path`. See
+    /// [`Settings::parse`].
+    pub fn load<P>(path: P) -> Result<Self, SettingsError>
+    where
+        P: AsRef<Path>,
+    {
+        let input = std::fs::read_to_string(path).map_err(SettingsError::Io)?;
+        Self::parse(&input)
+    }
+
+    /// Returns the settings of all sessions, in the same order as their
+    /// `[SESSION]` sections.
+    pub fn sessions(&self) -> &[SessionSettings] {
+        &self.sessions
+    }
+}
+
+// Values indexed by key, together with the line they come from.
+type Values = HashMap<String, (String, usize)>;
+
+/// The settings of a single `[SESSION]` section, including those inherited
+/// from `[DEFAULT]`.
+#[derive(Debug, Clone)]
+pub struct SessionSettings {
+    line: usize,
+    values: Values,
+}
+
+impl SessionSettings {
+    /// Returns the line number of the `[SESSION]` header.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the raw value of the setting `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|(value, _)| value.as_str())
+    }
+
+    /// Returns the [`SessionId`] of the session, made of `BeginString`,
+    /// `SenderCompID` and `TargetCompID`.
+    pub fn session_id(&self) -> Result<SessionId, SettingsError> {
+        Ok(SessionId {
+            begin_string: self.require("BeginString")?.to_string(),
+            sender_comp_id: self.require("SenderCompID")?.to_string(),
+            target_comp_id: self.require("TargetCompID")?.to_string(),
+        })
+    }
+
+    /// Creates a [`FixConnectionBuilder`] out of the session settings. The
+    /// following keys are supported:
+    ///
+    /// - `BeginString`, `SenderCompID` and `TargetCompID`.
+    /// - `ConnectionType`, either `initiator` or `acceptor`.
+    /// - `HeartBtInt`, in seconds. It's mandatory for initiators.
+    /// - `StartTime` and `EndTime` (`HH:MM:SS`), optionally together with
+    ///   `StartDay` and `EndDay` for weekly sessions, and `TimeZone` (`UTC`,
+    ///   a fixed offset such as `+01:00`, or an IANA time zone such as
+    ///   `America/New_York`, see [`ZoneInfo::load`]).
+    /// - `ResetOnLogon`, `EnableNextExpectedMsgSeqNum` and
+    ///   `UseDataDictionary` (`Y` or `N`), and `MaxLatency` in seconds.
+    /// - `FileStorePath`, the directory of a [`FileStore`] which is opened
+    ///   right away. Files are prefixed by `BeginString-SenderCompID-TargetCompID`.
+    /// - `DefaultApplVerID` and `AppDataDictionary`, for FIXT.1.1 sessions.
+    ///
+    /// Other keys are ignored, but see [`SessionSettings::endpoints`],
+    /// [`SessionSettings::accept_port`], [`SessionSettings::backoff`] and
+    /// [`SessionSettings::data_dictionary`].
+    pub fn builder(&self) -> Result<FixConnectionBuilder, SettingsError> {
+        let session_id = self.session_id()?;
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_begin_string(session_id.begin_string.as_str());
+        builder.set_sender_comp_id(session_id.sender_comp_id.as_str());
+        builder.set_target_comp_id(session_id.target_comp_id.as_str());
+        let role = self.parse("ConnectionType", |value| match value {
+            "initiator" => Some(Role::Initiator),
+            "acceptor" => Some(Role::Acceptor),
+            _ => None,
+        })?;
+        let role = role.ok_or_else(|| self.missing("ConnectionType"))?;
+        builder.set_role(role);
+        match self.parse("HeartBtInt", |value| value.parse().ok())? {
+            Some(secs) => builder.set_heartbeat(Duration::from_secs(secs)),
+            None if role == Role::Initiator => return Err(self.missing("HeartBtInt")),
+            None => {}
+        }
+        if let Some(schedule) = self.schedule()? {
+            builder.set_schedule(schedule);
+        }
+        let mut config = Config::default();
+        if let Some(reset) = self.parse("ResetOnLogon", parse_bool)? {
+            config.set_reset_seq_num_on_logon(reset);
+        }
+        if let Some(enable) = self.parse("EnableNextExpectedMsgSeqNum", parse_bool)? {
+            config.set_enable_next_expected_msg_seq_num(enable);
+        }
+        if let Some(validate) = self.parse("UseDataDictionary", parse_bool)? {
+            config.set_validate_messages(validate);
+        }
+        if let Some(secs) = self.parse("MaxLatency", |value| value.parse().ok())? {
+            config.set_max_allowed_latency(Duration::from_secs(secs));
+        }
+        builder.set_config(config);
+        if let Some(dir) = self.get("FileStorePath") {
+            let prefix = PathBuf::from(dir).join(format!(
+                "{}-{}-{}",
+                session_id.begin_string, session_id.sender_comp_id, session_id.target_comp_id
+            ));
+            let store = FileStore::open(prefix).map_err(SettingsError::Io)?;
+            builder.set_message_store(store);
+        }
+        let appl_ver_id = self.parse("DefaultApplVerID", parse_appl_ver_id)?;
+        if let Some(appl_ver_id) = appl_ver_id {
+            builder.set_default_appl_ver_id(appl_ver_id);
+            if let Some(dictionary) = self.load_dictionary("AppDataDictionary")? {
+                builder.add_appl_dictionary(appl_ver_id, dictionary);
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Returns the counterparty's [`Endpoint`]s, from `SocketConnectHost`
+    /// and `SocketConnectPort` followed by the backup ones, i.e.
+    /// `SocketConnectHost1`, `SocketConnectPort1` and so on.
+    pub fn endpoints(&self) -> Result<Vec<Endpoint>, SettingsError> {
+        let mut endpoints = Vec::new();
+        for i in 0.. {
+            let suffix = if i == 0 { String::new() } else { i.to_string() };
+            let host_key = format!("SocketConnectHost{}", suffix);
+            let port_key = format!("SocketConnectPort{}", suffix);
+            let host = match self.get(&host_key) {
+                Some(host) => host,
+                None => break,
+            };
+            let port = self.parse(&port_key, |value| value.parse().ok())?;
+            let port = port.ok_or_else(|| self.missing(&port_key))?;
+            endpoints.push(Endpoint::new(host, port));
+        }
+        Ok(endpoints)
+    }
+
+    /// Returns `SocketAcceptPort`, if any.
+    pub fn accept_port(&self) -> Result<Option<u16>, SettingsError> {
+        self.parse("SocketAcceptPort", |value| value.parse().ok())
+    }
+
+    /// Returns a constant [`Backoff`] of `ReconnectInterval` seconds (30 by
+    /// default), as QuickFIX initiators don't back off exponentially.
+    pub fn backoff(&self) -> Result<Backoff, SettingsError> {
+        let secs = self.parse("ReconnectInterval", |value| value.parse().ok())?;
+        let interval = Duration::from_secs(secs.unwrap_or(30));
+        let mut backoff = Backoff::default();
+        backoff.set_initial(interval);
+        backoff.set_max(interval);
+        backoff.set_multiplier(1);
+        Ok(backoff)
+    }
+
+    /// Loads the QuickFIX dictionary at `DataDictionary`, if any. Relative
+    /// paths are resolved against the current working directory.
+    pub fn data_dictionary(&self) -> Result<Option<Dictionary>, SettingsError> {
+        self.load_dictionary("DataDictionary")
+    }
+
+    fn load_dictionary(&self, key: &str) -> Result<Option<Dictionary>, SettingsError> {
+        let (path, line) = match self.values.get(key) {
+            Some((path, line)) => (path, *line),
+            None => return Ok(None),
+        };
+        let error = |reason: String| SettingsError::Dictionary {
+            line,
+            path: path.clone(),
+            reason,
+        };
+        let spec = std::fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+        Dictionary::from_quickfix_spec(spec)
+            .map(Some)
+            .map_err(|err| error(format!("{:?}", err)))
+    }
+
+    fn schedule(&self) -> Result<Option<Schedule>, SettingsError> {
+        let parse_time = |value: &str| NaiveTime::parse_from_str(value, "%H:%M:%S").ok();
+        let start_time = self.parse("StartTime", parse_time)?;
+        let end_time = self.parse("EndTime", parse_time)?;
+        let (start_time, end_time) = match (start_time, end_time) {
+            (None, None) => return Ok(None),
+            (Some(start_time), Some(end_time)) => (start_time, end_time),
+            (Some(_), None) => return Err(self.missing("EndTime")),
+            (None, Some(_)) => return Err(self.missing("StartTime")),
+        };
+        let parse_day = |value: &str| value.parse::<Weekday>().ok();
+        let start_day = self.parse("StartDay", parse_day)?;
+        let end_day = self.parse("EndDay", parse_day)?;
+        let mut schedule = match (start_day, end_day) {
+            (None, None) => Schedule::daily(start_time, end_time),
+            (Some(start_day), Some(end_day)) => {
+                Schedule::weekly(start_day, start_time, end_day, end_time)
+            }
+            (Some(_), None) => return Err(self.missing("EndDay")),
+            (None, Some(_)) => return Err(self.missing("StartDay")),
+        };
+        match self.parse("TimeZone", parse_time_zone)? {
+            Some(ScheduleTimeZone::Fixed(offset)) => schedule.set_time_zone(offset),
+            Some(ScheduleTimeZone::Zone(zone)) => schedule.set_time_zone(zone),
+            None => (),
+        }
+        Ok(Some(schedule))
+    }
+
+    fn require(&self, key: &str) -> Result<&str, SettingsError> {
+        self.get(key).ok_or_else(|| self.missing(key))
+    }
+
+    fn missing(&self, key: &str) -> SettingsError {
+        SettingsError::MissingKey {
+            line: self.line,
+            key: key.to_string(),
+        }
+    }
+
+    /// Parses the value of `key` with `f`, if present.
+    fn parse<T, F>(&self, key: &str, f: F) -> Result<Option<T>, SettingsError>
+    where
+        F: FnOnce(&str) -> Option<T>,
+    {
+        match self.values.get(key) {
+            Some((value, line)) => f(value)
+                .map(Some)
+                .ok_or_else(|| SettingsError::InvalidValue {
+                    line: *line,
+                    key: key.to_string(),
+                    value: value.clone(),
+                }),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "Y" => Some(true),
+        "N" => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+enum ScheduleTimeZone {
+    Fixed(FixedOffset),
+    Zone(ZoneInfo),
+}
+
+/// Parses `UTC`, a fixed offset (e.g. `+01:00`) or the name of an IANA time
+/// zone (e.g. `Europe/London`).
+fn parse_time_zone(value: &str) -> Option<ScheduleTimeZone> {
+    if value == "UTC" || value == "GMT" {
+        return Some(ScheduleTimeZone::Fixed(FixedOffset::east(0)));
+    }
+    let sign = match value.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return ZoneInfo::load(value).ok().map(ScheduleTimeZone::Zone),
+    };
+    let time = NaiveTime::parse_from_str(value.get(1..)?, "%H:%M").ok()?;
+    let secs = time.signed_duration_since(NaiveTime::from_hms(0, 0, 0));
+    FixedOffset::east_opt(sign * secs.num_seconds() as i32).map(ScheduleTimeZone::Fixed)
+}
+
+/// Parses either the enumerated value of `ApplVerID <1128>` (e.g. `9`) or
+/// its QuickFIX name (e.g. `FIX.5.0SP2`).
+fn parse_appl_ver_id(value: &str) -> Option<ApplVerId> {
+    let value = match value {
+        "FIX.4.0" => "2",
+        "FIX.4.1" => "3",
+        "FIX.4.2" => "4",
+        "FIX.4.3" => "5",
+        "FIX.4.4" => "6",
+        "FIX.5.0" => "7",
+        "FIX.5.0SP1" => "8",
+        "FIX.5.0SP2" => "9",
+        value => value,
+    };
+    ApplVerId::from_value(value.as_bytes())
+}
+
+/// The error type returned when loading [`Settings`]. Line numbers start
+/// from 1.
+#[derive(Debug)]
+pub enum SettingsError {
+    /// The configuration file (or the message store) couldn't be accessed.
+    Io(io::Error),
+    /// The line is neither a section header, a `Key=Value` pair within a
+    /// section nor a comment.
+    Syntax {
+        /// The offending line.
+        line: usize,
+    },
+    /// The section is neither `[DEFAULT]` nor `[SESSION]`.
+    UnknownSection {
+        /// The line of the section header.
+        line: usize,
+        /// The name of the section.
+        name: String,
+    },
+    /// A session lacks a required setting.
+    MissingKey {
+        /// The line of the `[SESSION]` header.
+        line: usize,
+        /// The missing setting.
+        key: String,
+    },
+    /// A setting has an invalid value.
+    InvalidValue {
+        /// The line of the setting.
+        line: usize,
+        /// The name of the setting.
+        key: String,
+        /// The invalid value.
+        value: String,
+    },
+    /// A session has the same [`SessionId`] as a previous one.
+    DuplicateSession {
+        /// The line of the `[SESSION]` header.
+        line: usize,
+    },
+    /// A data dictionary couldn't be loaded.
+    Dictionary {
+        /// The line of the setting.
+        line: usize,
+        /// The path of the data dictionary.
+        path: String,
+        /// Why loading failed.
+        reason: String,
+    },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Syntax { line } => write!(
+                f,
+                "line {}: expected a section header, a Key=Value pair or a comment",
+                line
+            ),
+            Self::UnknownSection { line, name } => {
+                write!(f, "line {}: unknown section [{}]", line, name)
+            }
+            Self::MissingKey { line, key } => {
+                write!(f, "line {}: the session lacks the setting {}", line, key)
+            }
+            Self::InvalidValue { line, key, value } => {
+                write!(f, "line {}: invalid value '{}' for {}", line, value, key)
+            }
+            Self::DuplicateSession { line } => {
+                write!(f, "line {}: the session is defined more than once", line)
+            }
+            Self::Dictionary { line, path, reason } => write!(
+                f,
+                "line {}: can't load the data dictionary '{}': {}",
+                line, path, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    const EXAMPLE: &str =
+        include_str!("../../../examples/20_tokio_fix_initiator/quickfix_executor_config.ini");
+
+    #[test]
+    fn defaults_are_overridden_by_sessions() {
+        let settings = Settings::parse(EXAMPLE).unwrap();
+        let session = &settings.sessions()[0];
+        assert_eq!(session.get("SenderCompID"), Some("TW"));
+        assert_eq!(session.get("ReconnectInterval"), Some("30"));
+        assert_eq!(session.accept_port().unwrap(), Some(3859));
+        assert_eq!(
+            session.endpoints().unwrap(),
+            vec![Endpoint::new("127.0.0.1", 3859)]
+        );
+        assert_eq!(session.backoff().unwrap().delay(3), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn builder_applies_session_settings() {
+        let settings = Settings::parse(
+            "[DEFAULT]\n\
+             ConnectionType=acceptor\n\
+             [SESSION]\n\
+             BeginString=FIX.4.4\n\
+             SenderCompID=SENDER\n\
+             TargetCompID=TARGET\n\
+             StartTime=08:00:00\n\
+             EndTime=17:00:00\n\
+             TimeZone=+01:00\n",
+        )
+        .unwrap();
+        let session = &settings.sessions()[0];
+        let schedule = session.schedule().unwrap().unwrap();
+        let day = Utc.ymd(2021, 3, 1);
+        assert!(schedule.is_active(day.and_hms(7, 30, 0)));
+        assert!(!schedule.is_active(day.and_hms(16, 30, 0)));
+        let connection = session.builder().unwrap().build();
+        assert_eq!(
+            connection.session_id().to_string(),
+            "FIX.4.4:SENDER->TARGET"
+        );
+    }
+
+    #[test]
+    fn errors_point_at_offending_lines() {
+        let session = "[SESSION]\nBeginString=FIX.4.4\nSenderCompID=A\nTargetCompID=B\n";
+        let error = |input: &str| Settings::parse(input).unwrap_err().to_string();
+        assert_eq!(
+            error("SenderCompID=A\n"),
+            "line 1: expected a section header, a Key=Value pair or a comment"
+        );
+        assert_eq!(error("[SESSIONS]\n"), "line 1: unknown section [SESSIONS]");
+        assert_eq!(
+            error("[SESSION]\nBeginString=FIX.4.4\n"),
+            "line 1: the session lacks the setting SenderCompID"
+        );
+        assert_eq!(
+            error(&format!("{}{}", session, session)),
+            "line 5: the session is defined more than once"
+        );
+        let settings = Settings::parse(&format!(
+            "{}ConnectionType=initiator\nHeartBtInt=ten\n",
+            session
+        ))
+        .unwrap();
+        assert_eq!(
+            settings.sessions()[0].builder().unwrap_err().to_string(),
+            "line 6: invalid value 'ten' for HeartBtInt"
+        );
+    }
+
+    #[test]
+    fn initiators_require_heartbeat() {
+        let settings = Settings::parse(
+            "[SESSION]\nConnectionType=initiator\nBeginString=FIX.4.4\nSenderCompID=A\nTargetCompID=B\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            settings.sessions()[0].builder(),
+            Err(SettingsError::MissingKey { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn schedules_in_iana_time_zones() {
+        let settings = Settings::parse(
+            "[SESSION]\n\
+             BeginString=FIX.4.4\n\
+             SenderCompID=SENDER\n\
+             TargetCompID=TARGET\n\
+             StartTime=09:00:00\n\
+             EndTime=17:00:00\n\
+             TimeZone=America/New_York\n",
+        )
+        .unwrap();
+        let schedule = settings.sessions()[0].schedule().unwrap().unwrap();
+        // 09:00 is 14:00 UTC in winter and 13:00 UTC in summer.
+        assert!(!schedule.is_active(Utc.ymd(2021, 1, 4).and_hms(13, 30, 0)));
+        assert!(schedule.is_active(Utc.ymd(2021, 7, 5).and_hms(13, 30, 0)));
+    }
+
+    #[test]
+    fn time_zones_and_appl_ver_ids() {
+        let fixed = |value| match parse_time_zone(value) {
+            Some(ScheduleTimeZone::Fixed(offset)) => Some(offset),
+            _ => None,
+        };
+        assert_eq!(fixed("UTC"), Some(FixedOffset::east(0)));
+        assert_eq!(fixed("-05:30"), Some(FixedOffset::west(19800)));
+        assert!(parse_time_zone("Mars/Olympus_Mons").is_none());
+        assert!(parse_time_zone("../London").is_none());
+        assert_eq!(parse_appl_ver_id("FIX.5.0SP2"), Some(ApplVerId::Fix50Sp2));
+        assert_eq!(parse_appl_ver_id("9"), Some(ApplVerId::Fix50Sp2));
+    }
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the test modules of the session layer.
+
+use super::SessionId;
+
+/// The [`SessionId`] of a FIX 4.4 session from `SENDER` to `TARGET`.
+pub(crate) fn session_id() -> SessionId {
+    SessionId {
+        begin_string: "FIX.4.4".to_string(),
+        sender_comp_id: "SENDER".to_string(),
+        target_comp_id: "TARGET".to_string(),
+    }
+}
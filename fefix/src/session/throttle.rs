@@ -0,0 +1,283 @@
+use super::OutboundMessage;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// A limit of `messages` per `interval`, with bursts of up to `messages`
+/// messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    messages: u32,
+    interval: Duration,
+}
+
+impl RateLimit {
+    /// Creates a new [`RateLimit`] of `messages` per `interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `messages` is zero or greater than [`i32::MAX`].
+    pub fn new(messages: u32, interval: Duration) -> Self {
+        assert!(messages > 0, "Rate limits must allow at least one message");
+        assert!(
+            i32::try_from(messages).is_ok(),
+            "Rate limits can't exceed i32::MAX messages"
+        );
+        Self { messages, interval }
+    }
+
+    /// Creates a new [`RateLimit`] of `messages` per second.
+    pub fn per_second(messages: u32) -> Self {
+        Self::new(messages, Duration::from_secs(1))
+    }
+}
+
+/// What a [`Throttle`] does with application messages which exceed its
+/// limits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ThrottlePolicy {
+    /// Queue messages and send them as soon as the limits allow it.
+    Queue,
+    /// Refuse messages right away.
+    Reject,
+}
+
+/// Rate limits for outbound application messages of a
+/// [`FixConnection`](super::FixConnection), e.g. to honour the order entry
+/// limits of a venue.
+///
+/// Each limit is a token bucket: a session-wide one, and optionally one per
+/// `MsgType <35>`. Messages are always sent in order, so a message which is
+/// held back delays all later ones, regardless of their type. Session-level
+/// messages (e.g. `Heartbeat <0>` and `Logout <5>`) are never throttled, but
+/// they do count towards the session-wide limit.
+///
+/// Throttled messages are either queued or refused, according to the
+/// [`ThrottlePolicy`]. The queue is discarded when the transport is closed.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    policy: ThrottlePolicy,
+    max_queue_depth: Option<usize>,
+    session: Option<Bucket>,
+    msg_types: HashMap<Vec<u8>, Bucket>,
+    // Queued messages, together with their `Input::Send` ids.
+    queue: VecDeque<(u64, OutboundMessage)>,
+}
+
+impl Throttle {
+    /// Creates a new [`Throttle`] without any limits, which queues throttled
+    /// messages.
+    pub fn new() -> Self {
+        Self {
+            policy: ThrottlePolicy::Queue,
+            max_queue_depth: None,
+            session: None,
+            msg_types: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Limits the rate of all outbound messages.
+    pub fn set_limit(&mut self, limit: RateLimit) {
+        self.session = Some(Bucket::new(limit));
+    }
+
+    /// Limits the rate of outbound application messages of type `msg_type`,
+    /// on top of the session-wide limit.
+    pub fn set_msg_type_limit(&mut self, msg_type: &[u8], limit: RateLimit) {
+        self.msg_types.insert(msg_type.to_vec(), Bucket::new(limit));
+    }
+
+    /// Sets the [`ThrottlePolicy`]. [`ThrottlePolicy::Queue`] by default.
+    pub fn set_policy(&mut self, policy: ThrottlePolicy) {
+        self.policy = policy;
+    }
+
+    /// Refuses throttled messages once `depth` messages are queued already.
+    /// The queue is unbounded by default.
+    pub fn set_max_queue_depth(&mut self, depth: usize) {
+        self.max_queue_depth = Some(depth);
+    }
+
+    /// Returns the number of queued messages.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Queues `message` with the given `id`, unless it must be refused.
+    /// Returns `false` if it's refused.
+    pub(crate) fn push(&mut self, id: u64, message: OutboundMessage, now: DateTime<Utc>) -> bool {
+        let max_queue_depth = match self.policy {
+            ThrottlePolicy::Queue => self.max_queue_depth,
+            ThrottlePolicy::Reject => Some(0),
+        };
+        let is_full = matches!(max_queue_depth, Some(max) if self.queue.len() >= max);
+        // With an empty queue, the message might be sent right away.
+        let is_ready = self.queue.is_empty() && self.ready_at(message.msg_type(), now) <= now;
+        if is_full && !is_ready {
+            return false;
+        }
+        self.queue.push_back((id, message));
+        true
+    }
+
+    /// Pops the first queued message and its id, if the limits allow
+    /// sending it at `now`.
+    pub(crate) fn pop(&mut self, now: DateTime<Utc>) -> Option<(u64, OutboundMessage)> {
+        let msg_type = self.queue.front()?.1.msg_type().to_vec();
+        if self.ready_at(&msg_type, now) > now {
+            return None;
+        }
+        if let Some(bucket) = self.msg_types.get_mut(&msg_type) {
+            bucket.consume(now);
+        }
+        if let Some(bucket) = self.session.as_mut() {
+            bucket.consume(now);
+        }
+        self.queue.pop_front()
+    }
+
+    /// Records a session-level message, which isn't throttled.
+    pub(crate) fn on_admin_message(&mut self, now: DateTime<Utc>) {
+        if let Some(bucket) = self.session.as_mut() {
+            bucket.consume(now);
+        }
+    }
+
+    /// Returns the time at which the first queued message can be sent, if
+    /// any.
+    pub(crate) fn next_release(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let msg_type = self.queue.front()?.1.msg_type();
+        Some(self.ready_at(msg_type, now))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    fn ready_at(&self, msg_type: &[u8], now: DateTime<Utc>) -> DateTime<Utc> {
+        let session = self.session.as_ref().map(|bucket| bucket.ready_at(now));
+        let msg_type = self.msg_types.get(msg_type).map(|b| b.ready_at(now));
+        session.into_iter().chain(msg_type).max().unwrap_or(now)
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A token bucket, implemented as a generic cell rate algorithm: each
+/// message moves the theoretical arrival time (TAT) forward by one emission
+/// interval, and messages are allowed as long as the TAT is within the
+/// interval of the limit.
+#[derive(Debug, Clone)]
+struct Bucket {
+    emission_interval: chrono::Duration,
+    tolerance: chrono::Duration,
+    tat: Option<DateTime<Utc>>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        let interval = chrono::Duration::from_std(limit.interval).unwrap_or_else(|_| {
+            // Ridiculously long intervals are capped at ~100 years.
+            chrono::Duration::days(36_500)
+        });
+        // `RateLimit::new` makes sure that the conversion is lossless.
+        let emission_interval = interval / limit.messages as i32;
+        Self {
+            emission_interval,
+            tolerance: interval - emission_interval,
+            tat: None,
+        }
+    }
+
+    fn ready_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.tat {
+            Some(tat) => (tat - self.tolerance).max(now),
+            None => now,
+        }
+    }
+
+    fn consume(&mut self, now: DateTime<Utc>) {
+        let tat = self.tat.map_or(now, |tat| tat.max(now));
+        self.tat = Some(tat + self.emission_interval);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn order() -> OutboundMessage {
+        OutboundMessage::new(b"D")
+    }
+
+    #[test]
+    fn bursts_are_allowed_up_to_the_limit() {
+        let now = Utc::now();
+        let mut throttle = Throttle::new();
+        throttle.set_limit(RateLimit::per_second(2));
+        for _ in 0..3 {
+            assert!(throttle.push(0, order(), now));
+        }
+        assert!(throttle.pop(now).is_some());
+        assert!(throttle.pop(now).is_some());
+        assert!(throttle.pop(now).is_none());
+        assert_eq!(throttle.queue_depth(), 1);
+        let later = now + chrono::Duration::milliseconds(500);
+        assert_eq!(throttle.next_release(now), Some(later));
+        assert!(throttle.pop(later).is_some());
+    }
+
+    #[test]
+    fn msg_type_limits_hold_back_later_messages() {
+        let now = Utc::now();
+        let mut throttle = Throttle::new();
+        throttle.set_msg_type_limit(b"D", RateLimit::per_second(1));
+        throttle.push(0, order(), now);
+        throttle.push(0, order(), now);
+        throttle.push(1, OutboundMessage::new(b"F"), now);
+        assert_eq!(throttle.pop(now).unwrap().1.msg_type(), b"D");
+        assert!(throttle.pop(now).is_none());
+        let later = now + chrono::Duration::seconds(1);
+        assert_eq!(throttle.pop(later).unwrap().1.msg_type(), b"D");
+        assert_eq!(
+            throttle.pop(later).unwrap(),
+            (1, OutboundMessage::new(b"F"))
+        );
+    }
+
+    #[test]
+    fn reject_policy_refuses_throttled_messages() {
+        let now = Utc::now();
+        let mut throttle = Throttle::new();
+        throttle.set_limit(RateLimit::per_second(1));
+        throttle.set_policy(ThrottlePolicy::Reject);
+        assert!(throttle.push(0, order(), now));
+        assert!(throttle.pop(now).is_some());
+        assert!(!throttle.push(0, order(), now));
+        assert_eq!(throttle.queue_depth(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rate_limits_beyond_i32_are_refused() {
+        RateLimit::per_second(u32::MAX);
+    }
+
+    #[test]
+    fn admin_messages_count_towards_session_limit() {
+        let now = Utc::now();
+        let mut throttle = Throttle::new();
+        throttle.set_limit(RateLimit::per_second(1));
+        throttle.set_max_queue_depth(1);
+        throttle.on_admin_message(now);
+        assert!(throttle.push(0, order(), now));
+        assert!(!throttle.push(0, order(), now));
+        assert!(throttle.pop(now).is_none());
+    }
+}
@@ -0,0 +1,393 @@
+use super::{Clock, SystemClock};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::{ready, AsyncRead, AsyncWrite, FutureExt};
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// One end of an in-memory, full-duplex transport, e.g. to run an initiator
+/// and an acceptor back-to-back in the same process without sockets.
+///
+/// [`MemoryTransport`] implements both [`AsyncRead`] and [`AsyncWrite`];
+/// use [`AsyncReadExt::split`](futures::AsyncReadExt::split) to obtain the
+/// separate halves that [`FixConnection::start`](super::FixConnection::start)
+/// expects. Each end can simulate some network conditions:
+///
+/// - latency, via [`MemoryTransport::set_latency`];
+/// - fragmentation into tiny reads, via [`MemoryTransport::set_max_read_len`];
+/// - forced disconnections, via [`DisconnectHandle`].
+///
+/// Dropping (or closing) an end is seen by the other end as a clean
+/// end-of-file, once all bytes written before have been read.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    inbound: Arc<Mutex<Channel>>,
+    outbound: Arc<Mutex<Channel>>,
+    clock: Arc<dyn Clock>,
+    latency: chrono::Duration,
+    max_read_len: usize,
+    // Waits for the latency of the next inbound chunk to elapse.
+    delay: Option<Delay>,
+}
+
+struct Delay(BoxFuture<'static, ()>);
+
+impl std::fmt::Debug for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Delay").finish()
+    }
+}
+
+/// The bytes flowing in one direction of a [`MemoryTransport`].
+#[derive(Debug, Default)]
+struct Channel {
+    chunks: VecDeque<Chunk>,
+    // The writer is gone: EOF once all chunks are read.
+    is_closed: bool,
+    // The reader is gone: writes fail.
+    is_abandoned: bool,
+    // Forcibly disconnected via `DisconnectHandle`.
+    is_reset: bool,
+    reader: Option<Waker>,
+}
+
+impl Channel {
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Chunk {
+    written_at: DateTime<Utc>,
+    bytes: Vec<u8>,
+    // Bytes read so far.
+    pos: usize,
+}
+
+impl MemoryTransport {
+    /// Returns two connected ends, timed by a [`SystemClock`].
+    pub fn pair() -> (Self, Self) {
+        Self::pair_with_clock(SystemClock)
+    }
+
+    /// Returns two connected ends, whose latency is measured by `clock`. With
+    /// a [`MockClock`](super::MockClock), bytes only become readable once the
+    /// clock is advanced past their latency.
+    pub fn pair_with_clock<C>(clock: C) -> (Self, Self)
+    where
+        C: Clock + 'static,
+    {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        let a_to_b = Arc::new(Mutex::new(Channel::default()));
+        let b_to_a = Arc::new(Mutex::new(Channel::default()));
+        let a = Self::new(b_to_a.clone(), a_to_b.clone(), clock.clone());
+        let b = Self::new(a_to_b, b_to_a, clock);
+        (a, b)
+    }
+
+    fn new(
+        inbound: Arc<Mutex<Channel>>,
+        outbound: Arc<Mutex<Channel>>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            inbound,
+            outbound,
+            clock,
+            latency: chrono::Duration::zero(),
+            max_read_len: usize::MAX,
+            delay: None,
+        }
+    }
+
+    /// Delays all bytes read by `self` until `latency` after they were
+    /// written by the other end. No latency by default.
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency =
+            chrono::Duration::from_std(latency).unwrap_or_else(|_| chrono::Duration::max_value());
+    }
+
+    /// Limits each read of `self` to at most `len` bytes, regardless of the
+    /// size of writes, e.g. 1 to deliver messages byte by byte. Reads are
+    /// unlimited by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn set_max_read_len(&mut self, len: usize) {
+        assert!(len > 0, "Reads must allow at least one byte");
+        self.max_read_len = len;
+    }
+
+    /// Returns a [`DisconnectHandle`] which can tear down the transport
+    /// while both ends are in use.
+    pub fn disconnect_handle(&self) -> DisconnectHandle {
+        DisconnectHandle {
+            channels: [self.inbound.clone(), self.outbound.clone()],
+        }
+    }
+}
+
+impl AsyncRead for MemoryTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.0.poll_unpin(cx));
+                this.delay = None;
+            }
+            let mut channel = this.inbound.lock().unwrap();
+            if channel.is_reset {
+                return Poll::Ready(Ok(0));
+            }
+            if channel.chunks.is_empty() {
+                if channel.is_closed {
+                    return Poll::Ready(Ok(0));
+                }
+                channel.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let chunk = channel.chunks.front_mut().unwrap();
+            let ready_at = chunk
+                .written_at
+                .checked_add_signed(this.latency)
+                .unwrap_or(chrono::MAX_DATETIME);
+            if ready_at > this.clock.now() {
+                drop(channel);
+                this.delay = Some(Delay(this.clock.sleep_until(ready_at)));
+                continue;
+            }
+            let len = buf
+                .len()
+                .min(this.max_read_len)
+                .min(chunk.bytes.len() - chunk.pos);
+            buf[..len].copy_from_slice(&chunk.bytes[chunk.pos..chunk.pos + len]);
+            chunk.pos += len;
+            if chunk.pos == chunk.bytes.len() {
+                channel.chunks.pop_front();
+            }
+            return Poll::Ready(Ok(len));
+        }
+    }
+}
+
+impl AsyncWrite for MemoryTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut channel = self.outbound.lock().unwrap();
+        if channel.is_reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        if channel.is_closed || channel.is_abandoned {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if !buf.is_empty() {
+            channel.chunks.push_back(Chunk {
+                written_at: self.clock.now(),
+                bytes: buf.to_vec(),
+                pos: 0,
+            });
+            channel.wake_reader();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut channel = self.outbound.lock().unwrap();
+        channel.is_closed = true;
+        channel.wake_reader();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if let Ok(mut channel) = self.outbound.lock() {
+            channel.is_closed = true;
+            channel.wake_reader();
+        }
+        if let Ok(mut channel) = self.inbound.lock() {
+            channel.is_abandoned = true;
+        }
+    }
+}
+
+/// A cloneable handle which forcibly disconnects a [`MemoryTransport`], as if
+/// the network went down. See [`MemoryTransport::disconnect_handle`].
+#[derive(Debug, Clone)]
+pub struct DisconnectHandle {
+    channels: [Arc<Mutex<Channel>>; 2],
+}
+
+impl DisconnectHandle {
+    /// Disconnects both ends right away: pending bytes are discarded, reads
+    /// report end-of-file and writes fail with
+    /// [`io::ErrorKind::ConnectionReset`].
+    pub fn disconnect(&self) {
+        for channel in self.channels.iter() {
+            let mut channel = channel.lock().unwrap();
+            channel.is_reset = true;
+            channel.chunks.clear();
+            channel.wake_reader();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::fix44;
+    use crate::session::backends::Noop;
+    use crate::session::{Backend, FixConnection, FixConnectionBuilder, MockClock, Role};
+    use crate::tagvalue::{Decoder, FieldAccess, Message};
+    use crate::Dictionary;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use std::ops::Range;
+
+    #[test]
+    fn reads_are_fragmented() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        b.set_max_read_len(3);
+        futures::executor::block_on(async {
+            a.write_all(b"hello").await.unwrap();
+            let mut buffer = [0; 16];
+            assert_eq!(b.read(&mut buffer).await.unwrap(), 3);
+            assert_eq!(b.read(&mut buffer).await.unwrap(), 2);
+            assert_eq!(&buffer[..2], b"lo");
+            drop(a);
+            assert_eq!(b.read(&mut buffer).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn latency_holds_back_bytes() {
+        let clock = MockClock::new(Utc::now());
+        let (mut a, mut b) = MemoryTransport::pair_with_clock(clock.clone());
+        b.set_latency(Duration::from_millis(100));
+        let waker = futures::task::noop_waker();
+        let cx = &mut Context::from_waker(&waker);
+        let mut buffer = [0; 16];
+        assert!(Pin::new(&mut a).poll_write(cx, b"ping").is_ready());
+        assert!(Pin::new(&mut b).poll_read(cx, &mut buffer).is_pending());
+        clock.advance(chrono::Duration::milliseconds(99));
+        assert!(Pin::new(&mut b).poll_read(cx, &mut buffer).is_pending());
+        clock.advance(chrono::Duration::milliseconds(1));
+        let read = Pin::new(&mut b).poll_read(cx, &mut buffer);
+        assert!(matches!(read, Poll::Ready(Ok(4))));
+    }
+
+    #[test]
+    fn forced_disconnects_tear_down_both_ends() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let handle = a.disconnect_handle();
+        futures::executor::block_on(async {
+            a.write_all(b"lost").await.unwrap();
+            handle.disconnect();
+            let mut buffer = [0; 16];
+            assert_eq!(b.read(&mut buffer).await.unwrap(), 0);
+            assert_eq!(a.read(&mut buffer).await.unwrap(), 0);
+            let err = b.write_all(b"pong").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        });
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn event_loop_decodes_messages_read_byte_by_byte() {
+        use crate::session::{LlEvent, LlEventLoop};
+
+        let (mut a, mut b) = MemoryTransport::pair();
+        b.set_max_read_len(1);
+        let message = b"8=FIX.4.4\x019=5\x0135=0\x0110=163\x01";
+        let decoder = Decoder::new(Dictionary::fix44()).buffered();
+        let mut event_loop = LlEventLoop::new(decoder, b, Duration::from_secs(30));
+        futures::executor::block_on(async {
+            a.write_all(message).await.unwrap();
+            match event_loop.next().await {
+                LlEvent::Message { msg } => {
+                    assert_eq!(msg.fv::<&str, _>(fix44::MSG_TYPE), Ok("0"));
+                }
+                event => panic!("Unexpected event: {:?}", event),
+            }
+        });
+    }
+
+    /// Disconnects the transport as soon as the Logon handshake succeeds.
+    #[derive(Debug, Clone)]
+    struct HangUp(DisconnectHandle);
+
+    impl Backend for HangUp {
+        type Error = ();
+
+        fn on_inbound_app_message(&mut self, _message: Message<&[u8]>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_outbound_message(&mut self, _message: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_resend_request(&mut self, _range: Range<u64>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_successful_handshake(&mut self) -> Result<(), Self::Error> {
+            self.0.disconnect();
+            Ok(())
+        }
+    }
+
+    fn conn(role: Role, sender: &str, target: &str) -> FixConnection {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_sender_comp_id(sender);
+        builder.set_target_comp_id(target);
+        builder.set_role(role);
+        builder.build()
+    }
+
+    #[test]
+    fn initiator_and_acceptor_log_on_back_to_back() {
+        let (initiator_end, mut acceptor_end) = MemoryTransport::pair();
+        acceptor_end.set_max_read_len(1);
+        let backend = HangUp(initiator_end.disconnect_handle());
+        let mut initiator = conn(Role::Initiator, "BUYSIDE", "SELLSIDE");
+        let mut acceptor = conn(Role::Acceptor, "SELLSIDE", "BUYSIDE");
+        let (initiator_input, initiator_output) = initiator_end.split();
+        let (acceptor_input, acceptor_output) = acceptor_end.split();
+        let (initiator_established, acceptor_established) =
+            futures::executor::block_on(futures::future::join(
+                initiator.start(
+                    backend,
+                    initiator_input,
+                    initiator_output,
+                    Decoder::new(Dictionary::fix44()),
+                ),
+                acceptor.start(
+                    Noop,
+                    acceptor_input,
+                    acceptor_output,
+                    Decoder::new(Dictionary::fix44()),
+                ),
+            ));
+        assert!(initiator_established);
+        assert!(acceptor_established);
+    }
+}
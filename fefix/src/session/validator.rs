@@ -0,0 +1,411 @@
+use super::errs;
+use crate::definitions::fix44;
+use crate::dict::{Field, FixDatatype, IsFieldDefinition, LayoutItem, LayoutItemKind};
+use crate::fix_values::{Date, MonthYear, Time, Timestamp};
+use crate::tagvalue::{FieldAccess, Message};
+use crate::{Dictionary, FixValue, TagU16};
+use std::collections::{HashMap, HashSet};
+
+/// A problem found by [`Validator`] in an inbound message, which must be
+/// refused with a `Reject <3>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Violation {
+    /// The value of `SessionRejectReason <373>`.
+    pub reason: u32,
+    /// The value of `RefTagID <371>`, if any.
+    pub ref_tag: Option<u32>,
+    pub text: String,
+}
+
+impl Violation {
+    fn new(reason: fix44::SessionRejectReason, field: &Field, text: String) -> Self {
+        Self {
+            reason: reason as u32,
+            ref_tag: Some(field.tag().get().into()),
+            text,
+        }
+    }
+}
+
+/// Checks inbound messages against the layouts and data types of a
+/// [`Dictionary`].
+#[derive(Debug, Clone)]
+pub(crate) struct Validator {
+    dictionary: Dictionary,
+    // FIXT.1.1 only: defines the standard header and trailer, as well as all
+    // session-level fields.
+    transport: Option<Dictionary>,
+    // Lazily computed for each `MsgType <35>`. `None` for unknown types.
+    layouts: HashMap<Vec<u8>, Option<Layout>>,
+}
+
+impl Validator {
+    pub fn new(dictionary: Dictionary) -> Self {
+        Self {
+            dictionary,
+            transport: None,
+            layouts: HashMap::new(),
+        }
+    }
+
+    /// Creates a [`Validator`] for the application messages of a FIXT.1.1
+    /// session, whose standard header and trailer are defined by `transport`.
+    pub fn with_transport(dictionary: Dictionary, transport: Dictionary) -> Self {
+        Self {
+            dictionary,
+            transport: Some(transport),
+            layouts: HashMap::new(),
+        }
+    }
+
+    /// Returns the first [`Violation`] found in `msg`, if any.
+    pub fn validate(&mut self, msg: &Message<&[u8]>) -> Result<(), Violation> {
+        let msg_type = msg.fv_raw(fix44::MSG_TYPE).unwrap_or(b"");
+        let dictionary = &self.dictionary;
+        let transport = self.transport.as_ref().unwrap_or(dictionary);
+        let layout = self
+            .layouts
+            .entry(msg_type.to_vec())
+            .or_insert_with(|| Layout::for_msg_type(dictionary, transport, msg_type));
+        let layout = match layout {
+            Some(layout) => layout,
+            None => {
+                return Err(Violation {
+                    reason: fix44::SessionRejectReason::InvalidMsgtype as u32,
+                    ref_tag: Some(fix44::MSG_TYPE.tag().get().into()),
+                    text: errs::invalid_msg_type(),
+                })
+            }
+        };
+        // `BeginString <8>`, `BodyLength <9>` and `CheckSum <10>` are
+        // verified by the decoder already.
+        let fields: Vec<(TagU16, &[u8])> = msg
+            .fields()
+            .filter(|(tag, _)| !is_framing_tag(tag.get()))
+            .collect();
+        let mut checker = Checker {
+            dictionary,
+            transport,
+            fields: &fields[..],
+            i: 0,
+        };
+        checker.check(layout, true)
+    }
+}
+
+/// The fields allowed at some level of a message, i.e. at the top level or
+/// within a repeating group entry. Components are flattened.
+#[derive(Debug, Clone, Default)]
+struct Layout {
+    tags: HashSet<u16>,
+    required: Vec<u16>,
+    // Indexed by `NumInGroup` tag.
+    groups: HashMap<u16, Layout>,
+    // The first tag of every entry, for repeating groups only.
+    delimiter: Option<u16>,
+}
+
+impl Layout {
+    fn for_msg_type(
+        dictionary: &Dictionary,
+        transport: &Dictionary,
+        msg_type: &[u8],
+    ) -> Option<Self> {
+        let msg_type = std::str::from_utf8(msg_type).ok()?;
+        let message = dictionary.message_by_msgtype(msg_type)?;
+        let mut layout = Layout::default();
+        if let Some(header) = transport.component_by_name("StandardHeader") {
+            layout.add_items(&header.items().collect::<Vec<_>>(), true);
+        }
+        layout.add_items(&message.layout().collect::<Vec<_>>(), true);
+        if let Some(trailer) = transport.component_by_name("StandardTrailer") {
+            layout.add_items(&trailer.items().collect::<Vec<_>>(), true);
+        }
+        Some(layout)
+    }
+
+    /// Items within optional components are never required.
+    fn add_items(&mut self, items: &[LayoutItem], required: bool) {
+        for item in items {
+            let required = required && item.required();
+            match item.kind() {
+                LayoutItemKind::Field(field) => self.add_field(field.tag().get(), required),
+                LayoutItemKind::Component(component) => {
+                    self.add_items(&component.items().collect::<Vec<_>>(), required);
+                }
+                LayoutItemKind::Group(field, items) => {
+                    let tag = field.tag().get();
+                    self.add_field(tag, required);
+                    let mut group = Layout::default();
+                    group.add_items(&items[..], true);
+                    group.delimiter = items.first().and_then(first_tag);
+                    self.groups.insert(tag, group);
+                }
+            }
+        }
+    }
+
+    fn add_field(&mut self, tag: u16, required: bool) {
+        self.tags.insert(tag);
+        if required && !is_framing_tag(tag) {
+            self.required.push(tag);
+        }
+    }
+}
+
+fn first_tag(item: &LayoutItem) -> Option<u16> {
+    match item.kind() {
+        LayoutItemKind::Field(field) => Some(field.tag().get()),
+        LayoutItemKind::Group(field, _) => Some(field.tag().get()),
+        LayoutItemKind::Component(component) => {
+            component.items().next().and_then(|i| first_tag(&i))
+        }
+    }
+}
+
+fn is_framing_tag(tag: u16) -> bool {
+    matches!(tag, 8..=10)
+}
+
+/// Walks over the fields of a message, one [`Layout`] at a time.
+struct Checker<'a> {
+    dictionary: &'a Dictionary,
+    transport: &'a Dictionary,
+    fields: &'a [(TagU16, &'a [u8])],
+    i: usize,
+}
+
+impl<'a> Checker<'a> {
+    /// Consumes all fields which belong to `layout`. Group entries end at the
+    /// first field that doesn't belong to them, or at the next delimiter.
+    fn check(&mut self, layout: &Layout, top_level: bool) -> Result<(), Violation> {
+        let mut seen = HashSet::new();
+        while let Some((tag, value)) = self.fields.get(self.i).copied() {
+            let field = match self.field_by_tag(tag.get()) {
+                Some(field) => field,
+                None => {
+                    return Err(Violation {
+                        reason: fix44::SessionRejectReason::InvalidTagNumber as u32,
+                        ref_tag: Some(tag.get().into()),
+                        text: errs::invalid_tag_number(tag.get().into()),
+                    })
+                }
+            };
+            let name = field.name();
+            let tag = tag.get();
+            if !layout.tags.contains(&tag)
+                || (!top_level && Some(tag) == layout.delimiter && !seen.is_empty())
+            {
+                if top_level {
+                    return Err(Violation::new(
+                        fix44::SessionRejectReason::TagNotDefinedForThisMessageType,
+                        &field,
+                        errs::tag_not_defined_for_msg_type(name, tag.into()),
+                    ));
+                }
+                break;
+            }
+            if !seen.insert(tag) {
+                return Err(Violation::new(
+                    fix44::SessionRejectReason::TagAppearsMoreThanOnce,
+                    &field,
+                    errs::duplicate_tag(name, tag.into()),
+                ));
+            }
+            check_value(&field, value)?;
+            self.i += 1;
+            if let Some(group) = layout.groups.get(&tag) {
+                let num_in_group = u64::deserialize(value).unwrap_or(0);
+                let mut entries = 0;
+                while self.fields.get(self.i).map(|(tag, _)| Some(tag.get()))
+                    == Some(group.delimiter)
+                {
+                    self.check(group, false)?;
+                    entries += 1;
+                }
+                if entries != num_in_group {
+                    return Err(Violation::new(
+                        fix44::SessionRejectReason::IncorrectNumingroupCountForRepeatingGroup,
+                        &field,
+                        errs::num_in_group_count(name, tag.into()),
+                    ));
+                }
+            }
+        }
+        match layout.required.iter().find(|tag| !seen.contains(tag)) {
+            Some(tag) => {
+                let field = self.field_by_tag(*tag).unwrap();
+                Err(Violation::new(
+                    fix44::SessionRejectReason::RequiredTagMissing,
+                    &field,
+                    errs::missing_field(field.name(), (*tag).into()),
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn field_by_tag(&self, tag: u16) -> Option<Field<'a>> {
+        self.dictionary
+            .field_by_tag(tag.into())
+            .or_else(|| self.transport.field_by_tag(tag.into()))
+    }
+}
+
+/// Checks that `value` is non-empty, has the right format for the data type
+/// of `field` and belongs to its enumeration, if any.
+fn check_value(field: &Field, value: &[u8]) -> Result<(), Violation> {
+    let name = field.name();
+    let tag = field.tag().get().into();
+    if value.is_empty() {
+        return Err(Violation::new(
+            fix44::SessionRejectReason::TagSpecifiedWithoutAValue,
+            field,
+            errs::empty_value(name, tag),
+        ));
+    }
+    if !has_valid_format(field.fix_datatype(), value) {
+        return Err(Violation::new(
+            fix44::SessionRejectReason::IncorrectDataFormatForValue,
+            field,
+            errs::incorrect_data_format(name, tag),
+        ));
+    }
+    if let Some(enums) = field.enums() {
+        let allowed: Vec<String> = enums.map(|e| e.value().to_string()).collect();
+        let is_allowed = |value: &[u8]| allowed.iter().any(|a| a.as_bytes() == value);
+        let is_allowed = match field.fix_datatype() {
+            FixDatatype::MultipleCharValue | FixDatatype::MultipleStringValue => {
+                value.split(|byte| *byte == b' ').all(is_allowed)
+            }
+            _ => is_allowed(value),
+        };
+        if !allowed.is_empty() && !is_allowed {
+            return Err(Violation::new(
+                fix44::SessionRejectReason::ValueIsIncorrect,
+                field,
+                errs::value_out_of_range(name, tag),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn has_valid_format(datatype: FixDatatype, value: &[u8]) -> bool {
+    match datatype {
+        FixDatatype::Int => i64::deserialize(value).is_ok(),
+        FixDatatype::Length
+        | FixDatatype::NumInGroup
+        | FixDatatype::SeqNum
+        | FixDatatype::TagNum
+        | FixDatatype::DayOfMonth => u64::deserialize(value).is_ok(),
+        FixDatatype::Float
+        | FixDatatype::Amt
+        | FixDatatype::Price
+        | FixDatatype::PriceOffset
+        | FixDatatype::Qty
+        | FixDatatype::Percentage => is_decimal(value),
+        FixDatatype::Char => value.len() == 1,
+        FixDatatype::Boolean => bool::deserialize(value).is_ok(),
+        FixDatatype::UtcTimestamp => Timestamp::deserialize(value).is_ok(),
+        FixDatatype::UtcDateOnly | FixDatatype::LocalMktDate => Date::deserialize(value).is_ok(),
+        FixDatatype::UtcTimeOnly => Time::deserialize(value).is_ok(),
+        FixDatatype::MonthYear => MonthYear::deserialize(value).is_ok(),
+        _ => true,
+    }
+}
+
+fn is_decimal(value: &[u8]) -> bool {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let mut parts = digits.splitn(2, |byte| *byte == b'.');
+    let integer = parts.next().unwrap_or(b"");
+    let fraction = parts.next().unwrap_or(b"");
+    !(integer.is_empty() && fraction.is_empty())
+        && integer.iter().chain(fraction).all(u8::is_ascii_digit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tagvalue::{Config, Decoder};
+
+    fn validate(msg: &str) -> Result<(), Violation> {
+        let mut decoder = Decoder::<Config>::new(Dictionary::fix44());
+        decoder.config_mut().set_separator(b'|');
+        decoder.config_mut().set_verify_checksum(false);
+        let msg = decoder.decode(msg.as_bytes()).unwrap();
+        Validator::new(Dictionary::fix44()).validate(&msg)
+    }
+
+    fn reason(msg: &str) -> (u32, Option<u32>) {
+        let violation = validate(msg).unwrap_err();
+        (violation.reason, violation.ref_tag)
+    }
+
+    const HEADER: &str = "35=D|49=A|56=B|34=2|52=20210301-10:00:00.000|";
+
+    fn order(body: &str) -> String {
+        let msg = format!("{}{}", HEADER, body);
+        format!("8=FIX.4.4|9={}|{}10=000|", msg.len(), msg)
+    }
+
+    const ORDER: &str = "11=X|54=1|60=20210301-10:00:00|38=100|40=1|";
+
+    #[test]
+    fn valid_message() {
+        assert_eq!(validate(&order(ORDER)), Ok(()));
+    }
+
+    #[test]
+    fn required_tag_missing() {
+        assert_eq!(
+            reason(&order("11=X|54=1|60=20210301-10:00:00|38=100|")),
+            (1, Some(40))
+        );
+    }
+
+    #[test]
+    fn invalid_tag_number() {
+        assert_eq!(
+            reason(&order(&format!("{}9999=1|", ORDER))),
+            (0, Some(9999))
+        );
+    }
+
+    #[test]
+    fn tag_not_defined_for_msg_type() {
+        assert_eq!(reason(&order(&format!("{}112=X|", ORDER))), (2, Some(112)));
+    }
+
+    #[test]
+    fn empty_value() {
+        assert_eq!(reason(&order(&format!("{}58=|", ORDER))), (4, Some(58)));
+    }
+
+    #[test]
+    fn value_out_of_range_and_incorrect_format() {
+        let msg = order("11=X|54=Z|60=20210301-10:00:00|38=100|40=1|");
+        assert_eq!(reason(&msg), (5, Some(54)));
+        let msg = order("11=X|54=1|60=20210301-10:00:00|38=abc|40=1|");
+        assert_eq!(reason(&msg), (6, Some(38)));
+    }
+
+    #[test]
+    fn duplicate_tag() {
+        assert_eq!(
+            reason(&order(&format!("{}58=a|58=b|", ORDER))),
+            (13, Some(58))
+        );
+    }
+
+    #[test]
+    fn repeating_groups() {
+        let parties = "453=2|448=P1|447=D|452=1|448=P2|447=D|452=3|";
+        assert_eq!(validate(&order(&format!("{}{}", ORDER, parties))), Ok(()));
+        let parties = "453=3|448=P1|447=D|452=1|448=P2|447=D|452=3|";
+        assert_eq!(
+            reason(&order(&format!("{}{}", ORDER, parties))),
+            (16, Some(453))
+        );
+    }
+}
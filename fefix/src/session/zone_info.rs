@@ -0,0 +1,571 @@
+use chrono::{Datelike, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// A time zone of the IANA time zone database (e.g. `America/New_York`),
+/// loaded from a TZif file such as those in `/usr/share/zoneinfo`.
+///
+/// Unlike [`FixedOffset`], [`ZoneInfo`] follows daylight saving time changes,
+/// so it's the right choice for e.g. the trading hours of an exchange. See
+/// [`Schedule::set_time_zone`](super::Schedule::set_time_zone).
+#[derive(Debug, Clone)]
+pub struct ZoneInfo {
+    rules: Arc<Rules>,
+}
+
+impl ZoneInfo {
+    /// Loads the time zone called `name` (e.g. `Europe/London`) from the
+    /// time zone database of the system, i.e. from the directory in the
+    /// `TZDIR` environment variable or else from `/usr/share/zoneinfo`.
+    pub fn load(name: &str) -> io::Result<Self> {
+        let path = Path::new(name);
+        let is_relative = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !is_relative {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid time zone name",
+            ));
+        }
+        let dir = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"));
+        let tzif = std::fs::read(dir.join(path))?;
+        Self::from_tzif(name, &tzif)
+    }
+
+    /// Parses the contents of a TZif file (RFC 8536) and calls the resulting
+    /// time zone `name`.
+    pub fn from_tzif(name: &str, tzif: &[u8]) -> io::Result<Self> {
+        let mut rules = parse_tzif(tzif).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Invalid TZif time zone data")
+        })?;
+        rules.name = name.to_string();
+        Ok(Self {
+            rules: Arc::new(rules),
+        })
+    }
+
+    /// Returns the name of `self`, e.g. `Europe/London`.
+    pub fn name(&self) -> &str {
+        self.rules.name.as_str()
+    }
+
+    fn offset(&self, utc_offset: i32) -> ZoneInfoOffset {
+        ZoneInfoOffset {
+            zone: self.clone(),
+            offset: FixedOffset::east(utc_offset),
+        }
+    }
+}
+
+impl fmt::Display for ZoneInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl TimeZone for ZoneInfo {
+    type Offset = ZoneInfoOffset;
+
+    fn from_offset(offset: &ZoneInfoOffset) -> Self {
+        offset.zone.clone()
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<ZoneInfoOffset> {
+        self.offset_from_local_datetime(&local.and_hms(0, 0, 0))
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<ZoneInfoOffset> {
+        let local = local.timestamp();
+        // UTC offsets change at most once a day, so the right one must be
+        // in effect either a day before or a day after.
+        let mut candidates: Vec<i32> = vec![];
+        for utc in &[local - SECS_PER_DAY, local + SECS_PER_DAY] {
+            let offset = self.rules.offset_at(*utc);
+            let is_valid = self.rules.offset_at(local - offset as i64) == offset;
+            if is_valid && !candidates.contains(&offset) {
+                candidates.push(offset);
+            }
+        }
+        // The earliest instant comes first.
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        match candidates.as_slice() {
+            [] => LocalResult::None,
+            [offset] => LocalResult::Single(self.offset(*offset)),
+            [earliest, latest, ..] => {
+                LocalResult::Ambiguous(self.offset(*earliest), self.offset(*latest))
+            }
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneInfoOffset {
+        self.offset_from_utc_datetime(&utc.and_hms(0, 0, 0))
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneInfoOffset {
+        self.offset(self.rules.offset_at(utc.timestamp()))
+    }
+}
+
+/// The UTC offset of a [`ZoneInfo`] at some point in time.
+#[derive(Debug, Clone)]
+pub struct ZoneInfoOffset {
+    zone: ZoneInfo,
+    offset: FixedOffset,
+}
+
+impl Offset for ZoneInfoOffset {
+    fn fix(&self) -> FixedOffset {
+        self.offset
+    }
+}
+
+impl fmt::Display for ZoneInfoOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.offset)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    name: String,
+    // The UTC time (in seconds since the epoch) of each change, together
+    // with the index of the new UTC offset.
+    transitions: Vec<(i64, usize)>,
+    // UTC offsets in seconds. The first one applies before all transitions.
+    offsets: Vec<i32>,
+    // Applies after the last transition.
+    footer: Option<PosixRule>,
+}
+
+impl Rules {
+    /// Returns the UTC offset in seconds at `utc` seconds since the epoch.
+    fn offset_at(&self, utc: i64) -> i32 {
+        let i = self.transitions.partition_point(|(time, _)| *time <= utc);
+        match (i, &self.footer) {
+            (i, Some(footer)) if i == self.transitions.len() => footer.offset_at(utc),
+            (0, _) => self.offsets[0],
+            (i, _) => self.offsets[self.transitions[i - 1].1],
+        }
+    }
+}
+
+/// The rule of a POSIX `TZ` string, e.g. `EST5EDT,M3.2.0,M11.1.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PosixRule {
+    // In seconds east of UTC, unlike in `TZ` strings.
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Dst {
+    offset: i32,
+    // Both in local time: standard time for `start`, daylight saving time
+    // for `end`.
+    start: (RuleDate, i32),
+    end: (RuleDate, i32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RuleDate {
+    // `Jn`: 1 to 365, February 29 is never counted.
+    Julian(u32),
+    // `n`: 0 to 365, February 29 is counted in leap years.
+    Ordinal(u32),
+    // `Mm.w.d`: day `d` (0 is Sunday) of week `w` (5 is the last one) of
+    // month `m`.
+    MonthWeekDay(u32, u32, u32),
+}
+
+impl PosixRule {
+    fn offset_at(&self, utc: i64) -> i32 {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return self.std_offset,
+        };
+        let year = NaiveDateTime::from_timestamp(utc + self.std_offset as i64, 0).year();
+        let at = |(date, time): (RuleDate, i32), offset: i32| {
+            date.in_year(year).and_hms(0, 0, 0).timestamp() + time as i64 - offset as i64
+        };
+        let start = at(dst.start, self.std_offset);
+        let end = at(dst.end, dst.offset);
+        let is_dst = if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere.
+            !(end <= utc && utc < start)
+        };
+        if is_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+impl RuleDate {
+    fn in_year(self, year: i32) -> NaiveDate {
+        let last_day = NaiveDate::from_ymd(year, 12, 31);
+        match self {
+            Self::Julian(day) => {
+                let is_leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                let day = if is_leap && day >= 60 { day + 1 } else { day };
+                NaiveDate::from_yo_opt(year, day).unwrap_or(last_day)
+            }
+            Self::Ordinal(day) => NaiveDate::from_yo_opt(year, day + 1).unwrap_or(last_day),
+            Self::MonthWeekDay(month, week, weekday) => {
+                let first = NaiveDate::from_ymd(year, month, 1);
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                // The fifth week means the last one, which might be the
+                // fourth.
+                while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd(year, month, day)
+            }
+        }
+    }
+}
+
+/// Parses a TZif file of any version. Leap seconds are ignored.
+fn parse_tzif(bytes: &[u8]) -> Option<Rules> {
+    let mut reader = Reader(bytes);
+    let mut header = Header::read(&mut reader)?;
+    let mut time_size = 4;
+    if header.version >= 2 {
+        // Version 1 data is superseded by the 64-bit data that follows.
+        reader.take(header.data_len(time_size))?;
+        header = Header::read(&mut reader)?;
+        time_size = 8;
+    }
+    let mut times = Vec::with_capacity(header.timecnt);
+    for _ in 0..header.timecnt {
+        times.push(reader.int(time_size)?);
+    }
+    let indices = reader.take(header.timecnt)?;
+    let mut offsets = Vec::with_capacity(header.typecnt);
+    for _ in 0..header.typecnt {
+        let offset = reader.int(4)?;
+        if offset.abs() >= SECS_PER_DAY {
+            return None;
+        }
+        offsets.push(offset as i32);
+        // `tt_isdst` and `tt_desigidx`.
+        reader.take(2)?;
+    }
+    if offsets.is_empty() || indices.iter().any(|i| *i as usize >= offsets.len()) {
+        return None;
+    }
+    let transitions = times
+        .into_iter()
+        .zip(indices.iter().map(|i| *i as usize))
+        .collect();
+    reader.take(header.charcnt + header.leapcnt * (time_size + 4))?;
+    reader.take(header.isstdcnt + header.isutcnt)?;
+    let mut footer = None;
+    if header.version >= 2 {
+        let text = reader.0.strip_prefix(b"\n")?;
+        let end = text.iter().position(|byte| *byte == b'\n')?;
+        let text = std::str::from_utf8(&text[..end]).ok()?;
+        if !text.is_empty() {
+            footer = Some(parse_posix_rule(text)?);
+        }
+    }
+    Some(Rules {
+        name: String::new(),
+        transitions,
+        offsets,
+        footer,
+    })
+}
+
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        if reader.take(4)? != b"TZif" {
+            return None;
+        }
+        let version = match reader.take(1)?[0] {
+            0 => 1,
+            byte @ b'2'..=b'9' => byte - b'0',
+            _ => return None,
+        };
+        reader.take(15)?;
+        let mut count = || reader.int(4).map(|n| n as usize);
+        Some(Self {
+            version,
+            isutcnt: count()?,
+            isstdcnt: count()?,
+            leapcnt: count()?,
+            timecnt: count()?,
+            typecnt: count()?,
+            charcnt: count()?,
+        })
+    }
+
+    /// The length of the data block that follows, with `time_size`-byte
+    /// times.
+    fn data_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    /// Reads a big-endian signed integer of `len` bytes, i.e. 4 or 8.
+    fn int(&mut self, len: usize) -> Option<i64> {
+        let bytes = self.take(len)?;
+        let unsigned = bytes
+            .iter()
+            .fold(0u64, |n, byte| (n << 8) | u64::from(*byte));
+        let shift = 64 - 8 * len as u32;
+        Some(((unsigned << shift) as i64) >> shift)
+    }
+}
+
+/// Parses a POSIX `TZ` string, as found in the footer of TZif files.
+fn parse_posix_rule(text: &str) -> Option<PosixRule> {
+    let mut parser = PosixParser(text.as_bytes());
+    parser.name()?;
+    let std_offset = -parser.time()?;
+    if parser.0.is_empty() {
+        return Some(PosixRule {
+            std_offset,
+            dst: None,
+        });
+    }
+    parser.name()?;
+    let offset = match parser.0.first() {
+        Some(b',') => std_offset + 3600,
+        _ => -parser.time()?,
+    };
+    parser.expect(b',')?;
+    let start = parser.date_time()?;
+    parser.expect(b',')?;
+    let end = parser.date_time()?;
+    if !parser.0.is_empty() {
+        return None;
+    }
+    Some(PosixRule {
+        std_offset,
+        dst: Some(Dst { offset, start, end }),
+    })
+}
+
+struct PosixParser<'a>(&'a [u8]);
+
+impl<'a> PosixParser<'a> {
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        let (first, rest) = self.0.split_first()?;
+        if *first != byte {
+            return None;
+        }
+        self.0 = rest;
+        Some(())
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(u8) -> bool) -> &'a [u8] {
+        let len = self.0.iter().take_while(|byte| predicate(**byte)).count();
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        taken
+    }
+
+    /// Skips a time zone abbreviation, e.g. `EST` or `<+03>`.
+    fn name(&mut self) -> Option<()> {
+        if self.expect(b'<').is_some() {
+            self.take_while(|byte| byte != b'>');
+            return self.expect(b'>');
+        }
+        let name = self.take_while(|byte| byte.is_ascii_alphabetic());
+        if name.len() < 3 {
+            return None;
+        }
+        Some(())
+    }
+
+    fn number(&mut self, max: u32) -> Option<u32> {
+        let digits = self.take_while(|byte| byte.is_ascii_digit());
+        let number = std::str::from_utf8(digits).ok()?.parse().ok()?;
+        if number > max {
+            return None;
+        }
+        Some(number)
+    }
+
+    /// Parses `[+-]hh[:mm[:ss]]` into seconds.
+    fn time(&mut self) -> Option<i32> {
+        let sign = if self.expect(b'-').is_some() {
+            -1
+        } else {
+            self.expect(b'+');
+            1
+        };
+        let mut secs = self.number(167)? * 3600;
+        if self.expect(b':').is_some() {
+            secs += self.number(59)? * 60;
+            if self.expect(b':').is_some() {
+                secs += self.number(59)?;
+            }
+        }
+        Some(sign * secs as i32)
+    }
+
+    /// Parses a rule date followed by an optional time, 02:00 by default.
+    fn date_time(&mut self) -> Option<(RuleDate, i32)> {
+        let date = if self.expect(b'J').is_some() {
+            match self.number(365)? {
+                0 => return None,
+                day => RuleDate::Julian(day),
+            }
+        } else if self.expect(b'M').is_some() {
+            let month = self.number(12)?;
+            self.expect(b'.')?;
+            let week = self.number(5)?;
+            self.expect(b'.')?;
+            let weekday = self.number(6)?;
+            if month == 0 || week == 0 {
+                return None;
+            }
+            RuleDate::MonthWeekDay(month, week, weekday)
+        } else {
+            RuleDate::Ordinal(self.number(365)?)
+        };
+        let time = if self.expect(b'/').is_some() {
+            self.time()?
+        } else {
+            2 * 3600
+        };
+        Some((date, time))
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+
+    /// Builds a version 2 TZif file out of `transitions`, `offsets` and
+    /// `footer`.
+    fn tzif(transitions: &[(i64, u8)], offsets: &[i32], footer: &str) -> Vec<u8> {
+        let header = |tzif: &mut Vec<u8>| {
+            tzif.extend_from_slice(b"TZif2");
+            tzif.extend_from_slice(&[0; 15]);
+            // No UT/local and standard/wall indicators, nor leap seconds.
+            for count in &[0, 0, 0, transitions.len(), offsets.len(), 4] {
+                tzif.extend_from_slice(&(*count as u32).to_be_bytes());
+            }
+        };
+        let data = |tzif: &mut Vec<u8>, time_size: usize| {
+            for (time, _) in transitions {
+                tzif.extend_from_slice(&time.to_be_bytes()[8 - time_size..]);
+            }
+            tzif.extend(transitions.iter().map(|(_, index)| *index));
+            for offset in offsets {
+                tzif.extend_from_slice(&offset.to_be_bytes());
+                tzif.extend_from_slice(&[0, 0]);
+            }
+            tzif.extend_from_slice(b"LMT\0");
+        };
+        let mut tzif = vec![];
+        header(&mut tzif);
+        data(&mut tzif, 4);
+        header(&mut tzif);
+        data(&mut tzif, 8);
+        tzif.extend_from_slice(format!("\n{}\n", footer).as_bytes());
+        tzif
+    }
+
+    pub(in crate::session) fn new_york() -> ZoneInfo {
+        let tzif = tzif(&[], &[-5 * 3600], "EST5EDT,M3.2.0,M11.1.0");
+        ZoneInfo::from_tzif("America/New_York", &tzif).unwrap()
+    }
+
+    #[test]
+    fn footer_rules_follow_daylight_saving_time() {
+        let zone = new_york();
+        let winter = zone.ymd(2021, 1, 15).and_hms(9, 0, 0);
+        assert_eq!(winter.offset().fix(), FixedOffset::west(5 * 3600));
+        let summer = zone.ymd(2021, 7, 15).and_hms(9, 0, 0);
+        assert_eq!(summer.offset().fix(), FixedOffset::west(4 * 3600));
+        // 2021-03-14 02:30 doesn't exist, and 2021-11-07 01:30 happens twice.
+        let spring = NaiveDate::from_ymd(2021, 3, 14).and_hms(2, 30, 0);
+        assert!(matches!(
+            zone.offset_from_local_datetime(&spring),
+            LocalResult::None
+        ));
+        let autumn = NaiveDate::from_ymd(2021, 11, 7).and_hms(1, 30, 0);
+        match zone.offset_from_local_datetime(&autumn) {
+            LocalResult::Ambiguous(earliest, latest) => {
+                assert_eq!(earliest.fix(), FixedOffset::west(4 * 3600));
+                assert_eq!(latest.fix(), FixedOffset::west(5 * 3600));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn transitions_precede_the_footer() {
+        // Fixed at UTC+3 from 2016 onwards, like Europe/Istanbul.
+        let change = NaiveDate::from_ymd(2016, 9, 7).and_hms(0, 0, 0).timestamp();
+        let tzif = tzif(&[(change, 1)], &[2 * 3600, 3 * 3600], "<+03>-3");
+        let zone = ZoneInfo::from_tzif("Europe/Istanbul", &tzif).unwrap();
+        let offset = |y, m, d| zone.ymd(y, m, d).offset().fix();
+        assert_eq!(offset(2015, 1, 1), FixedOffset::east(2 * 3600));
+        assert_eq!(offset(2021, 1, 1), FixedOffset::east(3 * 3600));
+    }
+
+    #[test]
+    fn posix_rules() {
+        let rule = parse_posix_rule("GMT0BST,M3.5.0/1,M10.5.0").unwrap();
+        assert_eq!(rule.std_offset, 0);
+        let dst = rule.dst.unwrap();
+        assert_eq!(dst.offset, 3600);
+        assert_eq!(dst.start, (RuleDate::MonthWeekDay(3, 5, 0), 3600));
+        assert_eq!(dst.end, (RuleDate::MonthWeekDay(10, 5, 0), 7200));
+        // 2021-03-28 is the last Sunday of March.
+        let date = RuleDate::MonthWeekDay(3, 5, 0).in_year(2021);
+        assert_eq!(date, NaiveDate::from_ymd(2021, 3, 28));
+        assert!(parse_posix_rule("AEST-10AEDT,M10.1.0,M4.1.0/3").is_some());
+        assert!(parse_posix_rule("<-03>3").is_some());
+        assert!(parse_posix_rule("EST5EDT,M13.1.0,M11.1.0").is_none());
+    }
+
+    #[test]
+    fn invalid_data_and_names_are_refused() {
+        assert!(ZoneInfo::from_tzif("Nowhere", b"TZif2").is_err());
+        let err = ZoneInfo::load("../etc/passwd").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
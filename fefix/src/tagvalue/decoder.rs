@@ -133,6 +133,13 @@ where
         self.raw_decoder.config_mut()
     }
 
+    /// Returns an immutable reference to the [`Dictionary`] used by `self` to
+    /// parse messages.
+    #[inline]
+    pub fn dictionary(&self) -> &Dictionary {
+        &self.dict
+    }
+
     /// Turns `self` into a [`DecoderBuffered`] by allocating an internal buffer.
     pub fn buffered(self) -> DecoderBuffered<C> {
         let raw_decoder = self.raw_decoder.clone().buffered();
@@ -177,6 +184,7 @@ where
         T: AsRef<[u8]>,
     {
         self.builder.clear();
+        self.message_builder_mut().bytes = frame.as_bytes();
         let separator = self.config().separator();
         let payload = frame.payload();
         self.store_field(
@@ -340,6 +348,13 @@ where
         self.decoder.config_mut()
     }
 
+    /// Returns an immutable reference to the [`Dictionary`] used by `self` to
+    /// parse messages.
+    #[inline]
+    pub fn dictionary(&self) -> &Dictionary {
+        self.decoder.dictionary()
+    }
+
     #[inline]
     pub fn supply_buffer(&mut self) -> &mut [u8] {
         self.raw_decoder.supply_buffer()
@@ -569,8 +584,11 @@ impl DecoderState {
     }
 
     fn add_group(&mut self, tag: TagU16, index_of_group_tag: usize, field_value: &[u8]) {
-        let field_value_str = std::str::from_utf8(field_value).unwrap();
-        let num_entries = str::parse(field_value_str).unwrap();
+        // Malformed counts are treated as empty groups rather than panicking.
+        let num_entries = std::str::from_utf8(field_value)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
         if num_entries > 0 {
             self.new_group = Some(DecoderStateNewGroup {
                 tag,
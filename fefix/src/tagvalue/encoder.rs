@@ -65,12 +65,15 @@ where
         &mut self.config
     }
 
+    /// Starts encoding a new message with `begin_string` and `msg_type` into
+    /// `buffer`. Any previous contents of `buffer` are erased.
     pub fn start_message<'a>(
         &'a mut self,
         begin_string: &[u8],
         buffer: &'a mut Vec<u8>,
         msg_type: &[u8],
     ) -> EncoderHandle<'a, Vec<u8>, C> {
+        buffer.clear();
         let mut state = EncoderHandle {
             raw_encoder: self,
             buffer,
@@ -0,0 +1,348 @@
+use crate::script::{Script, Step};
+use chrono::{DateTime, Duration, Utc};
+use fefix::session::backends::Noop;
+use fefix::session::{FixConnection, FixConnectionBuilder, MemoryTransport, MockClock, Role};
+use fefix::tagvalue::Decoder;
+use fefix::Dictionary;
+use futures::executor::LocalPool;
+use futures::future::RemoteHandle;
+use futures::task::LocalSpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt, FutureExt};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The `BeginString <8>` of scripted sessions.
+pub const BEGIN_STRING: &str = "FIX.4.4";
+/// The `SenderCompID <49>` of the session under test.
+pub const SENDER_COMP_ID: &str = "ISLD";
+/// The `SenderCompID <49>` of the scripted counterparty.
+pub const TARGET_COMP_ID: &str = "TW";
+
+/// Runs [`Script`]s against a [`FixConnection`], playing the part of its
+/// counterparty.
+///
+/// The session under test runs [`FixConnection::start`] over an in-memory
+/// pipe (see [`MemoryTransport`]), on a single-threaded executor which is
+/// driven until it stalls after each step: injected messages are written to
+/// the pipe as raw bytes, and all messages that the session writes are
+/// queued until they're expected by the script. The [`MockClock`] of the
+/// session never moves, so timers never fire and scripts are fully
+/// deterministic.
+#[derive(Debug)]
+pub struct Harness {
+    pool: LocalPool,
+    // `None` while the session is running.
+    conn: Option<FixConnection>,
+    session: Option<Session>,
+    now: DateTime<Utc>,
+    outbound: VecDeque<Vec<u8>>,
+    // Bytes read from the pipe which don't make up a whole message yet.
+    pending: Vec<u8>,
+}
+
+/// The counterparty's end of the pipe, and the task of the session under
+/// test, which hands the [`FixConnection`] back once it's over.
+#[derive(Debug)]
+struct Session {
+    transport: MemoryTransport,
+    task: RemoteHandle<FixConnection>,
+}
+
+impl Harness {
+    /// Creates a new [`Harness`] for a FIX 4.4 acceptor with
+    /// [`SENDER_COMP_ID`] and [`TARGET_COMP_ID`].
+    pub fn new() -> Self {
+        let mut builder = FixConnectionBuilder::default();
+        builder.set_begin_string(BEGIN_STRING);
+        builder.set_sender_comp_id(SENDER_COMP_ID);
+        builder.set_target_comp_id(TARGET_COMP_ID);
+        Self::with_builder(builder)
+    }
+
+    /// Creates a new [`Harness`] for the session built by `builder`, which
+    /// must be a FIX 4.4 session with [`SENDER_COMP_ID`] and
+    /// [`TARGET_COMP_ID`]. The session is always an acceptor, and its
+    /// [`Clock`](fefix::session::Clock) is replaced by a [`MockClock`].
+    pub fn with_builder(mut builder: FixConnectionBuilder) -> Self {
+        let now = Utc::now();
+        builder.set_role(Role::Acceptor);
+        builder.set_clock(MockClock::new(now));
+        Self {
+            pool: LocalPool::new(),
+            conn: Some(builder.build()),
+            session: None,
+            now,
+            outbound: VecDeque::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Runs all steps of `script`, stopping at the first failure.
+    pub fn run(&mut self, script: &Script) -> Result<(), Failure> {
+        for (line, step) in script.steps() {
+            self.run_step(step).map_err(|text| Failure { line, text })?;
+        }
+        match self.outbound.pop_front() {
+            Some(message) => Err(Failure {
+                line: 0,
+                text: format!("Unexpected message at the end: {}", display(&message)),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Connect => self.connect()?,
+            Step::Logon => {
+                let logon = vec![
+                    (35, "A".to_string()),
+                    (34, "1".to_string()),
+                    (98, "0".to_string()),
+                    (108, "30".to_string()),
+                ];
+                self.inject(&logon)?;
+            }
+            Step::Inject(fields) => self.inject(fields)?,
+            Step::Expect(matchers) => {
+                let message = self
+                    .outbound
+                    .pop_front()
+                    .ok_or_else(|| "Expected a message, but none was sent".to_string())?;
+                if let Some(matcher) = matchers
+                    .iter()
+                    .find(|matcher| !matcher.matches(field(&message, matcher.tag())))
+                {
+                    return Err(format!(
+                        "Expected {}, but got {}",
+                        matcher,
+                        display(&message)
+                    ));
+                }
+            }
+            Step::Disconnect => {
+                // Closing our end of the pipe ends the session.
+                if let Some(session) = self.session.as_mut() {
+                    self.pool.run_until(session.transport.close()).ok();
+                }
+                self.run_until_stalled();
+            }
+            Step::ExpectDisconnect => {
+                if let Some(message) = self.outbound.pop_front() {
+                    return Err(format!(
+                        "Expected a disconnection, but got {}",
+                        display(&message)
+                    ));
+                }
+                if self.session.is_some() {
+                    return Err("Expected a disconnection, but the transport is open".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => return Err("Already connected".to_string()),
+        };
+        let (transport, pipe) = MemoryTransport::pair();
+        let (input, output) = pipe.split();
+        let task = self
+            .pool
+            .spawner()
+            .spawn_local_with_handle(async move {
+                let decoder = Decoder::new(Dictionary::fix44());
+                conn.start(Noop, input, output, decoder).await;
+                conn
+            })
+            .map_err(|err| err.to_string())?;
+        self.session = Some(Session { transport, task });
+        self.run_until_stalled();
+        Ok(())
+    }
+
+    fn inject(&mut self, fields: &[(u32, String)]) -> Result<(), String> {
+        let message = encode(fields, self.now)?;
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return Err("The transport is closed".to_string()),
+        };
+        self.pool
+            .run_until(session.transport.write_all(&message))
+            .map_err(|err| err.to_string())?;
+        self.run_until_stalled();
+        Ok(())
+    }
+
+    /// Lets the session process everything written so far, then collects
+    /// whatever it wrote and whether it's over.
+    fn run_until_stalled(&mut self) {
+        self.pool.run_until_stalled();
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return,
+        };
+        let mut buffer = [0; 4096];
+        while let Some(Ok(len)) = session.transport.read(&mut buffer).now_or_never() {
+            if len == 0 {
+                break;
+            }
+            self.pending.extend_from_slice(&buffer[..len]);
+        }
+        while let Some(len) = message_len(&self.pending) {
+            self.outbound.push_back(self.pending.drain(..len).collect());
+        }
+        if let Some(conn) = (&mut session.task).now_or_never() {
+            self.conn = Some(conn);
+            self.session = None;
+        }
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The reason why a [`Script`] failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The line of the failed step, or 0 if the script failed after its last
+    /// step.
+    pub line: usize,
+    /// A description of the problem.
+    pub text: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.text)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Returns the length of the first message in `bytes`, if it's complete,
+/// i.e. up to and including its `CheckSum <10>`.
+fn message_len(bytes: &[u8]) -> Option<usize> {
+    let checksum = bytes.windows(4).position(|window| window == b"\x0110=")? + 4;
+    let end = bytes[checksum..].iter().position(|byte| *byte == 0x1)?;
+    Some(checksum + end + 1)
+}
+
+/// Encodes the message of an `I` step. The standard header is completed
+/// with defaults, while `BodyLength <9>` and `CheckSum <10>` are computed
+/// unless given, so that scripts can inject garbled messages.
+fn encode(fields: &[(u32, String)], now: DateTime<Utc>) -> Result<Vec<u8>, String> {
+    let value = |tag: u32| {
+        fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_str())
+    };
+    let mut body = vec![];
+    let mut push = |tag: u32, value: &str| -> Result<(), String> {
+        body.extend_from_slice(format!("{}=", tag).as_bytes());
+        body.extend_from_slice(expand(value, now)?.as_bytes());
+        body.push(0x1);
+        Ok(())
+    };
+    push(35, value(35).unwrap_or_default())?;
+    push(49, value(49).unwrap_or(TARGET_COMP_ID))?;
+    push(56, value(56).unwrap_or(SENDER_COMP_ID))?;
+    push(52, value(52).unwrap_or("<TIME>"))?;
+    for (tag, value) in fields {
+        if ![8, 9, 10, 35, 49, 52, 56].contains(tag) {
+            push(*tag, value)?;
+        }
+    }
+    let mut message = format!("8={}\x01", value(8).unwrap_or(BEGIN_STRING)).into_bytes();
+    match value(9) {
+        Some(body_length) => message.extend_from_slice(format!("9={}\x01", body_length).as_bytes()),
+        None => message.extend_from_slice(format!("9={}\x01", body.len()).as_bytes()),
+    }
+    message.extend_from_slice(&body);
+    let checksum = match value(10) {
+        Some(checksum) => checksum.to_string(),
+        None => {
+            let sum = message
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            format!("{:03}", sum)
+        }
+    };
+    message.extend_from_slice(format!("10={}\x01", checksum).as_bytes());
+    Ok(message)
+}
+
+/// Replaces `<TIME>`, `<TIME+N>` and `<TIME-N>` with UTC timestamps.
+fn expand(value: &str, now: DateTime<Utc>) -> Result<String, String> {
+    let offset = match value
+        .strip_prefix("<TIME")
+        .and_then(|rest| rest.strip_suffix('>'))
+    {
+        Some("") => 0,
+        Some(offset) => offset
+            .strip_prefix('+')
+            .unwrap_or(offset)
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid placeholder `{}`", value))?,
+        None => return Ok(value.to_string()),
+    };
+    let time = now + Duration::seconds(offset);
+    Ok(time.format("%Y%m%d-%H:%M:%S%.3f").to_string())
+}
+
+/// Returns the value of the first field with `tag` in `message`, if any.
+fn field(message: &[u8], tag: u32) -> Option<&[u8]> {
+    let prefix = format!("{}=", tag);
+    message
+        .split(|byte| *byte == 0x1)
+        .find_map(|field| field.strip_prefix(prefix.as_bytes()))
+}
+
+fn display(message: &[u8]) -> String {
+    String::from_utf8_lossy(message).replace('\x01', "|")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SCRIPTS;
+    use fefix::tagvalue::FieldAccess;
+
+    #[test]
+    fn builtin_scripts_pass() {
+        for (name, script) in SCRIPTS {
+            let script = Script::parse(script).unwrap();
+            let result = Harness::new().run(&script);
+            assert_eq!(result, Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let script = Script::parse("iCONNECT\niLOGON\nE35=A|108=10|\n").unwrap();
+        let failure = Harness::new().run(&script).unwrap_err();
+        assert_eq!(failure.line, 3);
+        assert!(failure
+            .text
+            .starts_with("Expected 108=10, but got 8=FIX.4.4|"));
+    }
+
+    #[test]
+    fn injected_messages_are_completed() {
+        let now = Utc::now();
+        let fields = vec![(35, "0".to_string()), (34, "2".to_string())];
+        let message = encode(&fields, now).unwrap();
+        let mut decoder = Decoder::<fefix::tagvalue::Config>::new(Dictionary::fix44());
+        let msg = decoder.decode(&message).unwrap();
+        assert_eq!(
+            msg.fv_raw(fefix::definitions::fix44::SENDER_COMP_ID),
+            Some(&b"TW"[..])
+        );
+    }
+}
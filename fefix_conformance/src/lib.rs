@@ -0,0 +1,57 @@
+//! Scripted FIX session layer conformance tests for FerrumFIX, in the spirit
+//! of QuickFIX's acceptance tests.
+//!
+//! A [`Harness`] plays the counterparty of a
+//! [`FixConnection`](fefix::session::FixConnection) according to a
+//! [`Script`], i.e. a text file with one step per line:
+//!
+//! ```text
+//! # Comments start with `#`.
+//! iCONNECT               opens a new transport
+//! iLOGON                 sends a standard Logon(A) with MsgSeqNum(34) 1
+//! I35=1|34=2|112=ping|   sends a message
+//! E35=0|34=2|112=ping|   expects a message
+//! iDISCONNECT            closes the transport
+//! eDISCONNECT            expects the session under test to close the transport
+//! ```
+//!
+//! Injected messages only need `MsgType(35)` and the fields under test: the
+//! rest of the standard header and trailer is filled in by the harness.
+//! Values may refer to the current time as `<TIME>`, `<TIME+N>` or
+//! `<TIME-N>`, where `N` is a number of seconds. Expected messages are
+//! matched field by field (`tag=value`, `tag=*` for any value, `!tag` for
+//! absent fields), and all messages sent by the session under test must be
+//! expected.
+//!
+//! The scripts in [`SCRIPTS`] cover the FIX Session Layer Test Cases for
+//! sequence gaps, `PossDupFlag(43)`, `SequenceReset(4)`, `SendingTime(52)`
+//! accuracy, garbled messages and the Logout handshake. The
+//! `fefix-conformance` binary runs them, or any other scripts given as
+//! arguments.
+
+mod harness;
+mod script;
+
+pub use harness::{Failure, Harness, BEGIN_STRING, SENDER_COMP_ID, TARGET_COMP_ID};
+pub use script::{FieldMatcher, Script, ScriptError, Step};
+
+macro_rules! script {
+    ($name:literal) => {
+        ($name, include_str!(concat!("../scripts/", $name)))
+    };
+}
+
+/// The built-in scripts, as `(file name, contents)` pairs.
+pub const SCRIPTS: &[(&str, &str)] = &[
+    script!("1a_ValidLogonWithCorrectMsgSeqNum.def"),
+    script!("2b_MsgSeqNumHigherThanExpected.def"),
+    script!("2c_MsgSeqNumLowerThanExpected.def"),
+    script!("2d_GarbledMessage.def"),
+    script!("2e_PossDupAlreadyReceived.def"),
+    script!("2f_PossDupOrigSendingTimeTooHigh.def"),
+    script!("2g_PossDupNoOrigSendingTime.def"),
+    script!("2t_SendingTimeValueOutOfRange.def"),
+    script!("10_SequenceResetGapFill.def"),
+    script!("11_SequenceResetReset.def"),
+    script!("13b_UnsolicitedLogoutMessage.def"),
+];
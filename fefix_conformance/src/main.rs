@@ -0,0 +1,71 @@
+//! Runs FIX session layer conformance scripts.
+//!
+//! Usage: `fefix-conformance [PATH]...`, where each path is either a script
+//! or a directory of `.def` scripts. The built-in scripts are run if no path
+//! is given.
+
+use fefix_conformance::{Harness, Script, SCRIPTS};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::{env, fs};
+
+fn main() {
+    let paths: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();
+    let scripts = if paths.is_empty() {
+        SCRIPTS
+            .iter()
+            .map(|(name, script)| (name.to_string(), Script::parse(script).map_err(Into::into)))
+            .collect()
+    } else {
+        match load_all(&paths) {
+            Ok(scripts) => scripts,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(2);
+            }
+        }
+    };
+    let mut failed = 0;
+    for (name, script) in scripts.iter() {
+        let result = script
+            .as_ref()
+            .map_err(|err| err.to_string())
+            .and_then(|script| Harness::new().run(script).map_err(|err| err.to_string()));
+        match result {
+            Ok(()) => println!("{} ... ok", name),
+            Err(err) => {
+                failed += 1;
+                println!("{} ... FAILED ({})", name, err);
+            }
+        }
+    }
+    println!("\n{} passed; {} failed", scripts.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+type Loaded = (String, Result<Script, Box<dyn Error>>);
+
+fn load_all(paths: &[PathBuf]) -> Result<Vec<Loaded>, Box<dyn Error>> {
+    let mut scripts = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|path| path.extension().map_or(false, |ext| ext == "def"));
+            entries.sort();
+            scripts.extend(entries.iter().map(|path| load(path)));
+        } else {
+            scripts.push(load(path));
+        }
+    }
+    Ok(scripts)
+}
+
+fn load(path: &Path) -> Loaded {
+    let name = path.display().to_string();
+    (name, Script::load(path).map_err(Into::into))
+}
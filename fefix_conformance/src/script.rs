@@ -0,0 +1,258 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A test script, i.e. a sequence of [`Step`]s to be run by a
+/// [`Harness`](crate::Harness). See the [crate-level docs](crate) for the
+/// syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<(usize, Step)>,
+}
+
+impl Script {
+    /// Parses the contents of a script.
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let mut steps = vec![];
+        for (i, line) in script.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = Step::parse(line).map_err(|text| ScriptError::Syntax {
+                line: line_number,
+                text,
+            })?;
+            steps.push((line_number, step));
+        }
+        Ok(Self { steps })
+    }
+
+    /// Reads and parses the script at `path`.
+    pub fn load<P>(path: P) -> Result<Self, ScriptError>
+    where
+        P: AsRef<Path>,
+    {
+        let script = fs::read_to_string(path).map_err(ScriptError::Io)?;
+        Self::parse(&script)
+    }
+
+    /// Returns all steps of `self`, together with their line numbers.
+    pub fn steps(&self) -> impl Iterator<Item = (usize, &Step)> {
+        self.steps.iter().map(|(line, step)| (*line, step))
+    }
+}
+
+/// A single line of a [`Script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `iCONNECT`: opens a new transport to the session under test.
+    Connect,
+    /// `iLOGON`: sends a `Logon <A>` with `MsgSeqNum <34>` 1 and
+    /// `HeartBtInt <108>` 30.
+    Logon,
+    /// `I<fields>`: sends a message. Values may refer to the current time of
+    /// the harness as `<TIME>`, optionally shifted by some seconds (e.g.
+    /// `<TIME-300>`).
+    Inject(Vec<(u32, String)>),
+    /// `E<fields>`: expects the next message sent by the session under test
+    /// to match all of the given [`FieldMatcher`]s.
+    Expect(Vec<FieldMatcher>),
+    /// `iDISCONNECT`: closes the transport.
+    Disconnect,
+    /// `eDISCONNECT`: expects the session under test to close the transport,
+    /// without sending any further messages.
+    ExpectDisconnect,
+}
+
+impl Step {
+    fn parse(line: &str) -> Result<Self, String> {
+        match line {
+            "iCONNECT" => return Ok(Self::Connect),
+            "iLOGON" => return Ok(Self::Logon),
+            "iDISCONNECT" => return Ok(Self::Disconnect),
+            "eDISCONNECT" => return Ok(Self::ExpectDisconnect),
+            _ => {}
+        }
+        if let Some(fields) = line.strip_prefix('I') {
+            let fields = split_fields(fields)
+                .map(|field| {
+                    let (tag, value) = parse_field(field)?;
+                    Ok((tag, value.to_string()))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            if !fields.iter().any(|(tag, _)| *tag == 35) {
+                return Err("Injected messages must have a MsgType(35)".to_string());
+            }
+            Ok(Self::Inject(fields))
+        } else if let Some(fields) = line.strip_prefix('E') {
+            let matchers = split_fields(fields)
+                .map(FieldMatcher::parse)
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Self::Expect(matchers))
+        } else {
+            Err(format!("Unknown step `{}`", line))
+        }
+    }
+}
+
+/// A condition on a field of an expected message. Fields which aren't
+/// mentioned by any matcher may have any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldMatcher {
+    /// `<tag>=<value>`: the field has exactly this value.
+    Equals(u32, String),
+    /// `<tag>=*`: the field has any value.
+    Present(u32),
+    /// `!<tag>`: the field is absent.
+    Absent(u32),
+}
+
+impl FieldMatcher {
+    fn parse(field: &str) -> Result<Self, String> {
+        if let Some(tag) = field.strip_prefix('!') {
+            return Ok(Self::Absent(parse_tag(tag)?));
+        }
+        Ok(match parse_field(field)? {
+            (tag, "*") => Self::Present(tag),
+            (tag, value) => Self::Equals(tag, value.to_string()),
+        })
+    }
+
+    /// Returns `true` if `value` (i.e. the value of the field, if present)
+    /// satisfies `self`.
+    pub fn matches(&self, value: Option<&[u8]>) -> bool {
+        match (self, value) {
+            (Self::Equals(_, expected), Some(value)) => expected.as_bytes() == value,
+            (Self::Present(_), Some(_)) => true,
+            (Self::Absent(_), None) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the tag of the field.
+    pub fn tag(&self) -> u32 {
+        match self {
+            Self::Equals(tag, _) | Self::Present(tag) | Self::Absent(tag) => *tag,
+        }
+    }
+}
+
+impl fmt::Display for FieldMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equals(tag, value) => write!(f, "{}={}", tag, value),
+            Self::Present(tag) => write!(f, "{}=*", tag),
+            Self::Absent(tag) => write!(f, "!{}", tag),
+        }
+    }
+}
+
+/// Fields are separated by `|`, or by SOH as in QuickFIX's scripts.
+fn split_fields(fields: &str) -> impl Iterator<Item = &str> {
+    fields
+        .split(['|', '\x01'])
+        .filter(|field| !field.is_empty())
+}
+
+fn parse_field(field: &str) -> Result<(u32, &str), String> {
+    let mut parts = field.splitn(2, '=');
+    let tag = parse_tag(parts.next().unwrap_or_default())?;
+    let value = parts
+        .next()
+        .ok_or_else(|| format!("Missing value in `{}`", field))?;
+    Ok((tag, value))
+}
+
+fn parse_tag(tag: &str) -> Result<u32, String> {
+    match tag.parse() {
+        Ok(tag) if tag > 0 => Ok(tag),
+        _ => Err(format!("Invalid tag `{}`", tag)),
+    }
+}
+
+/// The error returned by [`Script::parse`] and [`Script::load`].
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script couldn't be read.
+    Io(io::Error),
+    /// A line of the script is invalid.
+    Syntax {
+        /// The line number, starting from 1.
+        line: usize,
+        /// A description of the problem.
+        text: String,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Syntax { line, text } => write!(f, "line {}: {}", line, text),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Syntax { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steps_are_parsed_with_line_numbers() {
+        let script = Script::parse(
+            "# Logon\niCONNECT\niLOGON\n\nE35=A|34=1|58=*|!141|\nI35=0|34=2|52=<TIME-5>|\neDISCONNECT\n",
+        )
+        .unwrap();
+        let steps: Vec<_> = script.steps().collect();
+        assert_eq!(steps[0], (2, &Step::Connect));
+        assert_eq!(steps[1], (3, &Step::Logon));
+        assert_eq!(
+            steps[2],
+            (
+                5,
+                &Step::Expect(vec![
+                    FieldMatcher::Equals(35, "A".to_string()),
+                    FieldMatcher::Equals(34, "1".to_string()),
+                    FieldMatcher::Present(58),
+                    FieldMatcher::Absent(141),
+                ])
+            )
+        );
+        assert_eq!(
+            steps[3],
+            (
+                6,
+                &Step::Inject(vec![
+                    (35, "0".to_string()),
+                    (34, "2".to_string()),
+                    (52, "<TIME-5>".to_string()),
+                ])
+            )
+        );
+        assert_eq!(steps[4], (7, &Step::ExpectDisconnect));
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let err = Script::parse("iCONNECT\nI34=1|\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: Injected messages must have a MsgType(35)"
+        );
+        let err = Script::parse("E35=A|foo=1|").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: Invalid tag `foo`");
+        assert!(Script::parse("iFOO").is_err());
+    }
+}
@@ -3,12 +3,6 @@
 git submodule init
 git submodule update
 
-mkdir lib/quickfix/config
-cd lib/quickfix/config
-cmake ..
-make
-cd ../../..
-
 # Increase number of iteration for QuickCheck.
 QUICKCHECK_TESTS="2500"
 
@@ -23,5 +17,7 @@ cargo test --no-default-features --features "utils-bytes, utils-rust-decimal"
 cargo test --no-default-features --features "fixs, utils-openssl, fix40"
 cargo test --no-default-features --features "derive, fix43"
 cargo test --no-default-features --features "full"
+# Session layer conformance scripts.
+cargo run -p fefix_conformance
 
 RUSTDOCFLAGS="--cfg doc_cfg" cargo +nightly doc --all-features